@@ -0,0 +1,20 @@
+// Builds the fake language server the lsp tests talk to. It is only a test
+// fixture, so it isn't a binary target that `cargo install` would ship.
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+const FAKE_LANGUAGE_SERVER: &str = "tests/support/fake_language_server.rs";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", FAKE_LANGUAGE_SERVER);
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    let binary = out_dir.join(format!("fake_language_server{}", env::consts::EXE_SUFFIX));
+    let status = Command::new(env::var_os("RUSTC").unwrap_or_else(|| "rustc".into()))
+        .args(["--edition", "2021", "--crate-name", "fake_language_server", FAKE_LANGUAGE_SERVER, "-o"])
+        .arg(&binary)
+        .status()
+        .expect("failed to run rustc");
+    assert!(status.success(), "failed to build {}", FAKE_LANGUAGE_SERVER);
+    println!("cargo:rustc-env=FAKE_LANGUAGE_SERVER={}", binary.display());
+}
@@ -0,0 +1,162 @@
+use crate::prelude::*;
+
+pub const USAGE: &str = "\
+usage: editor [options] [file ...]
+
+  file[:line[:col]]  open file, optionally at a position
+  -                  read the buffer from stdin
+  +N                 start at line N (+ alone for the last line)
+  +/pattern          start at the first line containing pattern
+  -c command         run an ex command after loading (repeatable)
+  -o / -O            open the files in horizontal / vertical splits
+  -d                 open the files side by side in diff mode
+  -R                 open the files readonly
+  --clean            don't load or save history and session state
+  -h, --help         show this help
+  --version          show the version";
+
+#[derive(Debug, PartialEq)]
+pub enum StartPosition {
+    Line(usize),
+    LastLine,
+    Column(usize, usize),
+    Pattern(String),
+}
+
+impl StartPosition {
+    pub fn resolve(&self, buffer: &Buffer) -> EditorResult<CursorPosition> {
+        let last_line = buffer.number_of_lines() - 1;
+        let line = match self {
+            StartPosition::Line(line) => cmp::min(line.saturating_sub(1), last_line),
+            StartPosition::LastLine => last_line,
+            StartPosition::Column(line, column) => {
+                let line = cmp::min(line.saturating_sub(1), last_line);
+                return Ok(CursorPosition { line, column: column.saturating_sub(1) });
+            },
+            StartPosition::Pattern(pattern) => {
+                return buffer.find_text(pattern).ok_or_else(|| EditorError::PatternNotFound(pattern.clone()));
+            },
+        };
+        Ok(CursorPosition { line, column: buffer.first_non_blank(line) })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FileArg {
+    Path(PathBuf, Option<StartPosition>),
+    Stdin,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Split {
+    Horizontal,
+    Vertical,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub files: Vec<FileArg>,
+    pub position: Option<StartPosition>,
+    pub commands: Vec<String>,
+    pub split: Option<Split>,
+    pub diff: bool,
+    pub read_only: bool,
+    pub clean: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> EditorResult<Self> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+        let mut files_only = false;
+
+        while let Some(arg) = args.next() {
+            if files_only {
+                parsed.files.push(Self::file_arg(&arg));
+                continue;
+            }
+            match arg.as_str() {
+                "--" => files_only = true,
+                "-" => parsed.files.push(FileArg::Stdin),
+                "-c" => match args.next() {
+                    Some(command) => parsed.commands.push(command),
+                    None => return Err(EditorError::InvalidArgument(String::from("-c needs a command"))),
+                },
+                "-o" => parsed.split = Some(Split::Horizontal),
+                "-O" => parsed.split = Some(Split::Vertical),
+                "-d" => parsed.diff = true,
+                "-R" => parsed.read_only = true,
+                "--clean" => parsed.clean = true,
+                "-h" | "--help" => parsed.help = true,
+                "--version" => parsed.version = true,
+                "+" => parsed.position = Some(StartPosition::LastLine),
+                _ if arg.starts_with("+/") => parsed.position = Some(StartPosition::Pattern(arg[2..].to_string())),
+                _ if arg.starts_with('+') => match arg[1..].parse() {
+                    Ok(line) => parsed.position = Some(StartPosition::Line(line)),
+                    Err(_) => parsed.commands.push(arg[1..].to_string()),
+                },
+                _ if arg.starts_with('-') => return Err(EditorError::InvalidArgument(arg)),
+                _ => parsed.files.push(Self::file_arg(&arg)),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    // Accepts the file:line:col locations printed by compilers and grep, as
+    // long as no file with the literal name exists.
+    fn file_arg(arg: &str) -> FileArg {
+        if Path::new(arg).exists() {
+            return FileArg::Path(PathBuf::from(arg), None);
+        }
+
+        let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+        let parts: Vec<&str> = trimmed.rsplitn(3, ':').collect();
+        let number = |part: &str| part.parse::<usize>().ok().filter(|number| *number > 0);
+        match parts[..] {
+            [column, line, file] if !file.is_empty() => {
+                if let (Some(line), Some(column)) = (number(line), number(column)) {
+                    return FileArg::Path(PathBuf::from(file), Some(StartPosition::Column(line, column)));
+                }
+            },
+            _ => {},
+        }
+        match trimmed.rsplit_once(':') {
+            Some((file, line)) if !file.is_empty() && number(line).is_some() => {
+                FileArg::Path(PathBuf::from(file), number(line).map(StartPosition::Line))
+            },
+            _ => FileArg::Path(PathBuf::from(arg), None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Args {
+        Args::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn parses_files_positions_and_flags() {
+        let args = parse(&["-O", "+12", "-c", "set nonumber", "+set wrap", "src/lib.rs:4:7:", "notes.txt:9", "-", "-R"]);
+        assert_eq!(args.split, Some(Split::Vertical));
+        assert_eq!(args.position, Some(StartPosition::Line(12)));
+        assert_eq!(args.commands, vec!["set nonumber".to_string(), "set wrap".to_string()]);
+        assert_eq!(args.files, vec![
+            FileArg::Path(PathBuf::from("src/lib.rs"), Some(StartPosition::Column(4, 7))),
+            FileArg::Path(PathBuf::from("notes.txt"), Some(StartPosition::Line(9))),
+            FileArg::Stdin,
+        ]);
+        assert!(args.read_only);
+
+        let args = parse(&["+/fn main", "--", "-weird:name"]);
+        assert_eq!(args.position, Some(StartPosition::Pattern("fn main".to_string())));
+        assert_eq!(args.files, vec![FileArg::Path(PathBuf::from("-weird:name"), None)]);
+
+        assert!(Args::parse(["-x".to_string()]).is_err());
+    }
+}
@@ -12,7 +12,9 @@ impl BufferLine {
             return "";
         }
 
-        let max_len = cmp::min(end, self.line.len());
+        // Both ends are columns, which may fall inside a character.
+        let start = self.line.floor_char_boundary(start);
+        let max_len = self.line.floor_char_boundary(cmp::max(start, end));
 
         &self.line[start..max_len]
     }
@@ -21,40 +23,247 @@ impl BufferLine {
 pub struct Buffer {
     lines: Vec<BufferLine>,
     pub file_path: Option<PathBuf>,
+    pub name: Option<String>,
+    pub new_file: bool,
+    pub signs: Signs,
+    pub marks: Marks,
+    pub changes: ChangeList,
+    pub modified: bool,
+    pub read_only: bool,
+    pub change_tick: usize,
+    pub swap: Option<SwapFile>,
+    pub swap_checked: bool,
+    pub disk_stamp: Option<FileStamp>,
+    pub file_options: FileOptions,
+    pub modifiable: bool,
+    pub sync: Option<Vec<TextChange>>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub git: Option<GitFile>,
+    pub git_checked: bool,
+    pub conflicts: Vec<Conflict>,
+    conflicts_tick: Option<usize>,
+    large: Option<LargeFile>,
+    hex: Option<HexView>,
 }
 
 impl Buffer {
-    pub fn new(file: Option<PathBuf>) -> Self {
-        match file {
+    fn with_lines(mut lines: Vec<BufferLine>, file_path: Option<PathBuf>) -> Self {
+        if lines.is_empty() {
+            lines.push(BufferLine { line_number: 1, line: String::new() });
+        }
+
+        Self {
+            lines,
+            file_path,
+            name: None,
+            new_file: false,
+            signs: Signs::default(),
+            marks: Marks::default(),
+            changes: ChangeList::default(),
+            modified: false,
+            read_only: false,
+            change_tick: 0,
+            swap: None,
+            swap_checked: false,
+            disk_stamp: None,
+            file_options: FileOptions::default(),
+            modifiable: true,
+            sync: None,
+            diagnostics: vec![],
+            git: None,
+            git_checked: false,
+            conflicts: vec![],
+            conflicts_tick: None,
+            large: None,
+            hex: None,
+        }
+    }
+
+    pub fn welcome() -> Self {
+        Self::with_lines(Self::build_welcome_buffer(), None)
+    }
+
+    pub fn open(file: PathBuf) -> EditorResult<Self> {
+        Self::open_with(file, None, LARGE_FILE_THRESHOLD_MB)
+    }
+
+    pub fn open_with(file: PathBuf, encoding: Option<FileEncoding>, largefile_mb: usize) -> EditorResult<Self> {
+        let stamp = FileStamp::of(&file);
+        if encoding.is_none() && LargeFile::should_map(&file, largefile_mb) {
+            if let Ok(large) = LargeFile::open(&file) {
+                let mut buffer = Self::with_lines(vec![], Some(file));
+                buffer.disk_stamp = stamp;
+                buffer.file_options = large.file_options;
+                // Only the decoded lines around the view are kept, so an edit could
+                // neither be kept nor written back without reading the whole file.
+                buffer.modifiable = false;
+                buffer.large = Some(large);
+                return Ok(buffer);
+            }
+        }
+
+        match fs::read(&file) {
+            Ok(bytes) => {
+                // Opening for writing neither truncates nor touches the file, it only checks access.
+                let writable = fs::OpenOptions::new().write(true).open(&file).is_ok();
+                let mut buffer = Self::with_lines(vec![], Some(file));
+                buffer.set_contents(bytes, encoding, false);
+                buffer.read_only = !writable;
+                buffer.disk_stamp = stamp;
+                Ok(buffer)
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let mut buffer = Self::with_lines(vec![], Some(file));
+                buffer.new_file = true;
+                Ok(buffer)
+            },
+            Err(err) => Err(EditorError::Io(file, err)),
+        }
+    }
+
+    fn lines_from(lines: &[String]) -> Vec<BufferLine> {
+        lines.iter()
+            .enumerate()
+            .map(|(idx, line)| BufferLine { line_number: idx + 1, line: line.clone() })
+            .collect()
+    }
+
+    pub fn from_bytes(name: &str, bytes: Vec<u8>) -> Self {
+        let mut buffer = Self::with_lines(vec![], None);
+        buffer.set_contents(bytes, None, false);
+        buffer.name = Some(name.to_string());
+        buffer
+    }
+
+    pub fn scratch(name: &str, lines: &[String]) -> Self {
+        let mut buffer = Self::with_lines(Self::lines_from(lines), None);
+        buffer.name = Some(name.to_string());
+        buffer
+    }
+
+    // Binary contents open in hex view unless an encoding was asked for or
+    // recognized.
+    fn set_contents(&mut self, bytes: Vec<u8>, encoding: Option<FileEncoding>, hex: bool) {
+        let binary = encoding.is_none() && FileEncoding::sniff(&bytes).is_none() && HexView::is_binary(&bytes);
+        if hex || binary {
+            let view = HexView { bytes };
+            self.lines = Self::lines_from(&view.lines());
+            self.hex = Some(view);
+        } else {
+            let (file_options, lines) = FileOptions::decode(&bytes, encoding);
+            self.lines = Self::lines_from(&lines);
+            self.file_options = file_options;
+            self.hex = None;
+        }
+        if self.lines.is_empty() {
+            self.lines.push(BufferLine { line_number: 1, line: String::new() });
+        }
+        if self.sync.is_some() {
+            self.sync = Some(vec![TextChange::Full]);
+        }
+    }
+
+    pub fn is_hex(&self) -> bool {
+        self.hex.is_some()
+    }
+
+    pub fn toggle_hex(&mut self) -> EditorResult<()> {
+        if !self.modifiable {
+            return Err(EditorError::NotModifiable);
+        }
+        match self.hex.take() {
+            Some(view) => self.set_contents(view.bytes, Some(self.file_options.fileencoding), false),
             None => {
-                Self {
-                    lines: Self::build_welcome_buffer(),
-                    file_path: None,
-                }
+                let bytes = self.file_options.encode(self.text_lines())?;
+                self.set_contents(bytes, None, true);
             },
-            Some(file) => {
-                Self {
-                    lines: Self::read_file(&file),
-                    file_path: Some(file.into()),
-                }
-            }
         }
+        self.change_tick += 1;
+        Ok(())
     }
 
-    pub fn read_file(file: &Path) -> Vec<BufferLine> {
-        let file_contents = fs::read_to_string(file).expect("Unable to read file");
-        let mut lines: Vec<BufferLine> = Vec::new();
+    pub fn format_info(&self) -> String {
+        match &self.hex {
+            Some(_) => String::from("hex"),
+            None => self.file_options.describe(),
+        }
+    }
 
-        for (line_number, line) in file_contents.lines().enumerate() {
-            lines.push(
-                BufferLine {
-                    line: line.to_string(),
-                    line_number: line_number + 1,
-                }
-            );
+    pub fn write(&mut self, file: Option<PathBuf>, force: bool) -> EditorResult<String> {
+        if self.read_only && !force {
+            return Err(EditorError::ReadOnly);
         }
+        if let (Some(line), None, false) = (self.file_options.conversion_error, &self.hex, force) {
+            return Err(EditorError::ConversionError(line));
+        }
+        let file = match file.or_else(|| self.file_path.clone()) {
+            Some(file) => file,
+            None => return Err(EditorError::NoFileName),
+        };
 
-        lines
+        let bytes = match (&self.large, &self.hex) {
+            // Large buffers can't change, and writing the mapping over its own file would truncate it.
+            (Some(_), _) if self.file_path.as_ref().is_some_and(|path| same_file(path, &file)) => {
+                return Ok(format!("\"{}\" unchanged", file.display()));
+            },
+            (Some(large), _) => std::borrow::Cow::Borrowed(large.bytes().map_err(|err| EditorError::Io(file.clone(), err))?),
+            (None, Some(view)) => std::borrow::Cow::Borrowed(view.bytes.as_slice()),
+            (None, None) => std::borrow::Cow::Owned(self.file_options.encode(self.text_lines())?),
+        };
+        fs::write(&file, &bytes).map_err(|err| EditorError::Io(file.clone(), err))?;
+        let bytes_written = bytes.len();
+
+        let info = format!(
+            "\"{}\" {}L, {}B written [{}]",
+            file.display(),
+            self.number_of_lines(),
+            bytes_written,
+            self.format_info(),
+        );
+        if self.file_path.is_none() {
+            self.file_path = Some(file.clone());
+            self.git_checked = false;
+        }
+        if self.file_path.as_ref().is_some_and(|path| same_file(path, &file)) {
+            self.disk_stamp = FileStamp::of(&file);
+            self.new_file = false;
+            self.modified = false;
+            self.file_options.conversion_error = None;
+            self.change_tick += 1;
+        }
+        Ok(info)
+    }
+
+    pub fn check_changeable(&self) -> EditorResult<()> {
+        if !self.modifiable {
+            return Err(EditorError::NotModifiable);
+        }
+        if self.read_only {
+            return Err(EditorError::ChangeReadOnly);
+        }
+        Ok(())
+    }
+
+    pub fn set_option(&mut self, arg: &str) -> EditorResult<()> {
+        let (name, value) = match arg.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        match name {
+            "readonly" | "ro" => self.read_only = value,
+            "modifiable" | "ma" if value && self.is_large() => return Err(EditorError::InvalidArgument(arg.to_string())),
+            "modifiable" | "ma" => self.modifiable = value,
+            _ => return self.set_file_option(arg),
+        }
+        Ok(())
+    }
+
+    fn set_file_option(&mut self, arg: &str) -> EditorResult<()> {
+        self.check_changeable()?;
+        self.file_options.set(arg)?;
+        self.modified = true;
+        self.change_tick += 1;
+        Ok(())
     }
 
     pub fn build_welcome_buffer() -> Vec<BufferLine> {
@@ -86,19 +295,366 @@ impl Buffer {
         lines
     }
 
+    pub fn display_name(&self) -> String {
+        match (&self.file_path, &self.name) {
+            (Some(file), _) => file.display().to_string(),
+            (None, Some(name)) => name.clone(),
+            (None, None) => String::from("[No name]"),
+        }
+    }
+
+    pub fn file_info(&self) -> String {
+        if self.new_file {
+            format!("\"{}\" [New]", self.display_name())
+        } else if let Some(large) = &self.large {
+            format!("\"{}\" [large file] {} bytes", self.display_name(), large.len())
+        } else {
+            format!(
+                "\"{}\" {} lines [{}]{}",
+                self.display_name(),
+                self.number_of_lines(),
+                self.format_info(),
+                self.file_options.conversion_error.map_or(String::new(), |line| format!(" [CONVERSION ERROR in line {}]", line + 1)),
+            )
+        }
+    }
+
     pub fn number_of_lines(&self) -> usize {
-        self.lines.len()
+        match &self.large {
+            Some(large) => large.number_of_lines(),
+            None => self.lines.len(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn is_blank(&self) -> bool {
+        self.number_of_lines() == 0
     }
 
     pub fn get_line(&self, at: usize) -> Option<&BufferLine> {
+        if let Some(large) = &self.large {
+            return self.lines.get(at.checked_sub(large.window_start())?);
+        }
         if at >= self.number_of_lines() {
             return None
         }
         Some(&self.lines[at])
     }
 
-    pub fn is_blank(&self) -> bool {
-        self.number_of_lines() == 0
+    pub fn is_large(&self) -> bool {
+        self.large.is_some()
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        self.large.as_ref().is_some_and(LargeFile::is_indexing)
+    }
+
+    // Large buffers only keep the lines around the view decoded; everything
+    // else is read from the mapping on demand.
+    pub fn load_lines(&mut self, start: usize, end: usize) {
+        let large = match &mut self.large {
+            Some(large) => large,
+            None => return,
+        };
+        let loaded = large.window_start()..large.window_start() + self.lines.len();
+        let end = cmp::min(end, large.number_of_lines());
+        if loaded.start <= start && end <= loaded.end {
+            return;
+        }
+        if let Some(lines) = large.read_lines(start.saturating_sub(LARGE_FILE_MARGIN), end + LARGE_FILE_MARGIN) {
+            self.lines = lines;
+        }
+    }
+
+    pub fn text_lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| line.line.as_str())
     }
 
+    // Looks for conflict markers again if the text changed since the last call.
+    pub fn update_conflicts(&mut self) {
+        if self.conflicts_tick == Some(self.change_tick) || self.is_large() {
+            return;
+        }
+        self.conflicts_tick = Some(self.change_tick);
+        self.conflicts = find_conflicts(self.text_lines());
+    }
+
+    pub fn conflict_section(&self, line: usize) -> Option<ConflictSection> {
+        self.conflicts.iter().find_map(|conflict| conflict.section(line))
+    }
+
+    pub fn find_text(&self, pattern: &str) -> Option<CursorPosition> {
+        self.lines.iter()
+            .enumerate()
+            .find_map(|(line, buffer_line)| Some(CursorPosition { line, column: buffer_line.line.find(pattern)? }))
+    }
+
+    pub fn first_non_blank(&self, at: usize) -> usize {
+        self.get_line(at)
+            .and_then(|line| line.line.find(|c: char| !c.is_whitespace()))
+            .unwrap_or(0)
+    }
+
+    fn renumber(&mut self) {
+        for (idx, line) in self.lines.iter_mut().enumerate() {
+            line.line_number = idx + 1;
+        }
+    }
+
+    fn record_change(&mut self, start: CursorPosition, end: CursorPosition) {
+        self.marks.set('[', start);
+        self.marks.set(']', end);
+        self.marks.set('.', start);
+        self.changes.record(start);
+        self.modified = true;
+        self.change_tick += 1;
+    }
+
+    pub fn update_swap(&mut self) -> io::Result<()> {
+        match (&mut self.swap, &self.file_path) {
+            (Some(swap), Some(file)) if !swap.is_current(self.change_tick) => {
+                let lines = self.lines.iter().map(|line| line.line.as_str());
+                swap.write(file, self.modified, lines, self.change_tick)
+            },
+            _ => Ok(()),
+        }
+    }
+
+    pub fn changed_on_disk(&self) -> bool {
+        self.file_path.as_ref().is_some_and(|file| FileStamp::of(file) != self.disk_stamp)
+    }
+
+    pub fn reload(&mut self, encoding: Option<FileEncoding>) -> EditorResult<()> {
+        let file = match &self.file_path {
+            Some(file) => file.clone(),
+            None => return Err(EditorError::NoFileName),
+        };
+
+        let stamp = FileStamp::of(&file);
+        if self.large.is_some() {
+            let large = LargeFile::open(&file).map_err(|err| EditorError::Io(file, err))?;
+            self.file_options = large.file_options;
+            self.large = Some(large);
+            self.lines.clear();
+            self.disk_stamp = stamp;
+            self.change_tick += 1;
+            return Ok(());
+        }
+        let bytes = fs::read(&file).map_err(|err| EditorError::Io(file, err))?;
+        self.set_contents(bytes, encoding, encoding.is_none() && self.hex.is_some());
+        self.disk_stamp = stamp;
+        self.new_file = false;
+        self.modified = false;
+        self.change_tick += 1;
+        Ok(())
+    }
+
+    pub fn recover(&mut self, lines: &[String]) {
+        self.lines = Self::lines_from(lines);
+        if self.lines.is_empty() {
+            self.lines.push(BufferLine { line_number: 1, line: String::new() });
+        }
+        if self.sync.is_some() {
+            self.sync = Some(vec![TextChange::Full]);
+        }
+        self.modified = true;
+        self.change_tick += 1;
+    }
+
+    fn utf16_position(&self, position: CursorPosition) -> CursorPosition {
+        let text = self.get_line(position.line).map_or("", |line| line.line.as_str());
+        CursorPosition { line: position.line, column: to_utf16(text, position.column) }
+    }
+
+    fn byte_position(&self, position: CursorPosition) -> CursorPosition {
+        match self.get_line(position.line) {
+            Some(line) => CursorPosition { line: position.line, column: from_utf16(&line.line, position.column) },
+            None => {
+                let line = self.number_of_lines() - 1;
+                CursorPosition { line, column: self.get_line(line).map_or(0, |line| line.line.len()) }
+            },
+        }
+    }
+
+    // Must run before the edit, while the positions still refer to the old text.
+    fn record_sync(&mut self, start: CursorPosition, end: CursorPosition, text: &str) {
+        if self.sync.is_none() || self.hex.is_some() {
+            return;
+        }
+        let edit = TextEdit { start: self.utf16_position(start), end: self.utf16_position(end), text: text.to_string() };
+        if let Some(changes) = &mut self.sync {
+            changes.push(TextChange::Edit(edit));
+        }
+    }
+
+    pub fn set_diagnostics(&mut self, mut diagnostics: Vec<Diagnostic>) {
+        self.signs.clear_group(LSP_SIGN_GROUP);
+        // The most severe diagnostic goes last so its sign wins on shared lines.
+        diagnostics.sort_by_key(|diagnostic| cmp::Reverse(diagnostic.severity as u8));
+        for diagnostic in diagnostics.iter_mut() {
+            diagnostic.start = self.byte_position(diagnostic.start);
+            diagnostic.end = self.byte_position(diagnostic.end);
+            self.signs.place(diagnostic.start.line, diagnostic.severity.sign());
+        }
+        self.diagnostics = diagnostics;
+    }
+
+    pub fn diagnostic_at(&self, line: usize) -> Option<&Diagnostic> {
+        self.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.covers_line(line))
+            .min_by_key(|diagnostic| diagnostic.severity as u8)
+    }
+
+    // Applies edits from a language server, which count columns in UTF-16 units
+    // and all refer to the text before any of them is applied.
+    pub fn apply_edits(&mut self, mut edits: Vec<TextEdit>) -> EditorResult<()> {
+        self.check_changeable()?;
+        if self.hex.is_some() {
+            return Err(EditorError::HexMode);
+        }
+        edits.sort_by_key(|edit| (edit.start.line, edit.start.column));
+        for edit in edits.into_iter().rev() {
+            let (start, end) = (self.byte_position(edit.start), self.byte_position(edit.end));
+            self.replace_text(start, end, &edit.text);
+        }
+        Ok(())
+    }
+
+    // Returns the position just after the new text.
+    pub fn replace_text(&mut self, start: CursorPosition, end: CursorPosition, text: &str) -> CursorPosition {
+        let end = if (end.line, end.column) < (start.line, start.column) { start } else { end };
+        self.record_sync(start, end, text);
+
+        let prefix = self.lines[start.line].line[..start.column].to_string();
+        let suffix = self.lines[end.line].line[end.column..].to_string();
+        let replaced = format!("{}{}{}", prefix, text, suffix);
+        let new_lines: Vec<BufferLine> = replaced.split('\n')
+            .map(|line| BufferLine { line_number: 0, line: line.to_string() })
+            .collect();
+        let added = new_lines.len();
+        self.lines.splice(start.line..=end.line, new_lines);
+        self.adjust_lines(start.line + 1, end.line - start.line, added - 1);
+        self.renumber();
+
+        let text_end = match text.rfind('\n') {
+            Some(idx) => CursorPosition { line: start.line + added - 1, column: text.len() - idx - 1 },
+            None => CursorPosition { line: start.line, column: start.column + text.len() },
+        };
+        self.record_change(start, text_end);
+        text_end
+    }
+
+    fn adjust_lines(&mut self, at: usize, removed: usize, added: usize) {
+        self.marks.adjust(at, removed, added);
+        self.changes.adjust(at, removed, added);
+        self.signs.adjust(at, removed, added);
+    }
+
+    // Replaces `count` whole lines from `start` with `lines`, either of which may be empty.
+    pub fn replace_lines(&mut self, start: usize, count: usize, lines: &[String]) {
+        let text = lines.join("\n");
+        let line_end = |line: usize| CursorPosition { line, column: self.lines[line].line.len() };
+        match (count, lines.is_empty()) {
+            (0, true) => {},
+            (_, true) => self.delete_lines(start, start + count),
+            (0, false) if start < self.number_of_lines() => {
+                let at = CursorPosition { line: start, column: 0 };
+                self.replace_text(at, at, &format!("{}\n", text));
+            },
+            (0, false) => {
+                let at = line_end(self.number_of_lines() - 1);
+                self.replace_text(at, at, &format!("\n{}", text));
+            },
+            _ => {
+                let end = line_end(start + count - 1);
+                self.replace_text(CursorPosition { line: start, column: 0 }, end, &text);
+            },
+        }
+    }
+
+    pub fn delete_lines(&mut self, start: usize, end: usize) {
+        let end = cmp::min(end, self.number_of_lines());
+        if start >= end {
+            return;
+        }
+
+        // Deleting through the last line takes the newline before it instead of the one after.
+        let line_end = |line: usize| CursorPosition { line, column: self.lines[line].line.len() };
+        let (from, to) = if end < self.lines.len() {
+            (CursorPosition { line: start, column: 0 }, CursorPosition { line: end, column: 0 })
+        } else if start > 0 {
+            (line_end(start - 1), line_end(end - 1))
+        } else {
+            (CursorPosition::default(), line_end(end - 1))
+        };
+        self.record_sync(from, to, "");
+
+        self.lines.drain(start..end);
+        self.adjust_lines(start, end - start, 0);
+        if self.lines.is_empty() {
+            self.lines.push(BufferLine { line_number: 1, line: String::new() });
+        }
+        self.renumber();
+
+        let position = CursorPosition { line: cmp::min(start, self.number_of_lines() - 1), column: 0 };
+        self.record_change(position, position);
+    }
+
+    pub fn replace_chars(&mut self, at: usize, column: usize, c: char, count: usize) -> EditorResult<CursorPosition> {
+        let position = match &mut self.hex {
+            Some(view) => {
+                let no_byte = || EditorError::InvalidArgument(String::from("no byte under the cursor"));
+                let mut targets = vec![view.position(at, column).ok_or_else(no_byte)?];
+                while targets.len() < count {
+                    let (offset, pane) = targets[targets.len() - 1];
+                    match view.next(offset, pane) {
+                        Some(next) => targets.push(next),
+                        // Like the text branch, change nothing unless all of them fit.
+                        None => return Ok(CursorPosition { line: at, column }),
+                    }
+                }
+                for (offset, pane) in &targets {
+                    view.replace(*offset, pane, c)?;
+                }
+                let (first, (offset, pane)) = (targets[0].0, targets[targets.len() - 1]);
+                let position = HexView::column(offset, &pane);
+                for line in first / HEX_BYTES_PER_LINE..=position.line {
+                    self.lines[line].line = view.line(line);
+                }
+                position
+            },
+            None => {
+                let buffer_line = match self.lines.get(at) {
+                    Some(buffer_line) => buffer_line,
+                    None => return Ok(CursorPosition { line: at, column }),
+                };
+                let mut ends = buffer_line.line.get(column..).unwrap_or("").char_indices().map(|(idx, c)| column + idx + c.len_utf8());
+                let end = match ends.nth(count.saturating_sub(1)) {
+                    Some(end) => end,
+                    None => return Ok(CursorPosition { line: at, column }),
+                };
+                let replacement = c.to_string().repeat(count);
+                self.record_sync(CursorPosition { line: at, column }, CursorPosition { line: at, column: end }, &replacement);
+                self.lines[at].line.replace_range(column..end, &replacement);
+                CursorPosition { line: at, column: column + (count - 1) * c.len_utf8() }
+            },
+        };
+
+        self.record_change(CursorPosition { line: at, column }, position);
+        Ok(position)
+    }
+
+    pub fn delete_text(&mut self, at: usize, start: usize, end: usize) {
+        if let Some(buffer_line) = self.lines.get(at) {
+            let end = cmp::min(end, buffer_line.line.len());
+            if start >= end {
+                return;
+            }
+            self.record_sync(CursorPosition { line: at, column: start }, CursorPosition { line: at, column: end }, "");
+            self.lines[at].line.replace_range(start..end, "");
+
+            let position = CursorPosition { line: at, column: start };
+            self.record_change(position, position);
+        }
+    }
 }
@@ -2,9 +2,17 @@ use crate::prelude::*;
 
 pub struct CleanUp;
 
+pub fn restore_terminal() {
+    // Best effort: this also runs from the panic hook, where there is nobody left to report to.
+    if !terminal::is_raw_mode_enabled().unwrap_or(true) {
+        return;
+    }
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stdout(), event::DisableMouseCapture, LeaveAlternateScreen, cursor::Show);
+}
+
 impl Drop for CleanUp {
     fn drop(&mut self) {
-        terminal::disable_raw_mode().expect("Could not turn off raw mode");
-        Frame::clear_screen().expect("Error");
+        restore_terminal();
     }
 }
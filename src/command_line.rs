@@ -0,0 +1,382 @@
+use crate::prelude::*;
+
+pub const HISTORY_FILE: &str = "history";
+pub const HISTORY_SIZE: usize = 100;
+pub const COMPLETION_MENU_HEIGHT: usize = 10;
+pub const COMMAND_WINDOW_NAME: &str = "[Command Line]";
+pub const COMMAND_NAMES: &[&str] = &[
+    "Conflict",
+    "Explore",
+    "Files",
+    "Gblame",
+    "Ghunk",
+    "Gmerge",
+    "buffer",
+    "cclose",
+    "checktime",
+    "cnext",
+    "copen",
+    "cprevious",
+    "diffget",
+    "diffoff",
+    "diffput",
+    "diffthis",
+    "edit",
+    "grep",
+    "hex",
+    "lsp",
+    "make",
+    "messages",
+    "quit",
+    "set",
+    "sign",
+    "snippet",
+    "split",
+    "swapname",
+    "view",
+    "vsplit",
+    "write",
+];
+pub const CONFLICT_SUBCOMMANDS: &[&str] = &["base", "both", "ours", "theirs"];
+pub const EXPLORE_SUBCOMMANDS: &[&str] = &["close", "create", "delete", "move", "refresh", "reveal"];
+pub const GHUNK_SUBCOMMANDS: &[&str] = &["reset", "stage"];
+pub const SIGN_SUBCOMMANDS: &[&str] = &["clear", "place", "unplace"];
+pub const LSP_SUBCOMMANDS: &[&str] = &[
+    "action",
+    "add",
+    "definition",
+    "diagnostics",
+    "hover",
+    "info",
+    "references",
+    "rename",
+    "start",
+    "stop",
+];
+
+#[derive(Default)]
+pub struct CommandHistory {
+    entries: Vec<String>,
+}
+
+impl CommandHistory {
+    pub fn load() -> Self {
+        let entries = state_dir()
+            .and_then(|dir| fs::read_to_string(dir.join(HISTORY_FILE)).ok())
+            .map(|contents| contents.lines().map(String::from).collect())
+            .unwrap_or_default();
+        Self { entries }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let dir = state_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory"))?;
+        fs::create_dir_all(&dir)?;
+
+        let contents: String = self.entries.iter().map(|entry| format!("{}\n", entry)).collect();
+        fs::write(dir.join(HISTORY_FILE), contents)
+    }
+
+    pub fn add(&mut self, command: &str) {
+        if command.trim().is_empty() {
+            return;
+        }
+        self.entries.retain(|entry| entry != command);
+        self.entries.push(command.to_string());
+        if self.entries.len() > HISTORY_SIZE {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    pub fn replace(&mut self, entries: Vec<String>) {
+        self.entries.clear();
+        entries.iter().for_each(|entry| self.add(entry));
+    }
+}
+
+pub struct Completion {
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    start: usize,
+}
+
+#[derive(Default)]
+pub struct CommandLine {
+    text: String,
+    cursor: usize,
+    pub history: CommandHistory,
+    browsing: Option<(usize, String)>,
+    completion: Option<Completion>,
+}
+
+impl CommandLine {
+    pub fn new(history: CommandHistory) -> Self {
+        Self {
+            history,
+            ..Self::default()
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.cursor].chars().count()
+    }
+
+    pub fn clear(&mut self) {
+        self.set_text(String::new());
+        self.browsing = None;
+    }
+
+    pub fn take(&mut self) -> String {
+        let command = std::mem::take(&mut self.text);
+        self.history.add(&command);
+        self.clear();
+        command
+    }
+
+    // Starts editing a command with `text` already typed.
+    pub fn prefill(&mut self, text: String) {
+        self.browsing = None;
+        self.set_text(text);
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.text = text;
+        self.cursor = self.text.len();
+        self.completion = None;
+    }
+
+    fn previous_boundary(&self) -> usize {
+        self.text[..self.cursor].char_indices().last().map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..].chars().next().map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    pub fn edit(&mut self, edit: CommandLineEdit) {
+        self.completion = None;
+        if !matches!(edit, CommandLineEdit::HistoryOlder | CommandLineEdit::HistoryNewer) {
+            self.browsing = None;
+        }
+
+        match edit {
+            CommandLineEdit::Insert(c) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            },
+            CommandLineEdit::DeleteBackward => {
+                let start = self.previous_boundary();
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            },
+            CommandLineEdit::DeleteForward => {
+                let end = self.next_boundary();
+                self.text.replace_range(self.cursor..end, "");
+            },
+            CommandLineEdit::DeleteWord => {
+                let before = self.text[..self.cursor].trim_end();
+                let start = match before.chars().last() {
+                    Some(c) if c.is_alphanumeric() || c == '_' => {
+                        before.rfind(|c: char| !(c.is_alphanumeric() || c == '_')).map_or(0, |idx| idx + 1)
+                    },
+                    Some(c) => before.len() - c.len_utf8(),
+                    None => 0,
+                };
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            },
+            CommandLineEdit::DeleteToStart => {
+                self.text.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            },
+            CommandLineEdit::Left => self.cursor = self.previous_boundary(),
+            CommandLineEdit::Right => self.cursor = self.next_boundary(),
+            CommandLineEdit::Home => self.cursor = 0,
+            CommandLineEdit::End => self.cursor = self.text.len(),
+            CommandLineEdit::HistoryOlder => self.history_older(),
+            CommandLineEdit::HistoryNewer => self.history_newer(),
+        }
+    }
+
+    fn history_older(&mut self) {
+        let (idx, prefix) = self.browsing.take()
+            .unwrap_or_else(|| (self.history.entries.len(), self.text.clone()));
+        match self.history.entries[..idx].iter().rposition(|entry| entry.starts_with(&prefix)) {
+            Some(found) => {
+                self.set_text(self.history.entries[found].clone());
+                self.browsing = Some((found, prefix));
+            },
+            None => self.browsing = Some((idx, prefix)),
+        }
+    }
+
+    fn history_newer(&mut self) {
+        if let Some((idx, prefix)) = self.browsing.take() {
+            let entries = &self.history.entries;
+            match entries.iter().skip(idx + 1).position(|entry| entry.starts_with(&prefix)) {
+                Some(offset) => {
+                    self.set_text(entries[idx + 1 + offset].clone());
+                    self.browsing = Some((idx + 1 + offset, prefix));
+                },
+                None => self.set_text(prefix),
+            }
+        }
+    }
+
+    pub fn complete(&mut self, direction: Direction, buffer_names: &[String]) {
+        self.browsing = None;
+
+        if let Some(completion) = self.completion.as_mut() {
+            let len = completion.candidates.len();
+            completion.selected = match direction {
+                Direction::Forward => (completion.selected + 1) % len,
+                Direction::Backward => (completion.selected + len - 1) % len,
+            };
+            self.apply_completion();
+            return;
+        }
+
+        let (start, candidates) = self.candidates(buffer_names);
+        let selected = match direction {
+            Direction::Forward => 0,
+            Direction::Backward => candidates.len().saturating_sub(1),
+        };
+        if candidates.is_empty() {
+            return;
+        }
+
+        let single = candidates.len() == 1;
+        self.completion = Some(Completion { candidates, selected, start });
+        self.apply_completion();
+        if single {
+            self.completion = None;
+        }
+    }
+
+    fn apply_completion(&mut self) {
+        if let Some(completion) = &self.completion {
+            let candidate = &completion.candidates[completion.selected];
+            self.text.replace_range(completion.start..self.cursor, candidate);
+            self.cursor = completion.start + candidate.len();
+        }
+    }
+
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref()
+    }
+
+    pub fn completion_column(&self) -> usize {
+        self.completion.as_ref().map_or(0, |completion| self.text[..completion.start].chars().count())
+    }
+
+    fn candidates(&self, buffer_names: &[String]) -> (usize, Vec<String>) {
+        let before = &self.text[..self.cursor];
+        let start = before.rfind(' ').map_or(0, |idx| idx + 1);
+        let word = &before[start..];
+        let args: Vec<&str> = before[..start].split_whitespace().collect();
+
+        let candidates = match args[..] {
+            [] => Self::matching(COMMAND_NAMES.iter().copied(), word),
+            ["set" | "se", ..] => {
+                let mut options = Self::matching(OPTION_NAMES.iter().copied(), word);
+                if let Some(name) = word.strip_prefix("no") {
+                    options.extend(
+                        Self::matching(BOOLEAN_OPTION_NAMES.iter().copied(), name)
+                            .into_iter()
+                            .map(|option| format!("no{}", option)),
+                    );
+                }
+                options
+            },
+            ["Conflict"] => Self::matching(CONFLICT_SUBCOMMANDS.iter().copied(), word),
+            ["Ghunk"] => Self::matching(GHUNK_SUBCOMMANDS.iter().copied(), word),
+            ["sign"] => Self::matching(SIGN_SUBCOMMANDS.iter().copied(), word),
+            ["lsp"] => Self::matching(LSP_SUBCOMMANDS.iter().copied(), word),
+            ["e" | "edit" | "e!" | "edit!" | "vie" | "view" | "sp" | "split" | "vs" | "vsplit" | "w" | "write" | "w!" | "write!"] => Self::file_candidates(word),
+            ["gr" | "grep", _, ..] => Self::file_candidates(word),
+            ["Ex" | "Explore"] => {
+                let mut candidates = Self::matching(EXPLORE_SUBCOMMANDS.iter().copied(), word);
+                candidates.extend(Self::file_candidates(word).into_iter().filter(|name| name.ends_with('/')));
+                candidates
+            },
+            ["Ex" | "Explore", "create" | "delete" | "move", ..] => Self::file_candidates(word),
+            ["Files" | "files"] => Self::file_candidates(word).into_iter().filter(|name| name.ends_with('/')).collect(),
+            ["b" | "buffer"] => {
+                buffer_names.iter().filter(|name| name.contains(word)).cloned().collect()
+            },
+            _ => vec![],
+        };
+
+        (start, candidates)
+    }
+
+    fn matching<'a>(names: impl Iterator<Item = &'a str>, word: &str) -> Vec<String> {
+        names.filter(|name| name.starts_with(word)).map(String::from).collect()
+    }
+
+    fn file_candidates(word: &str) -> Vec<String> {
+        let (dir, prefix) = match word.rfind('/') {
+            Some(idx) => (&word[..=idx], &word[idx + 1..]),
+            None => ("", word),
+        };
+        let entries = match fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+
+        let mut candidates: Vec<String> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                    return None;
+                }
+                let suffix = if entry.path().is_dir() { "/" } else { "" };
+                Some(format!("{}{}{}", dir, name, suffix))
+            })
+            .collect();
+        candidates.sort();
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::Harness;
+
+    #[test]
+    fn recalls_completes_and_runs_commands() {
+        let mut harness = Harness::new(60, 12, "one\ntwo");
+        harness.keys(":set nonumber<CR>:set wrap<CR>:set number<CR>");
+
+        // Only entries starting with what was typed are recalled.
+        assert_eq!(harness.keys(":set n<Up>").screen().row(11).trim_end(), ":set number");
+        assert_eq!(harness.keys("<Up>").screen().row(11).trim_end(), ":set nonumber");
+        assert_eq!(harness.screen().cursor(), (13, 11));
+        assert_eq!(harness.keys("<Down>").screen().row(11).trim_end(), ":set number");
+        assert_eq!(harness.keys("<Down>").screen().row(11).trim_end(), ":set n");
+
+        harness.keys("<Esc>:s<Tab>");
+        assert_eq!(harness.screen().row(11).trim_end(), ":set");
+        assert!(harness.screen().row(6).starts_with("~ set "));
+        assert!(harness.screen().row(10).starts_with("t swapname "));
+        assert_eq!(harness.keys("<Tab>").screen().row(11).trim_end(), ":sign");
+        assert_eq!(harness.keys("<Esc>:set rel<Tab>").screen().row(11).trim_end(), ":set relativenumber");
+
+        harness.keys("<Esc>q:");
+        assert!(harness.screen().row(0).starts_with("    1 set nonumber "));
+        assert!(harness.screen().row(2).starts_with("    3 set number "));
+        assert_eq!(harness.screen().cursor(), (6, 2));
+        harness.keys("gg<CR>");
+        assert!(harness.screen().row(0).starts_with("one "));
+        assert_eq!(harness.buffer_text(), "one\ntwo");
+        assert_eq!(harness.keys(":<Up>").screen().row(11).trim_end(), ":set nonumber");
+    }
+}
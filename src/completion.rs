@@ -0,0 +1,360 @@
+use crate::prelude::*;
+use std::collections::HashSet;
+use std::str::FromStr;
+
+pub const COMPLETION_LABEL_WIDTH: usize = 40;
+pub const COMPLETION_DOC_WIDTH: usize = 50;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CompletionSource {
+    Words,
+    Paths,
+    LanguageServer,
+    Snippets,
+}
+
+impl FromStr for CompletionSource {
+    type Err = EditorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "words" => Ok(CompletionSource::Words),
+            "paths" => Ok(CompletionSource::Paths),
+            "lsp" => Ok(CompletionSource::LanguageServer),
+            "snippets" => Ok(CompletionSource::Snippets),
+            _ => Err(EditorError::InvalidArgument(format!("complete={}", value))),
+        }
+    }
+}
+
+impl CompletionSource {
+    pub fn all() -> Vec<Self> {
+        vec![CompletionSource::LanguageServer, CompletionSource::Snippets, CompletionSource::Words, CompletionSource::Paths]
+    }
+
+    // Parses the value of 'complete', e.g. "lsp,words".
+    pub fn parse_list(value: &str) -> EditorResult<Vec<Self>> {
+        value.split(',').filter(|name| !name.is_empty()).map(str::parse).collect()
+    }
+
+    pub fn tag(&self) -> &'static str {
+        match self {
+            CompletionSource::Words => "word",
+            CompletionSource::Paths => "path",
+            CompletionSource::LanguageServer => "lsp",
+            CompletionSource::Snippets => "snip",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CompletionItem {
+    pub label: String,
+    // Replaces the text typed since the completion started; snippet syntax
+    // such as ${1:name} is expanded when `snippet` is set.
+    pub text: String,
+    pub snippet: bool,
+    pub source: CompletionSource,
+    pub documentation: Vec<String>,
+}
+
+impl CompletionItem {
+    fn plain(label: String, source: CompletionSource) -> Self {
+        Self { text: label.clone(), label, snippet: false, source, documentation: vec![] }
+    }
+}
+
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Where completion starts on the line and what has been typed since. Text
+// containing a slash completes the last path component in `directory`.
+pub struct CompletionContext {
+    pub start: usize,
+    pub prefix: String,
+    pub directory: Option<PathBuf>,
+}
+
+impl CompletionContext {
+    pub fn new(line: &str, column: usize, file: Option<&Path>) -> Self {
+        let before = &line[..cmp::min(column, line.len())];
+        let token_start = before.rfind(|c: char| c.is_whitespace() || "\"'`()[]{}<>=,;".contains(c)).map_or(0, |idx| idx + 1);
+        let token = &before[token_start..];
+        if let Some(slash) = token.rfind('/') {
+            let dir = &token[..=slash];
+            let directory = match dir.strip_prefix("~/") {
+                Some(rest) => env::var_os("HOME").map(|home| PathBuf::from(home).join(rest)),
+                None if dir.starts_with('/') => Some(PathBuf::from(dir)),
+                // Relative paths are taken from the file's directory, like a reader of the file would.
+                None => Some(file.and_then(Path::parent).map_or_else(|| PathBuf::from("."), Path::to_path_buf).join(dir)),
+            };
+            let start = token_start + slash + 1;
+            return Self { start, prefix: before[start..].to_string(), directory };
+        }
+
+        let start = before.rfind(|c: char| !is_word_char(c)).map_or(0, |idx| idx + before[idx..].chars().next().map_or(1, char::len_utf8));
+        Self { start, prefix: before[start..].to_string(), directory: None }
+    }
+}
+
+pub fn word_items<'a>(lines: impl Iterator<Item = &'a str>, prefix: &str) -> Vec<CompletionItem> {
+    let mut seen = HashSet::new();
+    let mut items = vec![];
+    for line in lines {
+        for word in line.split(|c: char| !is_word_char(c)) {
+            if word.chars().count() < 2 || word == prefix || !seen.insert(word) {
+                continue;
+            }
+            items.push(CompletionItem::plain(word.to_string(), CompletionSource::Words));
+        }
+    }
+    items
+}
+
+pub fn path_items(directory: &Path, prefix: &str) -> Vec<CompletionItem> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut items: Vec<CompletionItem> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') && !prefix.starts_with('.') {
+                return None;
+            }
+            let suffix = if entry.path().is_dir() { "/" } else { "" };
+            Some(CompletionItem::plain(format!("{}{}", name, suffix), CompletionSource::Paths))
+        })
+        .collect();
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    items
+}
+
+pub struct Snippet {
+    pub name: String,
+    pub body: String,
+    // Languages as named by language_id; empty for snippets offered everywhere.
+    pub languages: Vec<String>,
+}
+
+pub struct Snippets {
+    snippets: Vec<Snippet>,
+}
+
+impl Default for Snippets {
+    fn default() -> Self {
+        let snippet = |name: &str, body: &str, languages: &[&str]| Snippet {
+            name: name.to_string(),
+            body: body.to_string(),
+            languages: languages.iter().map(|language| language.to_string()).collect(),
+        };
+        let scripts = ["typescript", "typescriptreact", "javascript", "javascriptreact"];
+        Self {
+            snippets: vec![
+                snippet("fn", "fn ${1:name}(${2}) {\n    $0\n}", &["rust"]),
+                snippet("impl", "impl ${1:Type} {\n    $0\n}", &["rust"]),
+                snippet("test", "#[test]\nfn ${1:name}() {\n    $0\n}", &["rust"]),
+                snippet("function", "function ${1:name}(${2}) {\n    $0\n}", &scripts),
+            ],
+        }
+    }
+}
+
+impl Snippets {
+    // Adds or replaces a snippet from the arguments of :snippet, where \n and
+    // \t in the body stand for a newline and a tab.
+    pub fn add(&mut self, args: &str) -> EditorResult<()> {
+        let (name, body) = args.split_once(' ').ok_or_else(|| EditorError::InvalidArgument(args.to_string()))?;
+        let body = body.trim_start().replace("\\n", "\n").replace("\\t", "\t");
+        self.snippets.retain(|snippet| !(snippet.name == name && snippet.languages.is_empty()));
+        self.snippets.push(Snippet { name: name.to_string(), body, languages: vec![] });
+        Ok(())
+    }
+
+    pub fn listing(&self) -> Vec<String> {
+        self.snippets.iter()
+            .map(|snippet| {
+                let languages = if snippet.languages.is_empty() { String::from("all") } else { snippet.languages.join(",") };
+                format!("{} [{}] {}", snippet.name, languages, snippet.body.replace('\n', "\\n"))
+            })
+            .collect()
+    }
+
+    pub fn items(&self, language: &str) -> Vec<CompletionItem> {
+        self.snippets.iter()
+            .filter(|snippet| snippet.languages.is_empty() || snippet.languages.iter().any(|name| name == language))
+            .map(|snippet| CompletionItem {
+                label: snippet.name.clone(),
+                text: snippet.body.clone(),
+                snippet: true,
+                source: CompletionSource::Snippets,
+                documentation: expand_snippet(&snippet.body).0.lines().map(String::from).collect(),
+            })
+            .collect()
+    }
+}
+
+// Expands tab stops ($1, ${2:default}, $0) into plain text and returns the
+// byte offset for the cursor: the first numbered stop, else $0, else the end.
+pub fn expand_snippet(body: &str) -> (String, usize) {
+    let mut text = String::new();
+    let mut stops: Vec<(usize, usize)> = vec![];
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            '$' if chars.peek().is_some_and(|c| c.is_ascii_digit() || *c == '{') => {
+                let braced = chars.next_if_eq(&'{').is_some();
+                let mut number = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    number.push(digit);
+                }
+                stops.push((number.parse().unwrap_or(0), text.len()));
+                if !braced {
+                    continue;
+                }
+                let mut default = String::new();
+                let mut depth = 0;
+                chars.next_if_eq(&':');
+                for c in chars.by_ref() {
+                    match c {
+                        '{' => depth += 1,
+                        '}' if depth == 0 => break,
+                        '}' => depth -= 1,
+                        _ => {},
+                    }
+                    default.push(c);
+                }
+                text.push_str(&expand_snippet(&default).0);
+            },
+            c => text.push(c),
+        }
+    }
+    let cursor = stops.iter()
+        .filter(|(number, _)| *number > 0)
+        .min_by_key(|(number, _)| *number)
+        .or_else(|| stops.iter().find(|(number, _)| *number == 0))
+        .map_or(text.len(), |(_, offset)| *offset);
+    (text, cursor)
+}
+
+// Scores a candidate for the typed pattern: every pattern character has to
+// appear in order, and matches at the start of words or right after the
+// previous match count more. Patterns without capitals ignore case.
+pub fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    fuzzy_match(pattern, candidate).map(|(score, _)| score)
+}
+
+// Smartcase subsequence match that also returns the char indices it matched,
+// so callers can highlight them.
+pub fn fuzzy_match(pattern: &str, candidate: &str) -> Option<(i64, Vec<usize>)> {
+    let ignore_case = !pattern.chars().any(char::is_uppercase);
+    let same = |a: char, b: char| if ignore_case { a.to_lowercase().eq(b.to_lowercase()) } else { a == b };
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last: Option<usize> = None;
+    let mut positions = vec![];
+    for p in pattern.chars() {
+        let found = (next..chars.len()).find(|idx| same(chars[*idx], p))?;
+        score += 1;
+        let word_start = found == 0
+            || !chars[found - 1].is_alphanumeric()
+            || (chars[found - 1].is_lowercase() && chars[found].is_uppercase());
+        if last.is_some_and(|last| last + 1 == found) {
+            score += 4;
+        } else if word_start {
+            score += 3;
+        }
+        score -= cmp::min(found - last.map_or(0, |last| last + 1), 3) as i64;
+        last = Some(found);
+        next = found + 1;
+        positions.push(found);
+    }
+    Some((score * 10 - chars.len() as i64, positions))
+}
+
+pub fn rank(items: Vec<CompletionItem>, prefix: &str) -> Vec<CompletionItem> {
+    let mut scored: Vec<(i64, CompletionItem)> = items.into_iter()
+        .filter_map(|item| Some((fuzzy_score(prefix, &item.label)?, item)))
+        .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| a.label.cmp(&b.label)));
+    scored.into_iter().map(|(_, item)| item).collect()
+}
+
+// The popup shown while completing in insert mode, anchored where the
+// completed text starts.
+pub struct CompletionMenu {
+    pub line: usize,
+    pub start: usize,
+    pub items: Vec<CompletionItem>,
+    pub selected: usize,
+    pub explicit: bool,
+    // Items from the language server, which is only asked when the menu opens.
+    pub server_items: Vec<CompletionItem>,
+    pub waiting: bool,
+}
+
+impl CompletionMenu {
+    pub fn select(&mut self, direction: Direction) {
+        let len = self.items.len();
+        if len > 0 {
+            self.selected = match direction {
+                Direction::Forward => (self.selected + 1) % len,
+                Direction::Backward => (self.selected + len - 1) % len,
+            };
+        }
+    }
+
+    pub fn selected_item(&self) -> Option<&CompletionItem> {
+        self.items.get(self.selected)
+    }
+}
+
+// Rows for a popup of the wanted height next to the anchor row: below it
+// when there is room, otherwise wherever more rows are free.
+pub fn popup_rows(anchor_row: usize, wanted: usize, lines: usize) -> (usize, usize) {
+    let below = lines.saturating_sub(anchor_row + 1);
+    let above = anchor_row;
+    if below >= wanted || below >= above {
+        (anchor_row + 1, cmp::min(wanted, below))
+    } else {
+        let height = cmp::min(wanted, above);
+        (anchor_row - height, height)
+    }
+}
+
+// Shifts the popup left when it would run past the right edge.
+pub fn popup_column(anchor_column: usize, width: usize, columns: usize) -> usize {
+    cmp::min(anchor_column, columns.saturating_sub(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+
+    #[test]
+    fn completes_in_insert_mode() {
+        assert_eq!(expand_snippet("fn ${1:name}(${2}) {\n    $0\n}"), (String::from("fn name() {\n    \n}"), 3));
+        assert!(fuzzy_score("fb", "foo_bar") > fuzzy_score("fb", "fabric"));
+        assert_eq!(popup_rows(8, 5, 10), (3, 5));
+        assert_eq!(popup_column(75, 10, 80), 70);
+
+        let mut harness = Harness::new(80, 12, "alpha alphabet beta\n");
+        harness.keys("Goal<C-n>");
+        let screen = harness.screen().text();
+        let rows: Vec<&str> = screen.lines().collect();
+        assert!(rows[2].contains(" alpha ") && rows[2].contains("word"));
+        assert!(rows[3].contains(" alphabet "));
+        harness.keys("<C-n><CR>");
+        assert_eq!(harness.buffer_text(), "alpha alphabet beta\nalphabet");
+
+        harness.keys("<Esc>:snippet pair (${1:left}, $0)<CR>A pai<C-n>");
+        assert!(harness.screen().row(2).contains(" pair snip  (left, )"));
+        harness.keys("<C-y>x<Esc>");
+        assert_eq!(harness.buffer_text(), "alpha alphabet beta\nalphabet (xleft, )");
+    }
+}
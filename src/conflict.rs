@@ -0,0 +1,159 @@
+use crate::prelude::*;
+use std::str::FromStr;
+
+const MARKER_LENGTH: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConflictSection {
+    Marker,
+    Ours,
+    Base,
+    Theirs,
+}
+
+pub enum ConflictChoice {
+    Ours,
+    Theirs,
+    Both,
+    Base,
+}
+
+impl FromStr for ConflictChoice {
+    type Err = EditorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ours" => Ok(ConflictChoice::Ours),
+            "theirs" => Ok(ConflictChoice::Theirs),
+            "both" => Ok(ConflictChoice::Both),
+            "base" => Ok(ConflictChoice::Base),
+            _ => Err(EditorError::InvalidArgument(value.to_string())),
+        }
+    }
+}
+
+// The marker lines of a conflict left by a merge. `base` is only there in
+// the diff3 style, which also shows the common ancestor's version.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conflict {
+    pub start: usize,
+    pub base: Option<usize>,
+    pub separator: usize,
+    pub end: usize,
+}
+
+impl Conflict {
+    pub fn contains_line(&self, line: usize) -> bool {
+        (self.start..=self.end).contains(&line)
+    }
+
+    pub fn section(&self, line: usize) -> Option<ConflictSection> {
+        match line {
+            line if !self.contains_line(line) => None,
+            line if line == self.start || Some(line) == self.base || line == self.separator || line == self.end => {
+                Some(ConflictSection::Marker)
+            },
+            line if line < self.base.unwrap_or(self.separator) => Some(ConflictSection::Ours),
+            line if line < self.separator => Some(ConflictSection::Base),
+            _ => Some(ConflictSection::Theirs),
+        }
+    }
+
+    // The lines that replace the whole conflict, markers included.
+    pub fn resolve(&self, lines: &[String], choice: &ConflictChoice) -> EditorResult<Vec<String>> {
+        let ours = &lines[self.start + 1..self.base.unwrap_or(self.separator)];
+        let theirs = &lines[self.separator + 1..self.end];
+        Ok(match choice {
+            ConflictChoice::Ours => ours.to_vec(),
+            ConflictChoice::Theirs => theirs.to_vec(),
+            ConflictChoice::Base => lines[self.base.ok_or(EditorError::NoBase)? + 1..self.separator].to_vec(),
+            ConflictChoice::Both => [ours, theirs].concat(),
+        })
+    }
+}
+
+fn is_marker(line: &str, c: char) -> bool {
+    let rest = line.trim_start_matches(c);
+    line.len() - rest.len() == MARKER_LENGTH && (rest.is_empty() || rest.starts_with(' '))
+}
+
+// Finds the complete conflicts in the text. Markers that don't make up a
+// whole conflict are left alone.
+pub fn find_conflicts<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<Conflict> {
+    let mut conflicts = vec![];
+    let (mut start, mut base, mut separator) = (None, None, None);
+    for (idx, line) in lines.enumerate() {
+        match (start, separator) {
+            _ if is_marker(line, '<') => (start, base, separator) = (Some(idx), None, None),
+            (Some(_), None) if base.is_none() && is_marker(line, '|') => base = Some(idx),
+            (Some(_), None) if is_marker(line, '=') => separator = Some(idx),
+            (Some(start_line), Some(separator_line)) if is_marker(line, '>') => {
+                conflicts.push(Conflict { start: start_line, base, separator: separator_line, end: idx });
+                (start, base, separator) = (None, None, None);
+            },
+            _ => {},
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+
+    #[test]
+    fn resolves_conflicts() {
+        let text = "\
+start
+<<<<<<< HEAD
+ours
+=======
+theirs
+>>>>>>> topic
+middle
+<<<<<<< HEAD
+ours 2
+||||||| base
+base 2
+=======
+theirs 2
+>>>>>>> topic
+=======
+end";
+        let conflicts = find_conflicts(text.lines());
+        assert_eq!(conflicts, vec![
+            Conflict { start: 1, base: None, separator: 3, end: 5 },
+            Conflict { start: 7, base: Some(9), separator: 11, end: 13 },
+        ]);
+        let sections: Vec<Option<ConflictSection>> = (7..=14).map(|line| conflicts[1].section(line)).collect();
+        assert_eq!(sections, vec![
+            Some(ConflictSection::Marker),
+            Some(ConflictSection::Ours),
+            Some(ConflictSection::Marker),
+            Some(ConflictSection::Base),
+            Some(ConflictSection::Marker),
+            Some(ConflictSection::Theirs),
+            Some(ConflictSection::Marker),
+            None,
+        ]);
+
+        let mut harness = Harness::new(80, 20, text);
+        assert!(harness.keys("").screen().row(18).contains("[2 conflicts]"));
+        harness.keys("]x");
+        assert_eq!(harness.cursor(), (1, 0));
+        harness.keys("]x");
+        assert_eq!(harness.cursor(), (7, 0));
+        assert!(harness.keys("]x").screen().row(19).contains("No more items"));
+        harness.keys("k:Conflict base<CR>");
+        assert!(harness.screen().row(19).contains("No conflict at the cursor"));
+
+        harness.keys("G[x[x:Conflict base<CR>");
+        assert!(harness.screen().row(19).contains("Conflict has no base"));
+        harness.keys(":Conflict both<CR>");
+        assert!(harness.screen().row(18).contains("[1 conflict]"));
+        harness.keys("G[xj:Conflict base<CR>");
+        assert_eq!(harness.buffer_text(), "start\nours\ntheirs\nmiddle\nbase 2\n=======\nend");
+        assert!(!harness.screen().row(18).contains("conflict"));
+    }
+}
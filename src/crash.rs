@@ -0,0 +1,192 @@
+use crate::prelude::*;
+use std::backtrace::Backtrace;
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const CRASH_DIR: &str = "crash";
+pub const CRASH_REPORT_FILE: &str = "report.txt";
+pub const RECOVERY_EXTENSION: &str = "recover";
+pub const KEY_LOG_SIZE: usize = 50;
+
+static KEY_LOG: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+pub fn log_key(key_event: &KeyEvent) {
+    if let Ok(mut log) = KEY_LOG.lock() {
+        log.push_back(format!("{:?}", key_event));
+        if log.len() > KEY_LOG_SIZE {
+            log.pop_front();
+        }
+    }
+}
+
+fn recent_keys() -> Vec<String> {
+    KEY_LOG.lock().map(|log| log.iter().cloned().collect()).unwrap_or_default()
+}
+
+pub struct CrashReporter {
+    dir: PathBuf,
+}
+
+impl CrashReporter {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn for_this_session() -> Option<Self> {
+        let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        state_dir().map(|dir| Self::new(dir.join(CRASH_DIR).join(format!("{}-{}", secs, std::process::id()))))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Only a panic on the thread installing the hook, the one running the
+    // editor, ends the session. Background threads just die with the usual
+    // message while the editor keeps the terminal.
+    pub fn install(&self) {
+        let dir = self.dir.clone();
+        let editor_thread = thread::current().id();
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().id() != editor_thread {
+                return default_hook(info);
+            }
+            restore_terminal();
+            let report = CrashReporter::new(dir.clone()).write_report(&info.to_string(), &Backtrace::force_capture());
+            default_hook(info);
+            match report {
+                Ok(path) => eprintln!("Crash report written to {}", path.display()),
+                Err(err) => eprintln!("Unable to write crash report: {}", err),
+            }
+        }));
+    }
+
+    // Runs the editor and, if it panics, saves the modified buffers next to
+    // the crash report before letting the panic carry on.
+    pub fn guard<T>(reporter: Option<&Self>, editor: &mut Editor, run: impl FnOnce(&mut Editor) -> T) -> T {
+        let payload = match panic::catch_unwind(AssertUnwindSafe(|| run(editor))) {
+            Ok(value) => return value,
+            Err(payload) => payload,
+        };
+        if let Some(reporter) = reporter {
+            match reporter.write_recovery(editor.buffers().into_iter()) {
+                Ok(files) if !files.is_empty() => eprintln!("Modified buffers saved to {}", reporter.dir().display()),
+                Ok(_) => {},
+                Err(err) => eprintln!("Unable to save modified buffers: {}", err),
+            }
+        }
+        panic::resume_unwind(payload)
+    }
+
+    pub fn write_report(&self, message: &str, backtrace: &Backtrace) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+
+        let mut report = format!("editor {} crashed\n{}\n\nLast key events:\n", VERSION, message);
+        for key in recent_keys() {
+            report.push_str(&format!("  {}\n", key));
+        }
+        report.push_str(&format!("\nBacktrace:\n{}\n", backtrace));
+
+        let path = self.dir.join(CRASH_REPORT_FILE);
+        fs::write(&path, report)?;
+        Ok(path)
+    }
+
+    pub fn write_recovery<'a>(&self, buffers: impl Iterator<Item = &'a Buffer>) -> io::Result<Vec<PathBuf>> {
+        let mut written = vec![];
+        for (idx, buffer) in buffers.enumerate().filter(|(_, buffer)| buffer.modified) {
+            fs::create_dir_all(&self.dir)?;
+
+            let name = buffer.display_name().replace(['/', '\\'], "%");
+            let path = self.dir.join(format!("{}-{}.{}", idx, name, RECOVERY_EXTENSION));
+            let contents: String = buffer.text_lines().map(|line| format!("{}\n", line)).collect();
+            fs::write(&path, contents)?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{parse_keys, Harness, TempDir};
+
+    // Panic hooks are global, so tests that install one take this lock and
+    // put the previous hook back on drop, even when an assertion fails.
+    static HOOK_LOCK: Mutex<()> = Mutex::new(());
+
+    type Hook = Box<dyn Fn(&panic::PanicHookInfo<'_>) + Sync + Send + 'static>;
+
+    struct InstalledHook {
+        previous: Option<Hook>,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+
+    impl InstalledHook {
+        fn new(reporter: &CrashReporter) -> Self {
+            let lock = HOOK_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let previous = panic::take_hook();
+            reporter.install();
+            Self { previous: Some(previous), _lock: lock }
+        }
+    }
+
+    impl Drop for InstalledHook {
+        fn drop(&mut self) {
+            if let Some(previous) = self.previous.take() {
+                panic::set_hook(previous);
+            }
+        }
+    }
+
+    #[test]
+    fn panic_writes_crash_report_and_recovery_files() {
+        let dir = TempDir::new("crash");
+        let reporter = CrashReporter::new(dir.join("session"));
+        let mut harness = Harness::new(80, 12, "first\nsecond");
+        harness.keys("x");
+
+        let hook = InstalledHook::new(&reporter);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            CrashReporter::guard(Some(&reporter), harness.editor(), |editor| {
+                editor.feed_keys(parse_keys("jdd"));
+                while editor.run().unwrap() {}
+                panic!("render failed")
+            })
+        }));
+        drop(hook);
+        assert!(result.is_err());
+
+        let recovered = dir.join("session/0-test.recover");
+        assert_eq!(fs::read_to_string(recovered).unwrap(), "irst\n");
+        let entries = fs::read_dir(dir.join("session")).unwrap().count();
+        assert_eq!(entries, 2);
+
+        let report = fs::read_to_string(dir.join("session").join(CRASH_REPORT_FILE)).unwrap();
+        assert!(report.contains("render failed"));
+        assert!(report.contains("Char('d')"));
+        assert!(report.contains("Backtrace:"));
+    }
+
+    #[test]
+    fn ignores_panics_on_background_threads() {
+        let dir = TempDir::new("crash");
+        let reporter = CrashReporter::new(dir.join("session"));
+
+        let hook = InstalledHook::new(&reporter);
+        let result = thread::spawn(|| panic!("reader failed")).join();
+        assert!(result.is_err());
+        assert!(!dir.join("session").exists());
+
+        let result = panic::catch_unwind(|| panic!("render failed"));
+        drop(hook);
+        assert!(result.is_err());
+        let report = fs::read_to_string(dir.join("session").join(CRASH_REPORT_FILE)).unwrap();
+        assert!(report.contains("render failed"));
+    }
+}
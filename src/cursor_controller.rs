@@ -1,5 +1,4 @@
-use crate::prelude::*;
-
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
 pub struct CursorPosition {
     pub column: usize,
     pub line: usize,
@@ -7,40 +6,20 @@ pub struct CursorPosition {
 
 pub struct CursorController {
     pub position: CursorPosition,
+    pub text_position: CursorPosition,
     pub frame_columns: usize,
     pub frame_lines: usize,
+    pub gutter_width: usize,
 }
 
 impl CursorController {
     pub fn new(win_size: (usize, usize)) -> Self {
         Self {
             position: CursorPosition { column: 0, line: 0 },
+            text_position: CursorPosition { column: 0, line: 0 },
             frame_columns: win_size.0,
             frame_lines: win_size.1,
-        }
-    }
-
-    pub fn move_cursor_up(&mut self) {
-        if self.position.line > 0 {
-            self.position.line -= 1;
-        }
-    }
-
-    pub fn move_cursor_down(&mut self) {
-        if self.position.line < self.frame_lines {
-            self.position.line += 1;
-        }
-    }
-
-    pub fn move_cursor_right(&mut self) {
-        if self.position.column < self.frame_columns {
-            self.position.column += 1;
-        }
-    }
-
-    pub fn move_cursor_left(&mut self) {
-        if self.position.column > 0 {
-            self.position.column -= 1;
+            gutter_width: 0,
         }
     }
 
@@ -48,10 +27,15 @@ impl CursorController {
         self.position.column = col;
     }
 
+    pub fn move_cursor_to_line(&mut self, line: usize) {
+        self.position.line = line;
+    }
+
     pub fn absolute_coords(&self) -> (usize, usize) {
-        (self.position.column + GUTTER_WIDTH, self.position.line)
+        (self.position.column + self.gutter_width, self.position.line)
     }
 
+    #[allow(dead_code)]
     pub fn reset_cursor(&mut self) {
         self.position.column = 0;
         self.position.line = 0;
@@ -0,0 +1,374 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+
+// Past this many differences the rest is reported as one change, which keeps
+// the search's memory bounded when comparing unrelated texts.
+const MAX_EDIT_DISTANCE: usize = 1000;
+
+// A run of changed items: `old_count` items at `old_start` were replaced by
+// `new_count` items at `new_start`. Either count may be zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_start: usize,
+    pub new_count: usize,
+}
+
+impl Hunk {
+    pub fn old_end(&self) -> usize {
+        self.old_start + self.old_count
+    }
+
+    pub fn new_end(&self) -> usize {
+        self.new_start + self.new_count
+    }
+
+    // The line in the new text the hunk is shown at: its first line, or for a
+    // deletion the line above the gap.
+    pub fn line(&self) -> usize {
+        if self.new_count == 0 { self.new_start.saturating_sub(1) } else { self.new_start }
+    }
+
+    pub fn contains_line(&self, line: usize) -> bool {
+        line == self.line() || (self.new_start..self.new_end()).contains(&line)
+    }
+}
+
+// The changes that turn `old` into `new`, in order, using Myers' algorithm
+// after setting aside the common prefix and suffix.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut hunks: Vec<Hunk> = vec![];
+    let (mut x, mut y) = (0, 0);
+    for (next_x, next_y, matched) in shortest_edit(old, new) {
+        if next_x > x || next_y > y {
+            match hunks.last_mut() {
+                Some(last) if last.old_end() == prefix + x && last.new_end() == prefix + y => {
+                    last.old_count += next_x - x;
+                    last.new_count += next_y - y;
+                },
+                _ => hunks.push(Hunk { old_start: prefix + x, old_count: next_x - x, new_start: prefix + y, new_count: next_y - y }),
+            }
+        }
+        (x, y) = (next_x + matched, next_y + matched);
+    }
+    hunks
+}
+
+// Walks the edit graph and returns each run of matching items as its start
+// in `a` and `b` and its length, ending with the far corner and a length of zero.
+fn shortest_edit<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let limit = cmp::min(a.len() + b.len(), MAX_EDIT_DISTANCE) as isize;
+    // `v[offset + k]` is the furthest x reached on diagonal k = x - y. Each
+    // round starts by keeping the diagonals it can read, to trace back later.
+    let offset = limit + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace: Vec<Vec<isize>> = vec![];
+    let mut depth = None;
+    'search: for d in 0..=limit {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[(offset + k - 1) as usize] < v[(offset + k + 1) as usize]);
+            let mut x = if down { v[(offset + k + 1) as usize] } else { v[(offset + k - 1) as usize] + 1 };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[(offset + k) as usize] = x;
+            if x >= n && y >= m {
+                depth = Some(d);
+                break 'search;
+            }
+        }
+    }
+
+    let mut runs = vec![(a.len(), b.len(), 0)];
+    let depth = match depth {
+        Some(depth) => depth,
+        None => return runs,
+    };
+    let (mut x, mut y) = (n, m);
+    for d in (1..=depth).rev() {
+        let at = |k: isize| trace[d as usize][(k + d) as usize];
+        let k = x - y;
+        let down = k == -d || (k != d && at(k - 1) < at(k + 1));
+        let previous_k = if down { k + 1 } else { k - 1 };
+        let (previous_x, previous_y) = (at(previous_k), at(previous_k) - previous_k);
+        // One insertion or deletion, then the run of matches up to (x, y).
+        let (run_x, run_y) = if down { (previous_x, previous_y + 1) } else { (previous_x + 1, previous_y) };
+        runs.push((run_x as usize, run_y as usize, (x - run_x) as usize));
+        (x, y) = (previous_x, previous_y);
+    }
+    runs.push((0, 0, x as usize));
+    runs.reverse();
+    runs
+}
+
+// Where the next change after `line` starts, or the previous one before it.
+pub fn next_change(starts: &[usize], line: usize, direction: &Direction) -> Option<usize> {
+    match direction {
+        Direction::Forward => starts.iter().copied().find(|start| *start > line),
+        Direction::Backward => starts.iter().rev().copied().find(|start| *start < line),
+    }
+}
+
+// A line only one side has, or one the sides have in different versions
+// with the byte ranges where its text differs.
+#[derive(Debug, PartialEq)]
+pub enum DiffLine {
+    Added,
+    Changed(Vec<(usize, usize)>),
+}
+
+// One frame's part in a diff. The texts are laid out in rows that line up
+// across the frames, and `line_rows` has the row of each line, so the rows
+// between two lines are fillers standing in for text only others have.
+#[derive(Default)]
+pub struct DiffView {
+    line_rows: Vec<usize>,
+    total_rows: usize,
+    lines: HashMap<usize, DiffLine>,
+    pub changes: Vec<usize>,
+    pub tick: Option<usize>,
+    pub sides: usize,
+}
+
+impl DiffView {
+    // Filler rows shown above `line`; one past the last line for those at the end.
+    pub fn fillers(&self, line: usize) -> usize {
+        let row = self.line_rows.get(line).copied().unwrap_or(self.total_rows);
+        match line.checked_sub(1).and_then(|previous| self.line_rows.get(previous)) {
+            Some(previous) => row.saturating_sub(previous + 1),
+            None if line == 0 => row,
+            None => 0,
+        }
+    }
+
+    pub fn line(&self, line: usize) -> Option<&DiffLine> {
+        self.lines.get(&line)
+    }
+
+    pub fn row_of(&self, line: usize) -> Option<usize> {
+        self.line_rows.get(line).copied()
+    }
+
+    // The line on `row`, or the first one below it with how many of its
+    // fillers are above `row`.
+    pub fn line_at(&self, row: usize) -> Option<(usize, usize)> {
+        let line = self.line_rows.partition_point(|line_row| *line_row < row);
+        let line_row = self.line_rows.get(line)?;
+        Some((line, self.fillers(line) - (line_row - row)))
+    }
+}
+
+// Lines the texts up to be shown side by side and returns a view for each.
+// Every text is compared with the one at `anchor`, which lets three texts
+// line up too.
+pub fn diff_views(texts: &[Vec<String>], anchor: usize) -> Vec<DiffView> {
+    let base = &texts[anchor];
+    // For each text, its own lines before each anchor line, and the line
+    // it shows beside it.
+    let mut before: Vec<Vec<Vec<usize>>> = vec![vec![vec![]; base.len() + 1]; texts.len()];
+    let mut beside: Vec<Vec<Option<usize>>> = vec![vec![None; base.len()]; texts.len()];
+    let mut changed = vec![false; base.len()];
+    for (side, text) in texts.iter().enumerate() {
+        if side == anchor {
+            beside[side] = (0..base.len()).map(Some).collect();
+            continue;
+        }
+        let (mut old, mut new) = (0, 0);
+        for hunk in diff(base, text) {
+            while old < hunk.old_start {
+                beside[side][old] = Some(new);
+                (old, new) = (old + 1, new + 1);
+            }
+            let paired = cmp::min(hunk.old_count, hunk.new_count);
+            for offset in 0..hunk.old_count {
+                beside[side][old + offset] = (offset < paired).then_some(new + offset);
+                changed[old + offset] = true;
+            }
+            before[side][hunk.old_end()].extend(hunk.new_start + paired..hunk.new_end());
+            (old, new) = (hunk.old_end(), hunk.new_end());
+        }
+        while old < base.len() {
+            beside[side][old] = Some(new);
+            (old, new) = (old + 1, new + 1);
+        }
+    }
+
+    let mut rows: Vec<(Vec<Option<usize>>, bool)> = vec![];
+    for line in 0..=base.len() {
+        let gap = before.iter().map(|lines| lines[line].len()).max().unwrap_or(0);
+        for offset in 0..gap {
+            rows.push((before.iter().map(|lines| lines[line].get(offset).copied()).collect(), true));
+        }
+        if line < base.len() {
+            rows.push((beside.iter().map(|lines| lines[line]).collect(), changed[line]));
+        }
+    }
+
+    // Other texts are compared with the anchor first, as that is what they were aligned with.
+    let order: Vec<usize> = std::iter::once(anchor).chain((0..texts.len()).filter(|side| *side != anchor)).collect();
+    (0..texts.len())
+        .map(|side| {
+            let mut view = DiffView { total_rows: rows.len(), sides: texts.len(), ..DiffView::default() };
+            for (row, (lines, changed)) in rows.iter().enumerate() {
+                let line = match lines[side] {
+                    Some(line) => line,
+                    None => continue,
+                };
+                view.line_rows.push(row);
+                if !changed {
+                    continue;
+                }
+                let text = texts[side][line].as_str();
+                let others: Vec<&str> = order.iter()
+                    .filter(|other| **other != side)
+                    .filter_map(|other| lines[*other].map(|line| texts[*other][line].as_str()))
+                    .collect();
+                let kind = match others.iter().find(|other| **other != text).or(others.first()) {
+                    Some(other) => DiffLine::Changed(changed_ranges(text, other)),
+                    None => DiffLine::Added,
+                };
+                view.lines.insert(line, kind);
+            }
+            // Each run of changed rows is a change, starting at its first line
+            // here or, when this side only has fillers there, the line above.
+            for (row, (_, changed)) in rows.iter().enumerate() {
+                if !changed || row > 0 && rows[row - 1].1 {
+                    continue;
+                }
+                let first = rows[row..].iter().take_while(|(_, changed)| *changed).find_map(|(lines, _)| lines[side]);
+                let above = view.line_rows.partition_point(|line_row| *line_row < row).saturating_sub(1);
+                view.changes.push(first.unwrap_or(above));
+            }
+            view.changes.dedup();
+            view
+        })
+        .collect()
+}
+
+// The byte ranges of `text` that are not in `other`.
+fn changed_ranges(text: &str, other: &str) -> Vec<(usize, usize)> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let offset = |idx: usize| chars.get(idx).map_or(text.len(), |(offset, _)| *offset);
+    let text_chars: Vec<char> = chars.iter().map(|(_, c)| *c).collect();
+    let other_chars: Vec<char> = other.chars().collect();
+    diff(&text_chars, &other_chars)
+        .into_iter()
+        .filter(|hunk| hunk.old_count > 0)
+        .map(|hunk| (offset(hunk.old_start), offset(hunk.old_end())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    fn lcs_length(a: &[u8], b: &[u8]) -> usize {
+        let mut lengths = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in 0..a.len() {
+            for j in 0..b.len() {
+                lengths[i + 1][j + 1] = if a[i] == b[j] { lengths[i][j] + 1 } else { cmp::max(lengths[i][j + 1], lengths[i + 1][j]) };
+            }
+        }
+        lengths[a.len()][b.len()]
+    }
+
+    #[test]
+    fn finds_minimal_hunks() {
+        let old = ["a", "b", "c", "d", "e", "f"];
+        let new = ["a", "x", "c", "d", "f", "g"];
+        let hunks = diff(&old, &new);
+        assert_eq!(hunks, vec![
+            Hunk { old_start: 1, old_count: 1, new_start: 1, new_count: 1 },
+            Hunk { old_start: 4, old_count: 1, new_start: 4, new_count: 0 },
+            Hunk { old_start: 6, old_count: 0, new_start: 5, new_count: 1 },
+        ]);
+        assert_eq!(hunks.iter().map(Hunk::line).collect::<Vec<_>>(), vec![1, 3, 5]);
+        let starts: Vec<usize> = hunks.iter().map(Hunk::line).collect();
+        assert_eq!(next_change(&starts, 1, &Direction::Forward), Some(3));
+        assert_eq!(next_change(&starts, 1, &Direction::Backward), None);
+        assert!(diff(&old, &old).is_empty());
+
+        // Applying the hunks must give the new text, with as few changes as the
+        // longest common subsequence allows.
+        let mut seed = 7u32;
+        let mut random = |len: usize| -> Vec<u8> {
+            (0..len).map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                b"abc"[(seed >> 16) as usize % 3]
+            }).collect()
+        };
+        for round in 0..200 {
+            let (old, new) = (random(round % 13), random(round % 7 + 3));
+            let hunks = diff(&old, &new);
+            let mut patched = old.clone();
+            for hunk in hunks.iter().rev() {
+                patched.splice(hunk.old_start..hunk.old_end(), new[hunk.new_start..hunk.new_end()].iter().copied());
+            }
+            assert_eq!(patched, new);
+            let changes: usize = hunks.iter().map(|hunk| hunk.old_count + hunk.new_count).sum();
+            assert_eq!(changes, old.len() + new.len() - 2 * lcs_length(&old, &new));
+        }
+    }
+
+    #[test]
+    fn shows_buffers_side_by_side() {
+        let lines = |text: &str| text.split(' ').map(|line| line.replace('_', " ")).collect::<Vec<String>>();
+        let views = diff_views(&[lines("two_words same"), lines("two_things same new")], 0);
+        assert_eq!(views[0].line(0), Some(&DiffLine::Changed(vec![(4, 8)])));
+        assert_eq!(views[1].line(0), Some(&DiffLine::Changed(vec![(4, 9)])));
+        assert_eq!(views[1].line(2), Some(&DiffLine::Added));
+        assert_eq!((views[0].fillers(2), views[0].line(1)), (1, None));
+        // Three texts line up around the middle one.
+        let views = diff_views(&[lines("a x c"), lines("a b c"), lines("a b c d")], 1);
+        assert_eq!(views.iter().map(|view| view.fillers(3)).collect::<Vec<_>>(), vec![1, 1, 0]);
+        assert_eq!(views.iter().map(|view| view.changes.clone()).collect::<Vec<_>>(), vec![vec![1, 2], vec![1, 2], vec![1, 3]]);
+
+        let dir = TempDir::new("diff");
+        let shared: String = (1..=20).map(|line| format!("line {}\n", line)).collect();
+        dir.write("a.txt", format!("{}one\ntwo words\nthree\nfour\nfive\n", shared));
+        dir.write("b.txt", format!("{}one\ntwo things\nthree\nfive\nsix\nseven\n", shared));
+
+        let mut harness = Harness::new(80, 12, "");
+        let right_of = |row: String| row.chars().skip(40).collect::<String>();
+        harness.keys(&format!(":e {}<CR>:diffthis<CR>", dir.join("a.txt").display()));
+        harness.keys(&format!("<C-w>l:e {}<CR>:diffthis<CR><C-w>h", dir.join("b.txt").display()));
+        // Both frames scroll together, with fillers where only the other has lines.
+        harness.keys("G");
+        assert!(harness.screen().row(0).starts_with("   16 line 16"));
+        assert!(right_of(harness.screen().row(0)).starts_with("   16 line 16"));
+        assert!(harness.screen().row(8).starts_with("   24 four"));
+        assert!(right_of(harness.screen().row(8)).trim_start().starts_with("-----"));
+        assert!(right_of(harness.screen().row(9)).starts_with("   24 five"));
+        harness.keys("<C-w>lG");
+        assert!(harness.screen().row(9).trim_start().starts_with("-----"));
+        assert!(right_of(harness.screen().row(9)).starts_with("   26 seven"));
+
+        harness.keys("gg]c");
+        assert_eq!(harness.cursor(), (21, 0));
+        // A change this side has no lines for starts on the line above it.
+        harness.keys("]c");
+        assert_eq!(harness.cursor(), (22, 0));
+        harness.keys("]c");
+        assert_eq!(harness.cursor(), (24, 0));
+        harness.keys("<C-w>hgg3]c");
+        assert_eq!(harness.cursor(), (24, 0));
+
+        harness.keys("[c[cdo");
+        assert!(harness.buffer_text().contains("\ntwo things\n"));
+        harness.keys("]cdp");
+        assert!(right_of(harness.screen().row(8)).starts_with("   24 four"));
+        assert!(harness.keys("]c]c").screen().row(11).contains("No more items"));
+        harness.keys(":diffoff!<CR>");
+        assert!(harness.keys("gg]c").screen().row(11).contains("No more items"));
+    }
+}
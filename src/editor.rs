@@ -0,0 +1,2603 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::io::Read;
+
+pub enum Mode {
+    Normal,
+    Command,
+    Insert,
+    Finder,
+}
+
+const PAGER_PROMPT: &str = "Press ENTER or type command to continue";
+const SWAP_PROMPT: &str = "[R]ecover, [O]pen read-only, [D]elete it, [E]dit anyway, [Q]uit: ";
+const FILE_CHANGED_PROMPT: &str = "[R]eload, [K]eep, [D]iff: ";
+const CONFIRM_PROMPT: &str = "[Y]es, [N]o: ";
+
+enum Prompt {
+    Swap { file: PathBuf, swap: SwapInfo },
+    FileChanged { file: PathBuf },
+    Delete { file: PathBuf },
+    Overwrite { file: PathBuf, source: PathBuf },
+}
+
+impl Prompt {
+    fn file(&self) -> &Path {
+        match self {
+            Prompt::Swap { file, .. } | Prompt::FileChanged { file } | Prompt::Delete { file } | Prompt::Overwrite { file, .. } => file,
+        }
+    }
+
+    fn lines(&self) -> Vec<String> {
+        match self {
+            Prompt::Swap { swap, .. } => swap.describe(),
+            Prompt::FileChanged { file } => vec![
+                format!("File \"{}\" has changed since editing started", file.display()),
+                String::from("and the buffer was changed in the editor as well"),
+            ],
+            Prompt::Delete { file } if file.is_dir() => {
+                vec![format!("Delete directory \"{}\" and everything in it?", file.display())]
+            },
+            Prompt::Delete { file } => vec![format!("Delete \"{}\"?", file.display())],
+            Prompt::Overwrite { file, source } => {
+                vec![format!("\"{}\" already exists, replace it with \"{}\"?", file.display(), source.display())]
+            },
+        }
+    }
+
+    fn footer(&self) -> &'static str {
+        match self {
+            Prompt::Swap { .. } => SWAP_PROMPT,
+            Prompt::FileChanged { .. } => FILE_CHANGED_PROMPT,
+            Prompt::Delete { .. } | Prompt::Overwrite { .. } => CONFIRM_PROMPT,
+        }
+    }
+}
+
+const MIN_FRAME_COLUMNS: usize = 20;
+const MIN_FRAME_LINES: usize = 3;
+
+struct HiddenBuffer {
+    buffer: Buffer,
+    position: CursorPosition,
+}
+
+struct CommandWindow {
+    frame_idx: usize,
+    buffer: Buffer,
+    position: CursorPosition,
+}
+
+pub struct Editor {
+    reader: Reader,
+    key_handler: KeyHandler,
+    frames: Vec<Frame>,
+    active_frame_idx: usize,
+    mode: Mode,
+    editor_contents: EditorContents,
+    command_line: CommandLine,
+    command_window: Option<CommandWindow>,
+    hidden: Vec<HiddenBuffer>,
+    clean: bool,
+    global_marks: HashMap<char, (PathBuf, CursorPosition)>,
+    messages: Messages,
+    pager: Option<Vec<Message>>,
+    prompt: Option<Prompt>,
+    keys_since_swap: usize,
+    servers: LanguageServers,
+    completion: Option<CompletionMenu>,
+    snippets: Snippets,
+    finder: Option<Finder>,
+    quickfix: QuickfixList,
+    grep: Option<GrepJob>,
+    make: Option<MakeJob>,
+    options: EditorOptions,
+    explorer: Option<Explorer>,
+    last_frame_idx: usize,
+    lines: usize,
+    columns: usize,
+}
+
+impl Editor {
+    pub fn new(args: Args, (columns, rows): (u16, u16)) -> Self {
+        let win_size = (columns as usize, rows as usize - 1);
+
+        let mut messages = Messages::default();
+        let mut buffers: Vec<(Buffer, Option<StartPosition>)> = args.files
+            .into_iter()
+            .map(|file| Self::open_file_arg(file, &mut messages))
+            .collect();
+        match buffers.first_mut() {
+            Some((_, position)) if args.position.is_some() => *position = args.position,
+            Some(_) => {},
+            None => buffers.push((Buffer::welcome(), None)),
+        }
+        if args.read_only {
+            buffers.iter_mut().for_each(|(buffer, _)| buffer.read_only = true);
+        }
+        if buffers[0].0.file_path.is_some() {
+            messages.info(buffers[0].0.file_info());
+        }
+        // Without an explicit split there are always two frames, both on the
+        // first file unless a second one was given.
+        let split = args.split.or(args.diff.then_some(Split::Vertical));
+        let frame_count = match split {
+            Some(Split::Vertical) => cmp::min(buffers.len(), cmp::max(win_size.0 / MIN_FRAME_COLUMNS, 1)),
+            Some(Split::Horizontal) => cmp::min(buffers.len(), cmp::max(win_size.1 / MIN_FRAME_LINES, 1)),
+            None => 2,
+        };
+        if buffers.len() < frame_count {
+            let copy = Self::copy_buffer(&buffers[0].0);
+            buffers.insert(1, (copy, None));
+        }
+
+        let mut hidden = vec![];
+        for (buffer, position) in buffers.drain(frame_count..) {
+            let position = position.map_or(Ok(CursorPosition::default()), |position| position.resolve(&buffer));
+            hidden.push(HiddenBuffer { position: position.unwrap_or_default(), buffer });
+        }
+        let layout = Self::layout(split.unwrap_or(Split::Vertical), frame_count, win_size.0, win_size.1);
+        let mut frames = vec![];
+        for ((start_column, start_line, columns, lines), (buffer, position)) in layout.into_iter().zip(buffers) {
+            let mut frame = Frame::new(start_column, start_line, columns, lines, buffer);
+            if let Some(position) = position {
+                match position.resolve(frame.buffer()) {
+                    Ok(position) => frame.move_to(position),
+                    Err(err) => messages.error(err),
+                }
+            }
+            if args.diff {
+                if let Err(err) = frame.start_diff() {
+                    messages.error(err);
+                }
+            }
+            frames.push(frame);
+        }
+
+        let (state, history) = if args.clean {
+            (SessionState::default(), CommandHistory::default())
+        } else {
+            (SessionState::load(), CommandHistory::load())
+        };
+
+        let (servers, server_events) = LanguageServers::new();
+        let mut editor = Self {
+            editor_contents: EditorContents::new(),
+            mode: Mode::Normal,
+            reader: Reader::new(server_events),
+            key_handler: KeyHandler::new(),
+            frames,
+            active_frame_idx: 0,
+            command_line: CommandLine::new(history),
+            command_window: None,
+            hidden,
+            clean: args.clean,
+            global_marks: state.global_marks
+                .into_iter()
+                .map(|(name, file, position)| (name, (file, position)))
+                .collect(),
+            messages,
+            pager: None,
+            prompt: None,
+            keys_since_swap: 0,
+            servers,
+            completion: None,
+            snippets: Snippets::default(),
+            finder: None,
+            quickfix: QuickfixList::default(),
+            grep: None,
+            make: None,
+            options: EditorOptions::default(),
+            explorer: None,
+            last_frame_idx: 0,
+            columns: win_size.0,
+            lines: win_size.1,
+        };
+        editor.frames[0].load_jumps(state.jumps);
+        (0..editor.frames.len()).for_each(|idx| editor.restore_global_marks(idx));
+        editor
+    }
+
+    fn open_file_arg(file: FileArg, messages: &mut Messages) -> (Buffer, Option<StartPosition>) {
+        match file {
+            FileArg::Path(path, position) => {
+                let buffer = Buffer::open(path).unwrap_or_else(|err| {
+                    messages.error(err);
+                    Buffer::welcome()
+                });
+                (buffer, position)
+            },
+            FileArg::Stdin => {
+                let mut bytes = vec![];
+                if let Err(err) = io::stdin().read_to_end(&mut bytes) {
+                    messages.error(EditorError::Io(PathBuf::from("-"), err));
+                }
+                (Buffer::from_bytes("[stdin]", bytes), None)
+            },
+        }
+    }
+
+    fn copy_buffer(buffer: &Buffer) -> Buffer {
+        let mut copy = match &buffer.file_path {
+            Some(file) => Buffer::open(file.clone()).unwrap_or_else(|_| Buffer::welcome()),
+            None if buffer.name.is_some() => {
+                let lines: Vec<String> = buffer.text_lines().map(String::from).collect();
+                Buffer::scratch(&buffer.display_name(), &lines)
+            },
+            None => Buffer::welcome(),
+        };
+        copy.read_only = buffer.read_only;
+        copy
+    }
+
+    // Each frame gets (start_column, start_line, columns, text lines); the row
+    // below the text lines holds its status bar.
+    fn layout(split: Split, count: usize, columns: usize, lines: usize) -> Vec<(usize, usize, usize, usize)> {
+        (0..count)
+            .map(|idx| match split {
+                Split::Vertical => {
+                    let start = idx * (columns / count);
+                    let width = if idx + 1 == count { columns - start } else { columns / count };
+                    (start, 0, width, lines - 1)
+                },
+                Split::Horizontal => {
+                    let start = idx * (lines / count);
+                    let height = if idx + 1 == count { lines - start } else { lines / count };
+                    (0, start, columns, height - 1)
+                },
+            })
+            .collect()
+    }
+
+    pub fn run_startup_commands(&mut self, commands: &[String]) -> bool {
+        for command in commands {
+            match self.execute_command(command) {
+                Ok(true) => {},
+                Ok(false) => return false,
+                Err(err) => self.messages.error(err),
+            }
+        }
+        true
+    }
+
+    fn stash_global_marks(&mut self, idx: usize) {
+        let frame = &mut self.frames[idx];
+        if let Some(file) = frame.file_path().map(|file| canonical_path(file)) {
+            for (name, position) in frame.buffer().marks.global() {
+                self.global_marks.insert(name, (file.clone(), position));
+            }
+        }
+    }
+
+    fn restore_global_marks(&mut self, idx: usize) {
+        let frame = &mut self.frames[idx];
+        if let Some(file) = frame.file_path().map(|file| canonical_path(file)) {
+            let names: Vec<char> = self.global_marks.iter()
+                .filter(|(_, (mark_file, _))| *mark_file == file)
+                .map(|(name, _)| *name)
+                .collect();
+            for name in names {
+                if let Some((_, position)) = self.global_marks.remove(&name) {
+                    frame.buffer().marks.set(name, position);
+                }
+            }
+        }
+    }
+
+    fn set_mark(&mut self, name: char) -> EditorResult<()> {
+        if is_global_mark(name) {
+            self.global_marks.remove(&name);
+            self.frames.iter_mut().for_each(|frame| frame.buffer().marks.remove(name));
+        }
+        self.active_frame().set_mark(name)
+    }
+
+    fn jump_to_mark(&mut self, name: char, linewise: bool) -> EditorResult<()> {
+        if !is_global_mark(name) || self.active_frame().mark(name).is_some() {
+            return self.active_frame().jump_to_mark(name, linewise);
+        }
+
+        let target = self.frames.iter()
+            .find_map(|frame| Some((frame.file_path()?.clone(), frame.mark(name)?)))
+            .or_else(|| self.global_marks.get(&name).cloned());
+        let (file, position) = target.ok_or(EditorError::MarkNotSet(name))?;
+        self.stash_global_marks(self.active_frame_idx);
+        let result = self.active_frame().jump_to_file(file, position, linewise);
+        self.restore_global_marks(self.active_frame_idx);
+        result
+    }
+
+    fn edit(&mut self, file: PathBuf, encoding: Option<FileEncoding>) -> EditorResult<()> {
+        self.stash_global_marks(self.active_frame_idx);
+        self.active_frame().edit(file, encoding)?;
+        self.restore_global_marks(self.active_frame_idx);
+        let info = self.active_frame().buffer().file_info();
+        self.messages.info(info);
+        Ok(())
+    }
+
+    pub fn save_state(&mut self) -> io::Result<()> {
+        if self.clean {
+            return Ok(());
+        }
+        // The active frame goes last so its marks win over stale copies.
+        let mut order: Vec<usize> = (0..self.frames.len()).filter(|idx| *idx != self.active_frame_idx).collect();
+        order.push(self.active_frame_idx);
+        order.into_iter().for_each(|idx| self.stash_global_marks(idx));
+
+        let mut global_marks: Vec<(char, PathBuf, CursorPosition)> = self.global_marks.iter()
+            .map(|(name, (file, position))| (*name, file.clone(), *position))
+            .collect();
+        global_marks.sort_by_key(|(name, _, _)| *name);
+
+        let jumps = self.active_frame().jumps()
+            .iter()
+            .map(|jump| Jump { file: jump.file.as_deref().map(canonical_path), position: jump.position })
+            .collect();
+
+        self.command_line.history.save()?;
+        SessionState { global_marks, jumps }.save()
+    }
+
+    pub fn active_frame(&mut self) -> &mut Frame {
+        &mut self.frames[self.active_frame_idx]
+    }
+
+    pub fn execute_command(&mut self, command: &str) -> EditorResult<bool> {
+        let (name, args) = command.trim().split_once(' ').unwrap_or((command.trim(), ""));
+        let args = args.trim();
+        match name {
+            "" => {},
+            "q" | "quit" if self.in_command_window() => self.close_command_window(),
+            "q" | "quit" if self.quickfix_window() == Some(self.active_frame_idx) => self.close_frame(self.active_frame_idx)?,
+            "q" | "quit" if self.explorer_window() == Some(self.active_frame_idx) => self.close_explorer()?,
+            "q" | "quit" if self.blame_source(self.active_frame_idx).is_some() => self.close_frame(self.active_frame_idx)?,
+            "q" | "quit" if self.diff_side(self.active_frame_idx) => self.close_frame(self.active_frame_idx)?,
+            "q" | "quit" => return Ok(false),
+            "e" | "edit" | "e!" | "edit!" => {
+                let (encoding, file) = match args.strip_prefix("++enc=") {
+                    Some(rest) => {
+                        let (encoding, file) = rest.split_once(' ').unwrap_or((rest, ""));
+                        (Some(encoding.parse()?), file.trim())
+                    },
+                    None => (None, args),
+                };
+                let force = name.ends_with('!');
+                if !force && self.active_frame().buffer().modified {
+                    return Err(EditorError::NoWriteSinceLastChange);
+                }
+
+                if file.is_empty() {
+                    if !force && encoding.is_none() {
+                        return Err(EditorError::NoFileName);
+                    }
+                    self.active_frame().reload(encoding)?;
+                    let info = self.active_frame().buffer().file_info();
+                    self.messages.info(info);
+                } else {
+                    self.edit(PathBuf::from(file), encoding)?;
+                }
+            },
+            "vie" | "view" if args.is_empty() => self.active_frame().buffer().read_only = true,
+            "vie" | "view" => {
+                if self.active_frame().buffer().modified {
+                    return Err(EditorError::NoWriteSinceLastChange);
+                }
+                self.edit(PathBuf::from(args), None)?;
+                self.active_frame().buffer().read_only = true;
+            },
+            "sp" | "split" | "vs" | "vsplit" => {
+                let split = if name.starts_with('s') { Split::Horizontal } else { Split::Vertical };
+                let buffer = if args.is_empty() {
+                    Self::copy_buffer(self.active_frame().buffer())
+                } else {
+                    Buffer::open_with(PathBuf::from(args), None, self.active_frame().options.largefile)?
+                };
+                self.split_frame(split, buffer, false)?;
+            },
+            "Files" | "files" => self.open_finder(args)?,
+            "Ex" | "Explore" => self.execute_explore_command(args)?,
+            "Gblame" => self.open_blame()?,
+            "Gmerge" => self.open_merge()?,
+            "diffthis" => self.active_frame().start_diff()?,
+            "diffoff" => self.active_frame().stop_diff(),
+            "diffoff!" => self.frames.iter_mut().for_each(Frame::stop_diff),
+            "diffget" | "diffput" => {
+                let count = match args {
+                    "" => None,
+                    count => Some(count.parse().map_err(|_| EditorError::InvalidArgument(count.to_string()))?),
+                };
+                self.copy_diff_change(count, name == "diffput")?;
+            },
+            "Conflict" => self.resolve_conflict(args.parse()?)?,
+            "Ghunk" => {
+                let line = self.active_frame().cursor_position().line;
+                let buffer = self.active_frame().buffer();
+                let git = buffer.git.as_ref().ok_or(EditorError::NotInRepository)?;
+                match args {
+                    "stage" => {
+                        git.stage_hunk(buffer, line)?;
+                        self.messages.info("Hunk staged");
+                    },
+                    "reset" => {
+                        let hunk = *git.hunk_at(line).ok_or(EditorError::NoHunk)?;
+                        let lines = git.head_lines(&hunk).to_vec();
+                        buffer.check_changeable()?;
+                        self.active_frame().replace_lines(hunk.new_start, hunk.new_count, &lines);
+                    },
+                    _ => return Err(EditorError::InvalidArgument(args.to_string())),
+                }
+            },
+            "gr" | "grep" => {
+                let (pattern, paths) = grep_args(args).ok_or_else(|| EditorError::InvalidArgument(args.to_string()))?;
+                // A new search replaces one still running, which stops when dropped.
+                self.grep = Some(GrepJob::start(pattern, paths)?);
+                self.quickfix = QuickfixList::new(format!(":{} {}", name, args), vec![]);
+                self.refresh_quickfix_window();
+                self.messages.info(format!("Searching for {}", pattern));
+            },
+            "mak" | "make" if self.make.is_some() => return Err(EditorError::BuildRunning),
+            "mak" | "make" => self.start_make(args)?,
+            "cope" | "copen" => self.open_quickfix_window()?,
+            "ccl" | "cclose" => {
+                if let Some(idx) = self.quickfix_window() {
+                    self.close_frame(idx)?;
+                }
+            },
+            "cn" | "cnext" => {
+                self.quickfix.step(Direction::Forward)?;
+                self.jump_to_quickfix()?;
+            },
+            "cp" | "cprevious" | "cN" | "cNext" => {
+                self.quickfix.step(Direction::Backward)?;
+                self.jump_to_quickfix()?;
+            },
+            "cc" => {
+                if !args.is_empty() {
+                    let number = args.parse().map_err(|_| EditorError::InvalidArgument(args.to_string()))?;
+                    self.quickfix.select(number)?;
+                }
+                self.jump_to_quickfix()?;
+            },
+            "w" | "write" | "w!" | "write!" => {
+                let file = (!args.is_empty()).then(|| PathBuf::from(args));
+                let info = self.active_frame().buffer().write(file, name.ends_with('!'))?;
+                self.messages.info(info);
+            },
+            "checkt" | "checktime" => self.check_files(None),
+            "hex" => self.active_frame().toggle_hex()?,
+            "lsp" => self.execute_lsp_command(args)?,
+            "snippet" | "snip" if args.is_empty() => {
+                let lines = self.snippets.listing();
+                self.pager = Some(lines.into_iter().map(|text| Message { level: MessageLevel::Info, text }).collect());
+            },
+            "snippet" | "snip" => self.snippets.add(args)?,
+            "b" | "buffer" => {
+                let exact = |name: String| name == args;
+                let frame = self.frames.iter().position(|frame| exact(frame.buffer_name()))
+                    .or_else(|| self.frames.iter().position(|frame| frame.buffer_name().contains(args)));
+                let hidden = self.hidden.iter().position(|hidden| exact(hidden.buffer.display_name()))
+                    .or_else(|| self.hidden.iter().position(|hidden| hidden.buffer.display_name().contains(args)));
+                match (frame, hidden) {
+                    (Some(idx), _) => self.active_frame_idx = idx,
+                    (None, Some(idx)) => self.show_hidden(idx),
+                    (None, None) => return Err(EditorError::NoMatchingBuffer(args.to_string())),
+                }
+                if self.buffer_names().iter().filter(|name| name.contains(args)).count() > 1 {
+                    self.messages.warn(format!("More than one match for {}", args));
+                }
+            },
+            "ls" | "buffers" => {
+                let mut listing = vec![];
+                for (idx, frame) in self.frames.iter_mut().enumerate() {
+                    let flags = if idx == self.active_frame_idx { "%a" } else { " a" };
+                    let line = frame.cursor_position().line + 1;
+                    listing.push(Self::buffer_listing(flags, frame.buffer(), line));
+                }
+                for hidden in &self.hidden {
+                    listing.push(Self::buffer_listing(" h", &hidden.buffer, hidden.position.line + 1));
+                }
+                self.pager = Some(listing);
+            },
+            "set" | "se" => {
+                for arg in split_unescaped(args, ' ').iter().filter(|arg| !arg.is_empty()) {
+                    match self.options.set(arg) {
+                        Err(EditorError::UnknownOption(_)) => self.active_frame().set_option(arg)?,
+                        result => result?,
+                    }
+                }
+            },
+            "sign" => {
+                let parts: Vec<&str> = args.split_whitespace().collect();
+                let signs = self.active_frame().signs();
+                match parts[..] {
+                    ["place", line, text] => match line.parse::<usize>() {
+                        Ok(line @ 1..) => signs.place(line - 1, Sign::new(text, USER_SIGN_GROUP)),
+                        _ => return Err(EditorError::InvalidArgument(line.to_string())),
+                    },
+                    ["unplace", line] => match line.parse::<usize>() {
+                        Ok(line @ 1..) => signs.unplace(line - 1, USER_SIGN_GROUP),
+                        _ => return Err(EditorError::InvalidArgument(line.to_string())),
+                    },
+                    ["clear"] => signs.clear_group(USER_SIGN_GROUP),
+                    _ => return Err(EditorError::InvalidArgument(args.to_string())),
+                }
+            },
+            "sw" | "swapname" => {
+                let swap = self.active_frame().buffer().swap.as_ref().map(|swap| swap.path().display().to_string());
+                self.messages.info(swap.unwrap_or_else(|| String::from("No swap file")));
+            },
+            "mes" | "messages" if args == "clear" => self.messages.clear_history(),
+            "mes" | "messages" => self.pager = Some(self.messages.history().to_vec()),
+            _ => return Err(EditorError::UnknownCommand(name.to_string())),
+        }
+
+        Ok(true)
+    }
+
+    fn buffer_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.frames.iter().map(|frame| frame.buffer_name()).collect();
+        names.extend(self.hidden.iter().map(|hidden| hidden.buffer.display_name()));
+        names.dedup();
+        names
+    }
+
+    fn buffer_listing(flags: &str, buffer: &Buffer, line: usize) -> Message {
+        let modified = if buffer.modified { " +" } else { "" };
+        Message {
+            level: MessageLevel::Info,
+            text: format!("{}{} \"{}\" line {}", flags, modified, buffer.display_name(), line),
+        }
+    }
+
+    fn show_hidden(&mut self, idx: usize) {
+        let hidden = self.hidden.remove(idx);
+        self.stash_global_marks(self.active_frame_idx);
+        let position = self.active_frame().cursor_position();
+        let buffer = self.active_frame().replace_buffer(hidden.buffer);
+        self.hidden.push(HiddenBuffer { buffer, position });
+        self.restore_global_marks(self.active_frame_idx);
+        self.active_frame().move_to(hidden.position);
+        let info = self.active_frame().buffer().file_info();
+        self.messages.info(info);
+    }
+
+    // The nearest frame whose start lies in the given direction and shares
+    // the current frame's row or column.
+    fn neighbour_frame(&self, direction: FrameDirection) -> Option<usize> {
+        let current = &self.frames[self.active_frame_idx].size;
+        self.frames.iter()
+            .enumerate()
+            .filter(|(_, frame)| match direction {
+                FrameDirection::Left => frame.size.start_column < current.start_column && frame.size.start_line == current.start_line,
+                FrameDirection::Right => frame.size.start_column > current.start_column && frame.size.start_line == current.start_line,
+                FrameDirection::Up => frame.size.start_line < current.start_line && frame.size.start_column == current.start_column,
+                FrameDirection::Down => frame.size.start_line > current.start_line && frame.size.start_column == current.start_column,
+            })
+            .min_by_key(|(_, frame)| {
+                frame.size.start_column.abs_diff(current.start_column) + frame.size.start_line.abs_diff(current.start_line)
+            })
+            .map(|(idx, _)| idx)
+    }
+
+    // Halves the active frame and shows `buffer` in a new frame on the top or
+    // left half, or on the other half when `after` is set. The new frame
+    // becomes the active one.
+    fn split_frame(&mut self, split: Split, buffer: Buffer, after: bool) -> EditorResult<()> {
+        let size = &self.frames[self.active_frame_idx].size;
+        let (start_column, start_line, columns, rows) = (size.start_column, size.start_line, size.columns, size.lines + 1);
+        let (first, second) = match split {
+            Split::Vertical if columns >= 2 * MIN_FRAME_COLUMNS => {
+                let width = columns / 2;
+                ((start_column, start_line, width, rows - 1), (start_column + width, start_line, columns - width, rows - 1))
+            },
+            Split::Horizontal if rows >= 2 * MIN_FRAME_LINES => {
+                let height = rows / 2;
+                ((start_column, start_line, columns, height - 1), (start_column, start_line + height, columns, rows - height - 1))
+            },
+            _ => return Err(EditorError::NoRoom),
+        };
+
+        let (kept, (start_column, start_line, columns, lines)) = if after { (first, second) } else { (second, first) };
+        let idx = self.active_frame_idx + usize::from(after);
+        self.active_frame().resize(kept.0, kept.1, kept.2, kept.3);
+        self.frames.insert(idx, Frame::new(start_column, start_line, columns, lines, buffer));
+        if let Some(window) = self.command_window.as_mut().filter(|window| window.frame_idx >= idx) {
+            window.frame_idx += 1;
+        }
+        self.active_frame_idx = idx;
+        self.restore_global_marks(idx);
+        Ok(())
+    }
+
+    // Gives a frame's area to a neighbour that shares a whole edge with it,
+    // keeping its buffer hidden if it belongs to a file.
+    fn close_frame(&mut self, idx: usize) -> EditorResult<()> {
+        let closed = &self.frames[idx].size;
+        let (start_column, start_line, columns, rows) = (closed.start_column, closed.start_line, closed.columns, closed.lines + 1);
+        let (neighbour, merged) = self.frames.iter()
+            .enumerate()
+            .filter(|(other, _)| *other != idx)
+            .find_map(|(other, frame)| {
+                let size = &frame.size;
+                let other_rows = size.lines + 1;
+                let stacked = size.start_column == start_column && size.columns == columns
+                    && (size.start_line + other_rows == start_line || start_line + rows == size.start_line);
+                let beside = size.start_line == start_line && other_rows == rows
+                    && (size.start_column + size.columns == start_column || start_column + columns == size.start_column);
+                if stacked {
+                    Some((other, (start_column, cmp::min(start_line, size.start_line), columns, rows + other_rows - 1)))
+                } else if beside {
+                    Some((other, (cmp::min(start_column, size.start_column), start_line, columns + size.columns, rows - 1)))
+                } else {
+                    None
+                }
+            })
+            .ok_or(EditorError::CloseLastFrame)?;
+
+        self.stash_global_marks(idx);
+        let mut frame = self.frames.remove(idx);
+        let position = frame.cursor_position();
+        let buffer = frame.replace_buffer(Buffer::welcome());
+        if buffer.file_path.is_some() {
+            self.hidden.push(HiddenBuffer { buffer, position });
+        }
+        let neighbour = if neighbour > idx { neighbour - 1 } else { neighbour };
+        self.frames[neighbour].resize(merged.0, merged.1, merged.2, merged.3);
+        if self.active_frame_idx == idx {
+            self.active_frame_idx = neighbour;
+        } else if self.active_frame_idx > idx {
+            self.active_frame_idx -= 1;
+        }
+        if let Some(window) = self.command_window.as_mut().filter(|window| window.frame_idx > idx) {
+            window.frame_idx -= 1;
+        }
+        Ok(())
+    }
+
+    fn quickfix_window(&self) -> Option<usize> {
+        self.frames.iter().position(|frame| frame.buffer_name().starts_with(QUICKFIX_NAME))
+    }
+
+    fn quickfix_buffer(&self) -> Buffer {
+        // The status bar shows the command that filled the list, as in "[Quickfix List] :grep foo".
+        let name = format!("{} {}", QUICKFIX_NAME, self.quickfix.title);
+        let mut buffer = Buffer::scratch(name.trim_end(), &self.quickfix.lines());
+        buffer.modifiable = false;
+        buffer
+    }
+
+    // Opens the quickfix window below the active frame, or moves to it if it is already open.
+    fn open_quickfix_window(&mut self) -> EditorResult<()> {
+        match self.quickfix_window() {
+            Some(idx) => self.active_frame_idx = idx,
+            None => self.split_frame(Split::Horizontal, self.quickfix_buffer(), true)?,
+        }
+        let line = self.quickfix.current;
+        self.active_frame().move_to(CursorPosition { line, column: 0 });
+        Ok(())
+    }
+
+    fn refresh_quickfix_window(&mut self) {
+        if let Some(idx) = self.quickfix_window() {
+            let buffer = self.quickfix_buffer();
+            self.frames[idx].replace_buffer(buffer);
+            self.frames[idx].move_to(CursorPosition { line: self.quickfix.current, column: 0 });
+        }
+    }
+
+    // Adds the matches found so far to the quickfix list, jumping to the
+    // first one as soon as it comes in.
+    fn update_grep(&mut self) {
+        let job = match self.grep.as_mut() {
+            Some(job) => job,
+            None => return,
+        };
+        let finished = job.finished();
+        let entries = job.update();
+        let first = self.quickfix.entries.is_empty() && !entries.is_empty();
+        if !entries.is_empty() {
+            self.quickfix.extend(entries);
+            self.refresh_quickfix_window();
+        }
+        let result = if first {
+            self.jump_to_quickfix()
+        } else if finished && self.quickfix.entries.is_empty() {
+            Err(EditorError::PatternNotFound(self.grep.as_ref().unwrap().pattern.clone()))
+        } else {
+            Ok(())
+        };
+        if finished {
+            self.grep = None;
+        }
+        if let Err(err) = result {
+            self.messages.error(err);
+        }
+    }
+
+    fn make_window(&self) -> Option<usize> {
+        self.frames.iter().position(|frame| frame.buffer_name() == MAKE_OUTPUT_NAME)
+    }
+
+    // Runs 'makeprg' in the background with its output shown in a window
+    // below the active frame, which keeps the focus.
+    fn start_make(&mut self, args: &str) -> EditorResult<()> {
+        let command = format!("{} {}", self.options.makeprg, args).trim().to_string();
+        let job = MakeJob::start(command.clone())?;
+        let mut output = Buffer::scratch(MAKE_OUTPUT_NAME, &[format!("$ {}", command)]);
+        output.modifiable = false;
+        match self.make_window() {
+            Some(idx) => {
+                self.frames[idx].replace_buffer(output);
+            },
+            None => {
+                let active = self.active_frame_idx;
+                self.split_frame(Split::Horizontal, output, true)?;
+                self.active_frame_idx = active;
+            },
+        }
+        self.make = Some(job);
+        self.messages.info(format!(":!{}", command));
+        Ok(())
+    }
+
+    // Streams new build output into its window and, once the build is over,
+    // fills the quickfix list from it and jumps to the first error.
+    fn update_make(&mut self) {
+        let job = match self.make.as_mut() {
+            Some(job) => job,
+            None => return,
+        };
+        let changed = job.update();
+        let status = job.finished();
+        if let (true, Some(idx)) = (changed, self.make_window()) {
+            let job = self.make.as_ref().unwrap();
+            let mut lines = vec![format!("$ {}", job.command)];
+            lines.extend(job.output.iter().cloned());
+            let mut output = Buffer::scratch(MAKE_OUTPUT_NAME, &lines);
+            output.modifiable = false;
+            let frame = &mut self.frames[idx];
+            frame.replace_buffer(output);
+            frame.move_to(CursorPosition { line: lines.len() - 1, column: 0 });
+        }
+        let code = match status {
+            Some(code) => code,
+            None => return,
+        };
+
+        let job = self.make.take().unwrap();
+        let entries = parse_errors(&job.output.join("\n"), &self.options.errorformat);
+        let mut list = QuickfixList::new(format!(":make {}", job.command), entries);
+        list.current = list.entries.iter().position(|entry| entry.text.starts_with("error")).unwrap_or(0);
+        let found = !list.entries.is_empty();
+        self.quickfix = list;
+        self.refresh_quickfix_window();
+        let result = match code {
+            _ if found => self.jump_to_quickfix(),
+            Some(0) => {
+                self.messages.info("Build finished");
+                Ok(())
+            },
+            Some(code) => Err(EditorError::BuildFailed(code.to_string())),
+            None => Err(EditorError::BuildFailed(String::from("killed"))),
+        };
+        if let Err(err) = result {
+            self.messages.error(err);
+        }
+    }
+
+    // Shows the current entry in the frame the quickfix or make output
+    // window was opened from.
+    fn jump_to_quickfix(&mut self) -> EditorResult<()> {
+        let entry = self.quickfix.current().cloned().ok_or(EditorError::NoErrors)?;
+        let windows = [self.quickfix_window(), self.make_window()];
+        if windows.contains(&Some(self.active_frame_idx)) {
+            let current = self.active_frame_idx;
+            self.active_frame_idx = self.neighbour_frame(FrameDirection::Up)
+                .or_else(|| (0..self.frames.len()).find(|idx| !windows.contains(&Some(*idx))))
+                .unwrap_or(current);
+        }
+
+        self.active_frame().remember_jump();
+        self.show_file(&entry.file)?;
+        let frame = self.active_frame();
+        frame.move_to(CursorPosition { line: entry.line, column: 0 });
+        let text = frame.current_line_text().unwrap_or("");
+        let column = text.char_indices().nth(entry.column).map_or(text.len(), |(idx, _)| idx);
+        frame.move_to(CursorPosition { line: entry.line, column });
+
+        if let Some(window) = self.quickfix_window() {
+            self.frames[window].move_to(CursorPosition { line: self.quickfix.current, column: 0 });
+        }
+        let (current, total) = (self.quickfix.current + 1, self.quickfix.entries.len());
+        self.messages.info(format!("({} of {}) {}", current, total, entry.text));
+        Ok(())
+    }
+
+    fn open_finder(&mut self, dir: &str) -> EditorResult<()> {
+        let root = if dir.is_empty() { PathBuf::from(".") } else { PathBuf::from(dir) };
+        if let Err(err) = fs::read_dir(&root) {
+            return Err(EditorError::Io(root, err));
+        }
+        self.finder = Some(Finder::new(root));
+        self.mode = Mode::Finder;
+        self.messages.dismiss();
+        Ok(())
+    }
+
+    fn open_finder_selection(&mut self, split: Option<Split>) -> EditorResult<()> {
+        let file = match self.finder.take().and_then(|finder| finder.selection()) {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        self.mode = Mode::Normal;
+        let file = file.strip_prefix(".").map(Path::to_path_buf).unwrap_or(file);
+        match split {
+            Some(split) => {
+                let buffer = Buffer::open_with(file, None, self.active_frame().options.largefile)?;
+                self.split_frame(split, buffer, false)?;
+                let info = self.active_frame().buffer().file_info();
+                self.messages.info(info);
+            },
+            None => {
+                self.active_frame().remember_jump();
+                self.show_file(&file)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn explorer_window(&self) -> Option<usize> {
+        self.frames.iter().position(|frame| frame.buffer_name() == EXPLORER_NAME)
+    }
+
+    fn explorer_buffer(explorer: &Explorer) -> Buffer {
+        let mut buffer = Buffer::scratch(EXPLORER_NAME, &explorer.lines());
+        buffer.modifiable = false;
+        buffer
+    }
+
+    // Moves the left edge of every frame's area from `old_start` to
+    // `new_start`, scaling the widths so frames that shared an edge still do.
+    fn rescale_frames(&mut self, old_start: usize, new_start: usize) -> EditorResult<()> {
+        let columns = self.columns;
+        let scale = |column: usize| new_start + (column - old_start) * (columns - new_start) / (columns - old_start);
+        let sizes: Vec<(usize, usize)> = self.frames
+            .iter()
+            .map(|frame| (scale(frame.size.start_column), scale(frame.size.start_column + frame.size.columns)))
+            .collect();
+        if sizes.iter().any(|(start, end)| end - start < MIN_FRAME_COLUMNS / 2) {
+            return Err(EditorError::NoRoom);
+        }
+        for (frame, (start, end)) in self.frames.iter_mut().zip(sizes) {
+            let (start_line, lines) = (frame.size.start_line, frame.size.lines);
+            frame.resize(start, start_line, end - start, lines);
+        }
+        Ok(())
+    }
+
+    // Opens the explorer in a column on the left of the screen, or moves to
+    // it if it is already open.
+    fn open_explorer(&mut self, dir: &str) -> EditorResult<()> {
+        let root = Path::new(if dir.is_empty() { "." } else { dir });
+        if let Some(idx) = self.explorer_window() {
+            if !dir.is_empty() || self.explorer.is_none() {
+                self.explorer = Some(Explorer::new(root)?);
+                self.refresh_explorer_window(Some(0));
+            }
+            self.active_frame_idx = idx;
+            return Ok(());
+        }
+
+        let explorer = Explorer::new(root)?;
+        if self.columns < EXPLORER_WIDTH + MIN_FRAME_COLUMNS {
+            return Err(EditorError::NoRoom);
+        }
+        self.rescale_frames(0, EXPLORER_WIDTH)?;
+        let mut frame = Frame::new(0, 0, EXPLORER_WIDTH, self.lines - 1, Self::explorer_buffer(&explorer));
+        frame.set_option("nonumber")?;
+        self.frames.insert(0, frame);
+        if let Some(window) = self.command_window.as_mut() {
+            window.frame_idx += 1;
+        }
+        self.last_frame_idx = self.active_frame_idx + 1;
+        self.active_frame_idx = 0;
+        self.explorer = Some(explorer);
+        Ok(())
+    }
+
+    // Gives the explorer's column back to the other frames.
+    fn close_explorer(&mut self) -> EditorResult<()> {
+        let idx = match self.explorer_window() {
+            Some(idx) => idx,
+            None => return Ok(()),
+        };
+        self.explorer = None;
+        let size = &self.frames[idx].size;
+        if (size.start_column, size.start_line, size.columns, size.lines) != (0, 0, EXPLORER_WIDTH, self.lines - 1) {
+            return self.close_frame(idx);
+        }
+        if self.frames.len() == 1 {
+            return Err(EditorError::CloseLastFrame);
+        }
+
+        self.frames.remove(idx);
+        for frame_idx in [&mut self.active_frame_idx, &mut self.last_frame_idx] {
+            if *frame_idx > idx {
+                *frame_idx -= 1;
+            }
+        }
+        if self.active_frame_idx == idx {
+            self.active_frame_idx = cmp::min(self.last_frame_idx, self.frames.len() - 1);
+        }
+        if let Some(window) = self.command_window.as_mut().filter(|window| window.frame_idx > idx) {
+            window.frame_idx -= 1;
+        }
+        self.rescale_frames(EXPLORER_WIDTH, 0)
+    }
+
+    // Renders the tree again, keeping the cursor on `row` or where it was.
+    fn refresh_explorer_window(&mut self, row: Option<usize>) {
+        let (idx, explorer) = match (self.explorer_window(), self.explorer.as_ref()) {
+            (Some(idx), Some(explorer)) => (idx, explorer),
+            _ => return,
+        };
+        let frame = &mut self.frames[idx];
+        let line = row.unwrap_or_else(|| frame.cursor_position().line);
+        frame.replace_buffer(Self::explorer_buffer(explorer));
+        let last = frame.buffer().number_of_lines() - 1;
+        frame.move_to(CursorPosition { line: cmp::min(line, last), column: 0 });
+    }
+
+    // Moves the explorer's cursor onto `path`, expanding the directories above it.
+    fn reveal_in_explorer(&mut self, path: &Path) -> EditorResult<()> {
+        let explorer = match self.explorer.as_mut() {
+            Some(explorer) => explorer,
+            None => return Ok(()),
+        };
+        let row = explorer.reveal(path).ok_or_else(|| EditorError::OutsideExplorerRoot(path.to_path_buf()))?;
+        self.refresh_explorer_window(Some(row));
+        Ok(())
+    }
+
+    fn execute_explore_command(&mut self, args: &str) -> EditorResult<()> {
+        let (subcommand, rest) = args.split_once(' ').unwrap_or((args, ""));
+        let paths: Vec<String> = split_unescaped(rest.trim(), ' ').into_iter().filter(|path| !path.is_empty()).collect();
+        match (subcommand, &paths[..]) {
+            ("close", []) => self.close_explorer()?,
+            ("refresh", []) => {
+                if let Some(explorer) = self.explorer.as_mut() {
+                    explorer.refresh();
+                }
+                self.refresh_explorer_window(None);
+            },
+            ("reveal", []) => {
+                let window = self.explorer_window();
+                let frame_idx = if window == Some(self.active_frame_idx) { self.last_frame_idx } else { self.active_frame_idx };
+                let file = self.frames.get(frame_idx).and_then(Frame::file_path).cloned().ok_or(EditorError::NoFileName)?;
+                self.open_explorer("")?;
+                self.reveal_in_explorer(&file)?;
+            },
+            ("create", [path]) => self.create_path(path)?,
+            ("move", [source, destination]) => self.move_path(Path::new(source), Path::new(destination), false)?,
+            ("delete", [path]) => {
+                let file = PathBuf::from(path);
+                if let Err(err) = fs::symlink_metadata(&file) {
+                    return Err(EditorError::Io(file, err));
+                }
+                self.prompt = Some(Prompt::Delete { file });
+            },
+            _ if EXPLORE_SUBCOMMANDS.contains(&subcommand) => return Err(EditorError::InvalidArgument(args.to_string())),
+            _ => self.open_explorer(args)?,
+        }
+        Ok(())
+    }
+
+    fn explorer_action(&mut self, action: ExplorerAction) -> EditorResult<()> {
+        let row = self.active_frame().cursor_position().line;
+        let (path, is_dir) = match self.explorer.as_ref().and_then(|explorer| explorer.entry(row)) {
+            Some(entry) => (entry.path.clone(), entry.is_dir),
+            None => return Ok(()),
+        };
+        let explorer = self.explorer.as_mut().unwrap();
+        let name = escape_spaces(&relative_path(&path));
+        let dir = if is_dir { path.clone() } else { path.parent().map(Path::to_path_buf).unwrap_or_default() };
+        let dir = match escape_spaces(&relative_path(&dir)) {
+            dir if dir.is_empty() => dir,
+            dir => format!("{}/", dir),
+        };
+        let is_root = path == explorer.root;
+
+        match action {
+            ExplorerAction::Open if is_dir => {
+                explorer.toggle(row);
+                self.refresh_explorer_window(Some(row));
+            },
+            ExplorerAction::Open => {
+                let explorer_idx = self.active_frame_idx;
+                let target = match self.last_frame_idx {
+                    idx if idx < self.frames.len() && idx != explorer_idx => idx,
+                    _ => (0..self.frames.len()).find(|idx| *idx != explorer_idx).ok_or(EditorError::NoRoom)?,
+                };
+                self.active_frame_idx = target;
+                self.active_frame().remember_jump();
+                self.show_file(&relative_path(&path))?;
+            },
+            ExplorerAction::Collapse => {
+                let row = explorer.collapse(row);
+                self.refresh_explorer_window(Some(row));
+            },
+            ExplorerAction::Refresh => {
+                explorer.refresh();
+                self.refresh_explorer_window(None);
+            },
+            ExplorerAction::Close => self.close_explorer()?,
+            ExplorerAction::Create => self.prefill_command(format!("Explore create {}", dir)),
+            ExplorerAction::Rename | ExplorerAction::Move | ExplorerAction::Delete if is_root => {
+                return Err(EditorError::InvalidArgument(name));
+            },
+            ExplorerAction::Rename => self.prefill_command(format!("Explore move {} {}", name, name)),
+            ExplorerAction::Move => {
+                let parent = path.parent().map(|parent| escape_spaces(&relative_path(parent))).unwrap_or_default();
+                let parent = if parent.is_empty() { parent } else { format!("{}/", parent) };
+                self.prefill_command(format!("Explore move {} {}", name, parent));
+            },
+            ExplorerAction::Delete => self.prompt = Some(Prompt::Delete { file: relative_path(&path) }),
+        }
+        Ok(())
+    }
+
+    fn prefill_command(&mut self, text: String) {
+        self.mode = Mode::Command;
+        self.messages.dismiss();
+        self.command_line.prefill(text);
+    }
+
+    // Creates an empty file, or a directory when the name ends with a slash,
+    // along with any missing parent directories.
+    fn create_path(&mut self, name: &str) -> EditorResult<()> {
+        let path = PathBuf::from(name);
+        if fs::symlink_metadata(&path).is_ok() {
+            return Err(EditorError::FileExists(path));
+        }
+        let result = if name.ends_with('/') {
+            fs::create_dir_all(&path)
+        } else {
+            path.parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::File::create_new(&path).map(|_| ()))
+        };
+        result.map_err(|err| EditorError::Io(path.clone(), err))?;
+        self.messages.info(format!("\"{}\" created", path.display()));
+        if let Some(explorer) = self.explorer.as_mut() {
+            explorer.refresh();
+        }
+        self.reveal_in_explorer(&path)
+    }
+
+    // Renames or moves a file or directory, into `destination` when that is a
+    // directory. Buffers showing anything that moved follow it to the new path.
+    fn move_path(&mut self, source: &Path, destination: &Path, overwrite: bool) -> EditorResult<()> {
+        if let Err(err) = fs::symlink_metadata(source) {
+            return Err(EditorError::Io(source.to_path_buf(), err));
+        }
+        let into_dir = destination.is_dir() || destination.as_os_str().to_string_lossy().ends_with('/');
+        let destination = match source.file_name() {
+            Some(name) if into_dir => destination.join(name),
+            _ => destination.to_path_buf(),
+        };
+        if same_file(source, &destination) {
+            return Ok(());
+        }
+        if !overwrite && fs::symlink_metadata(&destination).is_ok() {
+            self.prompt = Some(Prompt::Overwrite { file: destination, source: source.to_path_buf() });
+            return Ok(());
+        }
+
+        let from = canonical_path(source);
+        let moved: Vec<Option<PathBuf>> = self.buffers_mut()
+            .iter()
+            .map(|buffer| {
+                let file = canonical_path(buffer.file_path.as_ref()?);
+                file.strip_prefix(&from).ok().map(Path::to_path_buf)
+            })
+            .collect();
+        destination.parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::rename(source, &destination))
+            .map_err(|err| EditorError::Io(destination.clone(), err))?;
+        let to = canonical_path(&destination);
+        for (buffer, rest) in self.buffers_mut().into_iter().zip(moved) {
+            match rest {
+                Some(rest) if rest.as_os_str().is_empty() => buffer.file_path = Some(relative_path(&to)),
+                Some(rest) => buffer.file_path = Some(relative_path(&to.join(rest))),
+                None => {},
+            }
+        }
+
+        self.messages.info(format!("\"{}\" moved to \"{}\"", source.display(), destination.display()));
+        if let Some(explorer) = self.explorer.as_mut() {
+            explorer.refresh();
+        }
+        self.reveal_in_explorer(&destination)
+    }
+
+    fn delete_path(&mut self, path: &Path) -> EditorResult<()> {
+        let result = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+        result.map_err(|err| EditorError::Io(path.to_path_buf(), err))?;
+        self.messages.info(format!("\"{}\" deleted", path.display()));
+        if let Some(explorer) = self.explorer.as_mut() {
+            explorer.refresh();
+        }
+        self.refresh_explorer_window(None);
+        Ok(())
+    }
+
+    fn jump_to_change(&mut self, starts: &[usize], direction: Direction, count: usize) -> EditorResult<()> {
+        let frame = self.active_frame();
+        let mut line = frame.cursor_position().line;
+        for _ in 0..count {
+            match next_change(starts, line, &direction) {
+                Some(next) => line = next,
+                None if line == frame.cursor_position().line => return Err(EditorError::NoMoreItems),
+                None => break,
+            }
+        }
+        frame.move_to(CursorPosition { line, column: 0 });
+        Ok(())
+    }
+
+    fn resolve_conflict(&mut self, choice: ConflictChoice) -> EditorResult<()> {
+        let line = self.active_frame().cursor_position().line;
+        let buffer = self.active_frame().buffer();
+        buffer.update_conflicts();
+        let conflict = *buffer.conflicts.iter().find(|conflict| conflict.contains_line(line)).ok_or(EditorError::NotInConflict)?;
+        let text: Vec<String> = buffer.text_lines().take(conflict.end + 1).map(String::from).collect();
+        let lines = conflict.resolve(&text, &choice)?;
+        buffer.check_changeable()?;
+        self.active_frame().replace_lines(conflict.start, conflict.end + 1 - conflict.start, &lines);
+        Ok(())
+    }
+
+    fn sync_conflicts(&mut self) {
+        self.buffers_mut().into_iter().for_each(Buffer::update_conflicts);
+    }
+
+    // Diffs changed buffers against HEAD again and updates their signs.
+    fn sync_git(&mut self) {
+        for buffer in self.buffers_mut() {
+            if !buffer.git_checked {
+                buffer.git_checked = true;
+                buffer.git = buffer.file_path.as_deref().filter(|_| !buffer.is_large()).and_then(GitFile::open);
+            }
+            let mut git = match buffer.git.take() {
+                Some(git) => git,
+                None => continue,
+            };
+            if git.update(buffer) {
+                buffer.signs.clear_group(GIT_SIGN_GROUP);
+                git.signs().into_iter().for_each(|(line, sign)| buffer.signs.place(line, sign));
+            }
+            buffer.git = Some(git);
+        }
+    }
+
+    // The frame a blame window annotates: the one on its right showing the same buffer.
+    fn blame_source(&self, idx: usize) -> Option<usize> {
+        let name = self.frames[idx].buffer_name();
+        let file = name.strip_prefix(BLAME_NAME)?.trim_start();
+        let size = &self.frames[idx].size;
+        self.frames.iter().position(|frame| {
+            frame.size.start_column == size.start_column + size.columns
+                && frame.size.start_line == size.start_line
+                && frame.buffer_name() == file
+        })
+    }
+
+    // Opens a window left of the active frame with the commit that last
+    // changed each line, as narrow as its text allows.
+    fn open_blame(&mut self) -> EditorResult<()> {
+        let source: &Buffer = self.active_frame().buffer();
+        let git = source.git.as_ref().ok_or(EditorError::NotInRepository)?;
+        let lines = git.blame(source)?;
+        let mut buffer = Buffer::scratch(&format!("{} {}", BLAME_NAME, source.display_name()), &lines);
+        buffer.modifiable = false;
+        let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) + 1;
+
+        if let Some(idx) = (0..self.frames.len()).find(|idx| self.blame_source(*idx) == Some(self.active_frame_idx)) {
+            self.close_frame(idx)?;
+        }
+        self.split_frame(Split::Vertical, buffer, false)?;
+        let idx = self.active_frame_idx;
+        let (blame, source) = (&self.frames[idx].size, &self.frames[idx + 1].size);
+        let (start_column, start_line, lines) = (blame.start_column, blame.start_line, blame.lines);
+        let total = blame.columns + source.columns;
+        let width = cmp::min(width, blame.columns);
+        self.frames[idx].resize(start_column, start_line, width, lines);
+        self.frames[idx].set_option("nonumber")?;
+        self.frames[idx + 1].resize(start_column + width, start_line, total - width, lines);
+        self.active_frame_idx = idx + 1;
+        self.align_blame_windows();
+        Ok(())
+    }
+
+    // Shows our and their versions of a conflicted file left and right of
+    // it, with all three in diff mode.
+    fn open_merge(&mut self) -> EditorResult<()> {
+        let name = self.active_frame().buffer_name();
+        let git = self.active_frame().buffer().git.as_ref().ok_or(EditorError::NotInRepository)?;
+        let (ours, theirs) = git.conflict()?;
+        let side = |label: &str, lines: &[String]| {
+            let mut buffer = Buffer::scratch(&format!("{} {}", label, name), lines);
+            buffer.modifiable = false;
+            buffer
+        };
+        let (ours, theirs) = (side(OURS_NAME, &ours), side(THEIRS_NAME, &theirs));
+
+        let size = &self.active_frame().size;
+        let (start_column, start_line, columns, lines) = (size.start_column, size.start_line, size.columns, size.lines);
+        if columns < 3 * MIN_FRAME_COLUMNS {
+            return Err(EditorError::NoRoom);
+        }
+        self.active_frame().start_diff()?;
+        self.split_frame(Split::Vertical, ours, false)?;
+        let idx = self.active_frame_idx;
+        let third = columns / 3;
+        self.frames[idx].resize(start_column, start_line, third, lines);
+        self.frames[idx + 1].resize(start_column + third, start_line, columns - third, lines);
+        self.active_frame_idx = idx + 1;
+        self.split_frame(Split::Vertical, theirs, true)?;
+        for side in [idx, idx + 2] {
+            self.frames[side].start_diff()?;
+        }
+        self.active_frame_idx = idx + 1;
+        Ok(())
+    }
+
+    // Frames that only show another version of a file to compare with.
+    fn diff_side(&self, idx: usize) -> bool {
+        let name = self.frames[idx].buffer_name();
+        [OURS_NAME, THEIRS_NAME, DISK_NAME].iter().any(|side| name.starts_with(side))
+    }
+
+    // Frames in diff mode, from left to right.
+    fn diff_frames(&self) -> Vec<usize> {
+        let mut frames: Vec<usize> = (0..self.frames.len()).filter(|idx| self.frames[*idx].diff().is_some()).collect();
+        frames.sort_by_key(|idx| (self.frames[*idx].size.start_column, self.frames[*idx].size.start_line));
+        frames
+    }
+
+    // Compares the frames in diff mode again when one of their buffers
+    // changed, and keeps them scrolled together with the active one. The
+    // middle frame is the one the others are compared with.
+    fn sync_diff(&mut self) {
+        let frames = self.diff_frames();
+        let stale = frames.iter().any(|idx| {
+            let tick = self.frames[*idx].buffer().change_tick;
+            self.frames[*idx].diff().is_some_and(|diff| diff.tick != Some(tick) || diff.sides != frames.len())
+        });
+        if stale {
+            let texts: Vec<Vec<String>> = frames.iter()
+                .map(|idx| self.frames[*idx].buffer().text_lines().map(String::from).collect())
+                .collect();
+            for (idx, mut view) in frames.iter().zip(diff_views(&texts, (frames.len() - 1) / 2)) {
+                view.tick = Some(self.frames[*idx].buffer().change_tick);
+                self.frames[*idx].set_diff(view);
+            }
+        }
+
+        let active = self.active_frame_idx;
+        if let Some((top, cursor)) = self.frames[active].diff_rows() {
+            for idx in frames.into_iter().filter(|idx| *idx != active) {
+                self.frames[idx].align_diff(top, cursor);
+            }
+        }
+    }
+
+    // Copies the change under the cursor from another frame in the diff, or
+    // to it when `put`. A count picks that frame by its place from the left.
+    fn copy_diff_change(&mut self, count: Option<usize>, put: bool) -> EditorResult<()> {
+        let active = self.active_frame_idx;
+        let frames = self.diff_frames();
+        let position = frames.iter().position(|idx| *idx == active).ok_or(EditorError::NotInDiffMode)?;
+        let anchor = frames.len().saturating_sub(1) / 2;
+        let other = match count {
+            Some(count) => *count.checked_sub(1)
+                .and_then(|position| frames.get(position))
+                .filter(|idx| **idx != active)
+                .ok_or_else(|| EditorError::InvalidArgument(count.to_string()))?,
+            None if frames.len() < 2 => return Err(EditorError::NoOtherDiff),
+            None if frames.len() == 2 => frames[1 - position],
+            // The frames around the middle one default to it, as in a merge view.
+            None if position != anchor => frames[anchor],
+            None => return Err(EditorError::AmbiguousDiff),
+        };
+
+        let this: Vec<String> = self.frames[active].buffer().text_lines().map(String::from).collect();
+        let that: Vec<String> = self.frames[other].buffer().text_lines().map(String::from).collect();
+        let line = self.frames[active].cursor_position().line;
+        let hunk = diff(&that, &this).into_iter().find(|hunk| hunk.contains_line(line)).ok_or(EditorError::NoHunk)?;
+        if put {
+            self.frames[other].buffer().check_changeable()?;
+            self.frames[other].replace_lines(hunk.old_start, hunk.old_count, &this[hunk.new_start..hunk.new_end()]);
+        } else {
+            self.frames[active].buffer().check_changeable()?;
+            self.frames[active].replace_lines(hunk.new_start, hunk.new_count, &that[hunk.old_start..hunk.old_end()]);
+        }
+        Ok(())
+    }
+
+    // Keeps blame windows scrolled with the frame they annotate, following
+    // whichever of the two has the cursor.
+    fn align_blame_windows(&mut self) {
+        for idx in 0..self.frames.len() {
+            let source = match self.blame_source(idx) {
+                Some(source) => source,
+                None => continue,
+            };
+            let (from, to) = if self.active_frame_idx == idx { (idx, source) } else { (source, idx) };
+            let (line_offset, line) = (self.frames[from].line_offset(), self.frames[from].cursor_position().line);
+            self.frames[to].align_to(line_offset, line);
+        }
+    }
+
+    fn in_command_window(&self) -> bool {
+        self.command_window.as_ref().is_some_and(|window| window.frame_idx == self.active_frame_idx)
+    }
+
+    fn open_command_window(&mut self) {
+        if self.command_window.is_some() {
+            return;
+        }
+
+        let scratch = Buffer::scratch(COMMAND_WINDOW_NAME, self.command_line.history.entries());
+        let frame_idx = self.active_frame_idx;
+        let frame = self.active_frame();
+        let position = frame.cursor_position();
+        let buffer = frame.replace_buffer(scratch);
+        let last_line = frame.buffer().number_of_lines() - 1;
+        frame.move_to(CursorPosition { line: last_line, column: 0 });
+        self.command_window = Some(CommandWindow { frame_idx, buffer, position });
+    }
+
+    fn close_command_window(&mut self) {
+        if let Some(window) = self.command_window.take() {
+            let frame = &mut self.frames[window.frame_idx];
+            let scratch = frame.replace_buffer(window.buffer);
+            frame.move_to(window.position);
+            // Lines deleted in the window are dropped from the history.
+            self.command_line.history.replace(scratch.text_lines().map(String::from).collect());
+        }
+    }
+
+    fn process_keypress(&mut self) -> crossterm::Result<bool> {
+        // Keep repainting while large files are indexed so the line count catches up.
+        let timeout = if self.finder.as_ref().is_some_and(|finder| finder.is_walking()) {
+            FINDER_REFRESH_TIME
+        } else if self.make.is_some() {
+            MAKE_REFRESH_TIME
+        } else if self.grep.is_some() {
+            GREP_REFRESH_TIME
+        } else if self.frames.iter_mut().any(|frame| frame.buffer().is_indexing()) {
+            INDEX_REFRESH_TIME
+        } else {
+            SWAP_UPDATE_TIME
+        };
+        let key_event = match self.reader.next_event(timeout)? {
+            InputEvent::Key(key_event) => key_event,
+            InputEvent::FilesChanged(files) => {
+                self.check_files(Some(&files));
+                return Ok(true);
+            },
+            InputEvent::Server(event) => {
+                self.handle_server_event(event);
+                return Ok(true);
+            },
+            InputEvent::Idle => {
+                self.update_swap_files();
+                self.buffers_mut().into_iter().filter_map(|buffer| buffer.git.as_mut()).for_each(GitFile::refresh_head);
+                return Ok(true);
+            },
+            #[cfg(test)]
+            InputEvent::EndOfInput => return Ok(false),
+        };
+        log_key(&key_event);
+        if self.prompt.is_some() {
+            return Ok(self.answer_prompt(key_event));
+        }
+        self.keys_since_swap += 1;
+        if self.keys_since_swap >= SWAP_UPDATE_COUNT {
+            self.update_swap_files();
+        }
+        if self.pager.take().is_some() && matches!(key_event.code, KeyCode::Enter | KeyCode::Esc | KeyCode::Char(' ')) {
+            return Ok(true);
+        }
+
+        let in_explorer = matches!(self.mode, Mode::Normal) && self.explorer_window() == Some(self.active_frame_idx);
+        let command = match KeyHandler::process_explorer_key(key_event) {
+            Some(command) if in_explorer && self.key_handler.is_idle() => command,
+            _ => self.key_handler.process_key(key_event, &self.mode),
+        };
+        let (frame_idx, line) = (self.active_frame_idx, self.active_frame().cursor_position().line);
+        let running = match self.handle_command(command) {
+            Ok(running) => running,
+            Err(err) => {
+                self.messages.error(err);
+                true
+            },
+        };
+        if (frame_idx, line) != (self.active_frame_idx, self.active_frame().cursor_position().line) {
+            self.echo_diagnostic();
+        }
+        if self.explorer_window() != Some(self.active_frame_idx) {
+            self.last_frame_idx = self.active_frame_idx;
+        }
+        Ok(running)
+    }
+
+    fn handle_command(&mut self, command: EditorCommand) -> EditorResult<bool> {
+        if matches!(
+            command,
+            EditorCommand::DeleteChars(_)
+                | EditorCommand::ApplyOperator(Operator::Delete, _)
+                | EditorCommand::ReplaceChars(..)
+                | EditorCommand::StartInsert(_)
+        ) {
+            self.active_frame().buffer().check_changeable()?;
+        }
+
+        match command {
+            EditorCommand::QuitProgram => return Ok(false),
+            EditorCommand::MoveCursorLeft(count) => (0..count).for_each(|_| self.active_frame().move_cursor_left()),
+            EditorCommand::MoveCursorRight(count) => (0..count).for_each(|_| self.active_frame().move_cursor_right()),
+            EditorCommand::MoveCursorUp(count) => (0..count).for_each(|_| self.active_frame().move_cursor_up()),
+            EditorCommand::MoveCursorDown(count) => (0..count).for_each(|_| self.active_frame().move_cursor_down()),
+            EditorCommand::MoveCursorDisplayUp(count) => {
+                (0..count).for_each(|_| self.active_frame().move_cursor_display_up())
+            },
+            EditorCommand::MoveCursorDisplayDown(count) => {
+                (0..count).for_each(|_| self.active_frame().move_cursor_display_down())
+            },
+            EditorCommand::SetCommandMode => {
+                self.mode = Mode::Command;
+                self.command_line.clear();
+                self.messages.dismiss();
+            },
+            EditorCommand::SetNormalMode => {
+                if matches!(self.mode, Mode::Insert) {
+                    self.completion = None;
+                    self.active_frame().stop_insert();
+                }
+                self.finder = None;
+                self.mode = Mode::Normal;
+                self.command_line.clear();
+            },
+            EditorCommand::EditCommandLine(CommandLineEdit::DeleteBackward) if self.command_line.text().is_empty() => {
+                self.mode = Mode::Normal;
+            },
+            EditorCommand::EditCommandLine(edit) => self.command_line.edit(edit),
+            EditorCommand::CompleteCommand(direction) => {
+                let buffer_names = self.buffer_names();
+                self.command_line.complete(direction, &buffer_names);
+            },
+            EditorCommand::ExecuteCommand => {
+                let command = self.command_line.take();
+                self.mode = Mode::Normal;
+                return self.execute_command(&command);
+            },
+            EditorCommand::OpenCommandWindow => self.open_command_window(),
+            EditorCommand::Enter(_) if self.in_command_window() => {
+                let command = self.active_frame().current_line_text().unwrap_or_default().to_string();
+                self.close_command_window();
+                self.command_line.history.add(&command);
+                return self.execute_command(&command);
+            },
+            EditorCommand::Enter(_) if self.quickfix_window() == Some(self.active_frame_idx) => {
+                let line = self.active_frame().cursor_position().line;
+                self.quickfix.select(line + 1)?;
+                return self.jump_to_quickfix().map(|_| true);
+            },
+            EditorCommand::Enter(count) => self.active_frame().line_down_first_non_blank(count),
+            EditorCommand::JumpStartOfWord(Direction::Forward, count) => {
+                (0..count).for_each(|_| self.active_frame().jump_to_start_of_word_forward())
+            },
+            EditorCommand::JumpStartOfWord(Direction::Backward, count) => {
+                (0..count).for_each(|_| self.active_frame().jump_to_start_of_word_backward())
+            },
+            EditorCommand::JumpEndOfWord(Direction::Forward, count) => {
+                (0..count).for_each(|_| self.active_frame().jump_to_end_of_word_forward())
+            },
+            EditorCommand::Scroll(scroll, count) => {
+                self.active_frame().scroll(scroll, count)
+            },
+            EditorCommand::JumpToScreenLine(screen_line, count) => {
+                self.active_frame().jump_to_screen_line(screen_line, count)
+            },
+            EditorCommand::Fold(command, count) => {
+                self.active_frame().fold(command, count)
+            },
+            EditorCommand::ApplyOperator(Operator::Fold, motion) => {
+                self.active_frame().create_fold(motion)
+            },
+            EditorCommand::GoToFrame(direction) => {
+                if let Some(idx) = self.neighbour_frame(direction) {
+                    self.active_frame_idx = idx;
+                }
+            },
+            EditorCommand::GoToNextFrame => {
+                self.active_frame_idx = (self.active_frame_idx + 1) % self.frames.len();
+            },
+            EditorCommand::SetMark(name) => self.set_mark(name)?,
+            EditorCommand::JumpToMark(name, linewise) => self.jump_to_mark(name, linewise)?,
+            EditorCommand::JumpOlder(count) => {
+                self.stash_global_marks(self.active_frame_idx);
+                let result = self.active_frame().jump_older(count);
+                self.restore_global_marks(self.active_frame_idx);
+                result?
+            },
+            EditorCommand::JumpNewer(count) => {
+                self.stash_global_marks(self.active_frame_idx);
+                let result = self.active_frame().jump_newer(count);
+                self.restore_global_marks(self.active_frame_idx);
+                result?
+            },
+            EditorCommand::ChangeOlder(count) => self.active_frame().change_older(count),
+            EditorCommand::ChangeNewer(count) => self.active_frame().change_newer(count),
+            EditorCommand::GoToLine(line) => self.active_frame().go_to_line(line),
+            EditorCommand::DeleteChars(_) | EditorCommand::ApplyOperator(Operator::Delete, _) | EditorCommand::StartInsert(_)
+                if self.active_frame().buffer().is_hex() => {
+                return Err(EditorError::HexMode);
+            },
+            EditorCommand::ReplaceChars(c, count) => self.active_frame().replace_chars(c, count)?,
+            EditorCommand::Hover => {
+                let (file, position) = self.server_position()?;
+                self.servers.hover(&file, position)?;
+            },
+            EditorCommand::GoToDefinition => {
+                let (file, position) = self.server_position()?;
+                self.servers.definition(&file, position)?;
+            },
+            EditorCommand::FindReferences => {
+                let (file, position) = self.server_position()?;
+                self.servers.references(&file, position)?;
+            },
+            EditorCommand::StartInsert(position) => {
+                self.active_frame().start_insert(position);
+                self.mode = Mode::Insert;
+                self.messages.dismiss();
+            },
+            EditorCommand::EditText(InsertEdit::Up) if self.completion_visible() => self.select_completion(Direction::Backward),
+            EditorCommand::EditText(InsertEdit::Down) if self.completion_visible() => self.select_completion(Direction::Forward),
+            EditorCommand::EditText(InsertEdit::NewLine | InsertEdit::Insert('\t')) if self.completion_visible() => {
+                self.accept_completion()
+            },
+            EditorCommand::EditText(edit) => {
+                let typed = match edit {
+                    InsertEdit::Insert(c) => Some(c),
+                    _ => None,
+                };
+                self.active_frame().insert_edit(edit);
+                match typed {
+                    Some(c) if self.active_frame().options.completetrigger.contains(c) => self.open_completion(false),
+                    _ => self.refresh_completion(),
+                }
+            },
+            EditorCommand::Complete(direction) if self.completion.is_some() => self.select_completion(direction),
+            EditorCommand::Complete(_) => self.open_completion(true),
+            EditorCommand::AcceptCompletion => self.accept_completion(),
+            EditorCommand::CancelCompletion => self.completion = None,
+            EditorCommand::OpenFinder => self.open_finder("")?,
+            EditorCommand::EditFinderQuery(edit) => {
+                if let Some(finder) = self.finder.as_mut() {
+                    finder.query.edit(edit);
+                }
+            },
+            EditorCommand::SelectFinderEntry(direction) => {
+                if let Some(finder) = self.finder.as_mut() {
+                    finder.select(direction);
+                }
+            },
+            EditorCommand::OpenFinderSelection(split) => self.open_finder_selection(split)?,
+            EditorCommand::Explorer(action) => self.explorer_action(action)?,
+            EditorCommand::JumpHunk(direction, count) => {
+                let frame = self.active_frame();
+                let starts: Vec<usize> = match frame.diff() {
+                    Some(diff) => diff.changes.clone(),
+                    None => frame.buffer().git.as_ref().map_or(vec![], |git| git.hunks.iter().map(Hunk::line).collect()),
+                };
+                self.jump_to_change(&starts, direction, count)?;
+            },
+            EditorCommand::JumpConflict(direction, count) => {
+                let buffer = self.active_frame().buffer();
+                buffer.update_conflicts();
+                let starts: Vec<usize> = buffer.conflicts.iter().map(|conflict| conflict.start).collect();
+                self.jump_to_change(&starts, direction, count)?;
+            },
+            EditorCommand::DiffGet(count) => self.copy_diff_change(count, false)?,
+            EditorCommand::DiffPut(count) => self.copy_diff_change(count, true)?,
+            EditorCommand::DeleteChars(count) => self.active_frame().delete_chars(count),
+            EditorCommand::ApplyOperator(Operator::Delete, motion) => {
+                self.active_frame().delete_lines(motion)
+            },
+            _ => {},
+        }
+
+        Ok(true)
+    }
+
+    fn command_line_scroll(&self) -> usize {
+        (self.command_line.cursor_column() + 2).saturating_sub(self.columns)
+    }
+
+    fn draw_command_line(&mut self) -> crossterm::Result<()> {
+        match (&self.mode, self.messages.current()) {
+            (Mode::Command, _) => {
+                let cmd: String = format!(":{}", self.command_line.text())
+                    .chars()
+                    .skip(self.command_line_scroll())
+                    .take(self.columns)
+                    .collect();
+                self.editor_contents.push_str(&cmd);
+            },
+            (Mode::Normal, Some(message)) => {
+                let text: String = message.text.chars().take(self.columns).collect();
+                if let Some(color) = message.level.color() {
+                    queue!(self.editor_contents, style::SetForegroundColor(color))?;
+                }
+                queue!(self.editor_contents, style::Print(text), style::ResetColor)?;
+            },
+            (Mode::Normal, None) => {},
+            (Mode::Insert, _) => {
+                queue!(
+                    self.editor_contents,
+                    style::SetAttribute(style::Attribute::Bold),
+                    style::Print("-- INSERT --"),
+                    style::SetAttribute(style::Attribute::Reset),
+                )?;
+            },
+            (Mode::Finder, _) => {
+                let (query, (matched, total), walking) = match &self.finder {
+                    Some(finder) => (finder.query.text(), finder.counts(), finder.is_walking()),
+                    None => return Ok(()),
+                };
+                let prompt: String = format!("{}{}", FINDER_PROMPT, query).chars().take(self.columns).collect();
+                let counts = format!("{}/{}{}", matched, total, if walking { " scanning" } else { "" });
+                let command_row = self.lines as u16;
+                let counts_column = cmp::max(prompt.chars().count() + 1, self.columns.saturating_sub(counts.chars().count()));
+                queue!(self.editor_contents, style::Print(prompt))?;
+                if counts_column + counts.chars().count() <= self.columns {
+                    queue!(
+                        self.editor_contents,
+                        cursor::MoveTo(counts_column as u16, command_row),
+                        style::SetForegroundColor(style::Color::DarkGrey),
+                        style::Print(counts),
+                        style::ResetColor,
+                    )?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn overlay(&self) -> Option<(Vec<Message>, &'static str)> {
+        if let Some(prompt) = &self.prompt {
+            let lines = prompt.lines()
+                .into_iter()
+                .enumerate()
+                .map(|(idx, text)| Message {
+                    level: if idx == 0 { MessageLevel::Warning } else { MessageLevel::Info },
+                    text,
+                })
+                .collect();
+            return Some((lines, prompt.footer()));
+        }
+        self.pager.clone().map(|messages| (messages, PAGER_PROMPT))
+    }
+
+    fn draw_overlay(&mut self) -> crossterm::Result<()> {
+        let (messages, footer) = match self.overlay() {
+            Some(overlay) => overlay,
+            None => return Ok(()),
+        };
+
+        let lines = self.lines;
+        let visible = &messages[messages.len().saturating_sub(lines)..];
+        for (row, message) in visible.iter().enumerate() {
+            let text: String = message.text.chars().take(self.columns).collect();
+            queue!(
+                self.editor_contents,
+                cursor::MoveTo(0, (lines - visible.len() + row) as u16),
+                terminal::Clear(ClearType::UntilNewLine),
+            )?;
+            if let Some(color) = message.level.color() {
+                queue!(self.editor_contents, style::SetForegroundColor(color))?;
+            }
+            queue!(self.editor_contents, style::Print(text), style::ResetColor)?;
+        }
+
+        let footer: String = footer.chars().take(self.columns).collect();
+        queue!(
+            self.editor_contents,
+            cursor::MoveTo(0, lines as u16),
+            terminal::Clear(ClearType::UntilNewLine),
+            style::SetForegroundColor(style::Color::Green),
+            style::Print(footer),
+            style::ResetColor,
+        )
+    }
+
+    fn draw_completion_menu(&mut self) -> crossterm::Result<()> {
+        let completion = match (&self.mode, self.command_line.completion()) {
+            (Mode::Command, Some(completion)) => completion,
+            _ => return Ok(()),
+        };
+
+        let lines = self.lines;
+        let height = cmp::min(completion.candidates.len(), cmp::min(COMPLETION_MENU_HEIGHT, lines));
+        let first = completion.selected.saturating_sub(height - 1);
+        let width = completion.candidates.iter().map(|candidate| candidate.chars().count() + 2).max().unwrap_or(0);
+        let width = cmp::min(width, self.columns);
+        let column = cmp::min(
+            (self.command_line.completion_column() + 1).saturating_sub(self.command_line_scroll()),
+            self.columns - width,
+        );
+
+        for (row, (idx, candidate)) in completion.candidates.iter().enumerate().skip(first).take(height).enumerate() {
+            let item: String = format!(" {:<width$}", candidate, width = width - 1).chars().take(width).collect();
+            queue!(self.editor_contents, cursor::MoveTo(column as u16, (lines - height + row) as u16))?;
+            if idx == completion.selected {
+                queue!(self.editor_contents, style::SetAttribute(style::Attribute::Reverse))?;
+            } else {
+                queue!(self.editor_contents, style::SetBackgroundColor(style::Color::DarkGrey))?;
+            }
+            queue!(self.editor_contents, style::Print(item), style::SetAttribute(style::Attribute::Reset))?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_completion_popup(&mut self) -> crossterm::Result<()> {
+        let menu = match (&self.mode, &self.completion) {
+            (Mode::Insert, Some(menu)) if !menu.items.is_empty() => menu,
+            _ => return Ok(()),
+        };
+
+        // The popup starts where the completed text does, which is left of the cursor.
+        let frame = &self.frames[self.active_frame_idx];
+        let (cursor_column, cursor_row) = frame.cursor_controller.absolute_coords();
+        let typed = frame.cursor_position().column.saturating_sub(menu.start);
+        let anchor_column = (frame.size.start_column + cursor_column).saturating_sub(typed);
+        let anchor_row = frame.size.start_line + cursor_row;
+
+        let label_width = menu.items.iter()
+            .map(|item| cmp::min(item.label.chars().count(), COMPLETION_LABEL_WIDTH))
+            .max()
+            .unwrap_or(0);
+        let width = cmp::min(label_width + 7, self.columns);
+        let (row, height) = popup_rows(anchor_row, cmp::min(menu.items.len(), COMPLETION_MENU_HEIGHT), self.lines);
+        let column = popup_column(anchor_column, width, self.columns);
+        let first = menu.selected.saturating_sub(height.saturating_sub(1));
+
+        for (offset, (idx, item)) in menu.items.iter().enumerate().skip(first).take(height).enumerate() {
+            let label: String = item.label.chars().take(label_width).collect();
+            let entry = format!(" {:<label_width$} {:>4} ", label, item.source.tag(), label_width = label_width);
+            let entry: String = entry.chars().take(width).collect();
+            queue!(self.editor_contents, cursor::MoveTo(column as u16, (row + offset) as u16))?;
+            if idx == menu.selected {
+                queue!(self.editor_contents, style::SetAttribute(style::Attribute::Reverse))?;
+            } else {
+                queue!(self.editor_contents, style::SetBackgroundColor(style::Color::DarkGrey))?;
+            }
+            queue!(self.editor_contents, style::Print(entry), style::SetAttribute(style::Attribute::Reset))?;
+        }
+
+        // Documentation for the selected item goes beside the popup, on whichever side has room.
+        let documentation = menu.selected_item().map_or(&[][..], |item| &item.documentation[..]);
+        if documentation.is_empty() {
+            return Ok(());
+        }
+        let doc_width = documentation.iter()
+            .map(|line| cmp::min(line.chars().count(), COMPLETION_DOC_WIDTH) + 2)
+            .max()
+            .unwrap_or(0);
+        let doc_column = if column + width + doc_width <= self.columns {
+            column + width
+        } else if column >= doc_width {
+            column - doc_width
+        } else {
+            return Ok(());
+        };
+        let (doc_row, doc_height) = if row > anchor_row {
+            (row, cmp::min(documentation.len(), self.lines - row))
+        } else {
+            let doc_height = cmp::min(documentation.len(), anchor_row);
+            (anchor_row - doc_height, doc_height)
+        };
+        for (offset, line) in documentation.iter().take(doc_height).enumerate() {
+            let line: String = line.chars().take(doc_width - 2).collect();
+            queue!(
+                self.editor_contents,
+                cursor::MoveTo(doc_column as u16, (doc_row + offset) as u16),
+                style::SetBackgroundColor(style::Color::DarkBlue),
+                style::Print(format!(" {:<width$} ", line, width = doc_width - 2)),
+                style::SetAttribute(style::Attribute::Reset),
+            )?;
+        }
+        Ok(())
+    }
+
+    // The finder covers every frame: matches on the left, the selected file
+    // on the right when there is room for both.
+    fn draw_finder(&mut self) -> crossterm::Result<()> {
+        let (lines, columns) = (self.lines, self.columns);
+        let finder = match (&self.mode, self.finder.as_mut()) {
+            (Mode::Finder, Some(finder)) => finder,
+            _ => return Ok(()),
+        };
+        finder.update();
+
+        let list_width = if columns >= 2 * MIN_FRAME_COLUMNS { columns / 2 } else { columns };
+        let first = finder.selected.saturating_sub(lines.saturating_sub(1));
+        let entries = finder.entries(first, lines);
+        for row in 0..lines {
+            queue!(self.editor_contents, cursor::MoveTo(0, row as u16), terminal::Clear(ClearType::UntilNewLine))?;
+            let (idx, file, positions) = match entries.get(row) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            // Long paths lose their start rather than the file name.
+            let chars: Vec<char> = file.chars().collect();
+            let width = list_width.saturating_sub(3);
+            let skip = chars.len().saturating_sub(width);
+            let selected = *idx == finder.selected;
+            if selected {
+                queue!(self.editor_contents, style::SetAttribute(style::Attribute::Reverse))?;
+            }
+            queue!(self.editor_contents, style::Print(if selected { "> " } else { "  " }))?;
+            if skip > 0 {
+                queue!(self.editor_contents, style::Print('…'))?;
+            }
+            let start = if skip > 0 { skip + 1 } else { 0 };
+            for (column, c) in chars.iter().enumerate().skip(start) {
+                if positions.contains(&column) {
+                    queue!(
+                        self.editor_contents,
+                        style::SetForegroundColor(style::Color::Yellow),
+                        style::Print(c),
+                        style::SetForegroundColor(style::Color::Reset),
+                    )?;
+                } else {
+                    queue!(self.editor_contents, style::Print(c))?;
+                }
+            }
+            if selected {
+                let padding = list_width.saturating_sub(2 + chars.len() - start + usize::from(skip > 0));
+                queue!(
+                    self.editor_contents,
+                    style::Print(" ".repeat(padding)),
+                    style::SetAttribute(style::Attribute::Reset),
+                )?;
+            }
+        }
+
+        let preview_width = columns.saturating_sub(list_width + 2);
+        if preview_width == 0 {
+            return Ok(());
+        }
+        let preview = finder.preview(lines);
+        for row in 0..lines {
+            let text: String = preview.get(row).map_or(String::new(), |line| line.chars().take(preview_width).collect());
+            queue!(
+                self.editor_contents,
+                cursor::MoveTo(list_width as u16, row as u16),
+                style::SetForegroundColor(style::Color::DarkGrey),
+                style::Print("│ "),
+                style::ResetColor,
+                style::Print(text),
+                terminal::Clear(ClearType::UntilNewLine),
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn refresh_screen(&mut self) -> crossterm::Result<()> {
+        queue!(
+            self.editor_contents,
+            terminal::Clear(ClearType::All),
+            cursor::Hide,
+            cursor::MoveTo(0, 0)
+        )?;
+
+        // let frame_rows = self.active_frame().draw_rows()?;
+        // let status_bar_buffer = self.active_frame().draw_status_bar();
+
+        let indicator = self.make.as_ref().map(MakeJob::indicator).unwrap_or_default();
+        let active_frame_idx = self.active_frame_idx;
+        for (idx, frame) in self.frames.iter_mut().enumerate() {
+            frame.load_visible_lines();
+            frame.update_gutter();
+            queue!(
+                self.editor_contents,
+                frame.draw_rows()?,
+                frame.draw_status_bar(if idx == active_frame_idx { &indicator } else { "" })?,
+            )?;
+        }
+
+        self.draw_completion_menu()?;
+        self.draw_completion_popup()?;
+        self.draw_finder()?;
+        let command_row = self.lines as u16;
+        queue!(self.editor_contents, cursor::MoveTo(0, command_row))?;
+        self.draw_command_line()?;
+        self.draw_overlay()?;
+        let (cursor_row, cursor_line) = match self.mode {
+            _ if self.overlay().is_some() => {
+                let footer = self.overlay().map_or(0, |(_, footer)| footer.chars().count());
+                (cmp::min(footer, self.columns - 1), self.lines)
+            },
+            Mode::Normal | Mode::Insert => {
+                (
+                    self.active_frame().cursor_controller.absolute_coords().0 + self.active_frame().size.start_column,
+                    self.active_frame().cursor_controller.absolute_coords().1 + self.active_frame().size.start_line,
+                )
+            },
+            Mode::Command => {
+                (self.command_line.cursor_column() + 1 - self.command_line_scroll(), self.lines)
+            },
+            Mode::Finder => {
+                let column = self.finder.as_ref().map_or(0, |finder| finder.query.cursor_column());
+                (cmp::min(FINDER_PROMPT.len() + column, self.columns - 1), self.lines)
+            },
+        };
+        queue!(
+            self.editor_contents,
+            cursor::MoveTo(cursor_row as u16, cursor_line as u16),
+            cursor::Show,
+        )?;
+        self.editor_contents.flush()
+    }
+
+    pub fn buffers(&mut self) -> Vec<&Buffer> {
+        let mut buffers: Vec<&Buffer> = self.frames.iter_mut().map(|frame| &*frame.buffer()).collect();
+        buffers.extend(self.hidden.iter().map(|hidden| &hidden.buffer));
+        if let Some(window) = &self.command_window {
+            buffers.push(&window.buffer);
+        }
+        buffers
+    }
+
+    fn attach_swap_files(&mut self) {
+        for idx in 0..self.frames.len() {
+            let buffer = self.frames[idx].buffer();
+            let file = match &buffer.file_path {
+                Some(file) if !buffer.swap_checked => file.clone(),
+                _ => continue,
+            };
+            if self.prompt.as_ref().is_some_and(|prompt| same_file(prompt.file(), &file)) {
+                continue;
+            }
+
+            match SwapFile::find_existing(&file) {
+                Some(swap) if self.prompt.is_none() => self.prompt = Some(Prompt::Swap { file, swap }),
+                Some(_) => {},
+                None => self.create_swap_file(idx),
+            }
+        }
+    }
+
+    fn create_swap_file(&mut self, idx: usize) {
+        let buffer = self.frames[idx].buffer();
+        buffer.swap_checked = true;
+        if let Some(file) = buffer.file_path.clone() {
+            match SwapFile::create(&file) {
+                Ok(swap) => buffer.swap = Some(swap),
+                Err(err) => self.messages.warn(format!("Unable to create swap file for {}: {}", file.display(), err)),
+            }
+        }
+    }
+
+    fn update_swap_files(&mut self) {
+        self.keys_since_swap = 0;
+        let mut errors = vec![];
+        for frame in self.frames.iter_mut() {
+            if let Err(err) = frame.buffer().update_swap() {
+                errors.push(err);
+            }
+        }
+        if let Some(window) = self.command_window.as_mut() {
+            if let Err(err) = window.buffer.update_swap() {
+                errors.push(err);
+            }
+        }
+        for err in errors {
+            self.messages.error(format!("Unable to write swap file: {}", err));
+        }
+    }
+
+    fn frames_showing(&self, file: &Path) -> Vec<usize> {
+        (0..self.frames.len())
+            .filter(|idx| self.frames[*idx].file_path().is_some_and(|shown| same_file(shown, file)))
+            .collect()
+    }
+
+    fn answer_prompt(&mut self, key_event: KeyEvent) -> bool {
+        let prompt = match self.prompt.take() {
+            Some(prompt) => prompt,
+            None => return true,
+        };
+        let targets = self.frames_showing(prompt.file());
+
+        let answered = match prompt {
+            Prompt::Swap { ref swap, .. } => self.answer_swap_prompt(swap, &targets, key_event.code),
+            Prompt::FileChanged { ref file } => self.answer_file_changed_prompt(file, &targets, key_event.code),
+            Prompt::Delete { ref file } => self.answer_confirm_prompt(key_event.code, |editor| editor.delete_path(file)),
+            Prompt::Overwrite { ref file, ref source } => {
+                self.answer_confirm_prompt(key_event.code, |editor| editor.move_path(source, file, true))
+            },
+        };
+        match answered {
+            Some(running) => running,
+            None => {
+                self.prompt = Some(prompt);
+                true
+            },
+        }
+    }
+
+    fn answer_swap_prompt(&mut self, swap: &SwapInfo, targets: &[usize], code: KeyCode) -> Option<bool> {
+        match code {
+            KeyCode::Char('r' | 'R') => {
+                if swap.modified {
+                    targets.iter().for_each(|idx| self.frames[*idx].recover(&swap.contents));
+                }
+                if !swap.owner_alive() {
+                    let _ = fs::remove_file(&swap.path);
+                }
+                self.messages.info(format!("Recovered from {}", swap.path.display()));
+            },
+            KeyCode::Char('o' | 'O') => {
+                targets.iter().for_each(|idx| self.frames[*idx].buffer().read_only = true);
+            },
+            KeyCode::Char('d' | 'D') => {
+                if let Err(err) = fs::remove_file(&swap.path) {
+                    self.messages.error(EditorError::Io(swap.path.clone(), err));
+                }
+            },
+            KeyCode::Char('e' | 'E') => {},
+            KeyCode::Char('q' | 'Q') => return Some(false),
+            _ => return None,
+        }
+
+        targets.iter().for_each(|idx| self.create_swap_file(*idx));
+        Some(true)
+    }
+
+    fn answer_file_changed_prompt(&mut self, file: &Path, targets: &[usize], code: KeyCode) -> Option<bool> {
+        match code {
+            KeyCode::Char('r' | 'R') => {
+                for idx in targets {
+                    if let Err(err) = self.frames[*idx].reload(None) {
+                        self.messages.error(err);
+                    }
+                }
+            },
+            KeyCode::Char('k' | 'K') => {},
+            KeyCode::Char('d' | 'D') => {
+                if let Err(err) = self.show_disk_version(file, targets) {
+                    self.messages.error(err);
+                }
+            },
+            _ => return None,
+        }
+
+        // Whatever the answer, don't ask again until the file changes once more.
+        let stamp = FileStamp::of(file);
+        targets.iter().for_each(|idx| self.frames[*idx].buffer().disk_stamp = stamp);
+        Some(true)
+    }
+
+    fn answer_confirm_prompt(&mut self, code: KeyCode, action: impl FnOnce(&mut Self) -> EditorResult<()>) -> Option<bool> {
+        match code {
+            KeyCode::Char('y' | 'Y') => {
+                if let Err(err) = action(self) {
+                    self.messages.error(err);
+                }
+            },
+            KeyCode::Char('n' | 'N') | KeyCode::Esc => {},
+            _ => return None,
+        }
+        Some(true)
+    }
+
+    // Opens the file as it is on disk in a new frame beside the one showing
+    // it, with both in diff mode.
+    fn show_disk_version(&mut self, file: &Path, targets: &[usize]) -> EditorResult<()> {
+        let target = targets.first().copied().unwrap_or(self.active_frame_idx);
+        let disk = Buffer::open(file.to_path_buf())?;
+        let lines: Vec<String> = disk.text_lines().map(String::from).collect();
+        let mut buffer = Buffer::scratch(&format!("{} {}", DISK_NAME, file.display()), &lines);
+        buffer.modifiable = false;
+
+        self.active_frame_idx = target;
+        self.frames[target].start_diff()?;
+        if let Err(err) = self.split_frame(Split::Vertical, buffer, true) {
+            self.frames[target].stop_diff();
+            return Err(err);
+        }
+        self.active_frame().start_diff()?;
+        self.active_frame_idx = target;
+        Ok(())
+    }
+
+    fn check_files(&mut self, files: Option<&[PathBuf]>) {
+        for idx in 0..self.frames.len() {
+            let buffer = self.frames[idx].buffer();
+            let file = match &buffer.file_path {
+                Some(file) if files.is_none_or(|files| files.contains(file)) => file.clone(),
+                _ => continue,
+            };
+            if !buffer.changed_on_disk() {
+                continue;
+            }
+
+            if !file.exists() {
+                buffer.disk_stamp = None;
+                self.messages.warn(format!("File \"{}\" no longer available", file.display()));
+            } else if !buffer.modified {
+                match self.frames[idx].reload(None) {
+                    Ok(()) => self.messages.info(format!("\"{}\" reloaded", file.display())),
+                    Err(err) => self.messages.error(err),
+                }
+            } else if self.prompt.is_none() {
+                self.prompt = Some(Prompt::FileChanged { file });
+            }
+        }
+    }
+
+    fn watch_files(&mut self) {
+        let files = self.frames.iter().filter_map(|frame| frame.file_path().cloned()).collect();
+        self.reader.watcher.set_watched(files);
+    }
+
+    fn execute_lsp_command(&mut self, args: &str) -> EditorResult<()> {
+        let (subcommand, args) = args.split_once(' ').unwrap_or((args, ""));
+        let args = args.trim();
+        match subcommand {
+            "add" => self.servers.configure(ServerConfig::parse(args)?),
+            "start" => {
+                self.servers.enable();
+                self.buffers_mut().into_iter().for_each(|buffer| buffer.sync = None);
+            },
+            "stop" => {
+                self.servers.stop();
+                for buffer in self.buffers_mut() {
+                    buffer.sync = None;
+                    buffer.set_diagnostics(vec![]);
+                }
+            },
+            "info" | "" => {
+                let lines = self.servers.describe();
+                self.pager = Some(lines.into_iter().map(|text| Message { level: MessageLevel::Info, text }).collect());
+            },
+            "diagnostics" => {
+                let lines: Vec<Message> = self.servers.diagnostics()
+                    .into_iter()
+                    .map(|(file, diagnostic)| Message {
+                        level: diagnostic.severity.message_level(),
+                        text: format!("{}:{}: {}", file.display(), diagnostic.start.line + 1, diagnostic.message),
+                    })
+                    .collect();
+                if lines.is_empty() {
+                    self.messages.info("No diagnostics");
+                } else {
+                    self.pager = Some(lines);
+                }
+            },
+            "hover" => return self.handle_command(EditorCommand::Hover).map(|_| ()),
+            "definition" => return self.handle_command(EditorCommand::GoToDefinition).map(|_| ()),
+            "references" => return self.handle_command(EditorCommand::FindReferences).map(|_| ()),
+            "rename" if args.is_empty() => return Err(EditorError::InvalidArgument(String::from("rename needs a new name"))),
+            "rename" => {
+                let (file, position) = self.server_position()?;
+                self.servers.rename(&file, position, args)?;
+            },
+            "action" if args.is_empty() => {
+                let (file, position) = self.server_position()?;
+                self.servers.code_actions(&file, position)?;
+            },
+            "action" => {
+                let number = args.parse().map_err(|_| EditorError::InvalidArgument(args.to_string()))?;
+                if let Some(ServerReply::Edit(files)) = self.servers.apply_code_action(number)? {
+                    self.apply_workspace_edit(files);
+                }
+            },
+            _ => return Err(EditorError::InvalidArgument(subcommand.to_string())),
+        }
+        Ok(())
+    }
+
+    fn buffers_mut(&mut self) -> Vec<&mut Buffer> {
+        let mut buffers: Vec<&mut Buffer> = self.frames.iter_mut().map(Frame::buffer).collect();
+        buffers.extend(self.hidden.iter_mut().map(|hidden| &mut hidden.buffer));
+        buffers
+    }
+
+    // The cursor position as the protocol counts it, in UTF-16 units.
+    fn server_position(&mut self) -> EditorResult<(PathBuf, CursorPosition)> {
+        let frame = self.active_frame();
+        let file = frame.file_path().cloned().ok_or(EditorError::NoFileName)?;
+        let position = frame.cursor_position();
+        let column = to_utf16(frame.current_line_text().unwrap_or(""), position.column);
+        Ok((file, CursorPosition { line: position.line, column }))
+    }
+
+    fn sync_language_servers(&mut self) {
+        let mut errors = vec![];
+        let attached: Vec<PathBuf> = self.buffers_mut()
+            .into_iter()
+            .filter(|buffer| buffer.sync.is_some())
+            .filter_map(|buffer| buffer.file_path.clone())
+            .collect();
+        self.servers.close_unused(&attached);
+
+        let mut buffers: Vec<&mut Buffer> = self.frames.iter_mut().map(Frame::buffer).collect();
+        buffers.extend(self.hidden.iter_mut().map(|hidden| &mut hidden.buffer));
+        for buffer in buffers {
+            if let Err(err) = self.servers.sync(buffer) {
+                errors.push(err);
+            }
+        }
+        errors.into_iter().for_each(|err| self.messages.error(err));
+    }
+
+    fn handle_server_event(&mut self, event: ServerEvent) {
+        for reply in self.servers.handle(event) {
+            match reply {
+                ServerReply::Diagnostics(file, diagnostics) => {
+                    for buffer in self.buffers_mut() {
+                        if buffer.file_path.as_ref().is_some_and(|shown| same_file(shown, &file)) {
+                            buffer.set_diagnostics(diagnostics.clone());
+                        }
+                    }
+                },
+                ServerReply::Hover(lines) => {
+                    self.pager = Some(lines.into_iter().map(|text| Message { level: MessageLevel::Info, text }).collect());
+                },
+                ServerReply::Definition(locations) => match locations.first() {
+                    Some(location) => {
+                        if let Err(err) = self.jump_to_location(location) {
+                            self.messages.error(err);
+                        }
+                    },
+                    None => self.messages.warn("No definition found"),
+                },
+                ServerReply::References(locations) if locations.is_empty() => self.messages.warn("No references found"),
+                ServerReply::References(locations) => self.pager = Some(self.location_listing(&locations)),
+                ServerReply::Edit(files) => self.apply_workspace_edit(files),
+                ServerReply::CodeActions(titles) if titles.is_empty() => self.messages.info("No code actions available"),
+                ServerReply::CodeActions(titles) => {
+                    let mut lines: Vec<Message> = titles.into_iter()
+                        .enumerate()
+                        .map(|(idx, title)| Message { level: MessageLevel::Info, text: format!("{}: {}", idx + 1, title) })
+                        .collect();
+                    lines.push(Message { level: MessageLevel::Info, text: String::from("Apply one with :lsp action {number}") });
+                    self.pager = Some(lines);
+                },
+                ServerReply::Completion(anchor, items) => {
+                    if let Some(menu) = self.completion.as_mut().filter(|menu| (menu.line, menu.start) == (anchor.line, anchor.column)) {
+                        menu.server_items = items;
+                        menu.waiting = false;
+                        self.refresh_completion();
+                    }
+                },
+                ServerReply::Message(level, text) => self.messages.push(level, text),
+            }
+        }
+    }
+
+    fn completion_visible(&self) -> bool {
+        self.completion.as_ref().is_some_and(|menu| !menu.items.is_empty())
+    }
+
+    fn select_completion(&mut self, direction: Direction) {
+        if let Some(menu) = self.completion.as_mut() {
+            menu.select(direction);
+        }
+    }
+
+    fn completion_context(&mut self) -> CompletionContext {
+        let frame = self.active_frame();
+        let column = frame.cursor_position().column;
+        let file = frame.file_path().cloned();
+        CompletionContext::new(frame.current_line_text().unwrap_or(""), column, file.as_deref())
+    }
+
+    fn completion_items(&mut self, context: &CompletionContext, explicit: bool) -> Vec<CompletionItem> {
+        let sources = self.active_frame().options.complete.clone();
+        let language = self.active_frame().file_path().map(|file| language_id(file)).unwrap_or_default();
+        let mut items = vec![];
+        for source in sources {
+            match (source, &context.directory) {
+                (CompletionSource::Paths, Some(directory)) => items.extend(path_items(directory, &context.prefix)),
+                // Without anything typed, words and snippets only come up when asked for.
+                (CompletionSource::Words, None) if explicit || !context.prefix.is_empty() => {
+                    let buffers = self.buffers();
+                    items.extend(word_items(buffers.iter().flat_map(|buffer| buffer.text_lines()), &context.prefix));
+                },
+                (CompletionSource::Snippets, None) if explicit || !context.prefix.is_empty() => {
+                    items.extend(self.snippets.items(&language));
+                },
+                _ => {},
+            }
+        }
+        items
+    }
+
+    fn open_completion(&mut self, explicit: bool) {
+        let context = self.completion_context();
+        let line = self.active_frame().cursor_position().line;
+        let items = rank(self.completion_items(&context, explicit), &context.prefix);
+
+        let mut waiting = false;
+        let asks_server = self.active_frame().options.complete.contains(&CompletionSource::LanguageServer);
+        if asks_server && context.directory.is_none() {
+            // The server has to see the text typed so far before it is asked.
+            self.sync_language_servers();
+            let anchor = CursorPosition { line, column: context.start };
+            match self.server_position().and_then(|(file, position)| self.servers.completion(&file, position, anchor)) {
+                Ok(()) => waiting = true,
+                Err(EditorError::NoLanguageServer | EditorError::NoFileName) => {},
+                Err(err) => self.messages.error(err),
+            }
+        }
+
+        if items.is_empty() && !waiting {
+            self.completion = None;
+            if explicit {
+                self.messages.warn("No completions");
+            }
+            return;
+        }
+        self.completion = Some(CompletionMenu {
+            line,
+            start: context.start,
+            items,
+            selected: 0,
+            explicit,
+            server_items: vec![],
+            waiting,
+        });
+    }
+
+    // Filters the open menu again after the text changed, closing it once
+    // the cursor leaves the completed text.
+    fn refresh_completion(&mut self) {
+        let menu = match self.completion.take() {
+            Some(menu) => menu,
+            None => return,
+        };
+        let context = self.completion_context();
+        let line = self.active_frame().cursor_position().line;
+        if (line, context.start) != (menu.line, menu.start) {
+            return;
+        }
+        let mut items = self.completion_items(&context, menu.explicit);
+        items.extend(menu.server_items.iter().cloned());
+        let items = rank(items, &context.prefix);
+        if !items.is_empty() || menu.waiting {
+            self.completion = Some(CompletionMenu { items, selected: 0, ..menu });
+        }
+    }
+
+    fn accept_completion(&mut self) {
+        let (menu, item) = match self.completion.take() {
+            Some(menu) => match menu.selected_item().cloned() {
+                Some(item) => (menu, item),
+                None => return,
+            },
+            None => return,
+        };
+        let (text, cursor) = if item.snippet { expand_snippet(&item.text) } else { (item.text.clone(), item.text.len()) };
+
+        // Further lines of a snippet keep the indentation of the line it starts on.
+        let frame = self.active_frame();
+        let line_text = frame.current_line_text().unwrap_or("");
+        let indent = &line_text[..line_text.len() - line_text.trim_start().len()];
+        let cursor = cursor + text[..cursor].matches('\n').count() * indent.len();
+        let text = text.replace('\n', &format!("\n{}", indent));
+
+        let start = CursorPosition { line: menu.line, column: menu.start };
+        frame.replace_to_cursor(start, &text);
+        let before_cursor = &text[..cursor];
+        let position = match before_cursor.rfind('\n') {
+            Some(idx) => CursorPosition {
+                line: start.line + before_cursor.matches('\n').count(),
+                column: cursor - idx - 1,
+            },
+            None => CursorPosition { line: start.line, column: start.column + cursor },
+        };
+        frame.move_to(position);
+
+        // Accepting a directory goes straight on to its entries.
+        let trigger = text.chars().last().is_some_and(|c| frame.options.completetrigger.contains(c));
+        if trigger && item.source == CompletionSource::Paths {
+            self.open_completion(false);
+        }
+    }
+
+    fn echo_diagnostic(&mut self) {
+        let line = self.active_frame().cursor_position().line;
+        match self.active_frame().buffer().diagnostic_at(line) {
+            Some(diagnostic) => {
+                let (level, text) = (diagnostic.severity.message_level(), diagnostic.message.clone());
+                self.messages.echo(level, text.lines().next().unwrap_or(""));
+            },
+            None => self.messages.clear_echo(),
+        }
+    }
+
+    // Shows the file in the active frame, keeping the current buffer hidden
+    // rather than dropping it.
+    fn show_file(&mut self, file: &Path) -> EditorResult<()> {
+        if self.active_frame().file_path().is_some_and(|shown| same_file(shown, file)) {
+            return Ok(());
+        }
+        let idx = match self.hidden.iter().position(|hidden| hidden.buffer.file_path.as_ref().is_some_and(|path| same_file(path, file))) {
+            Some(idx) => idx,
+            None => {
+                let buffer = Buffer::open_with(file.to_path_buf(), None, self.active_frame().options.largefile)?;
+                self.hidden.push(HiddenBuffer { buffer, position: CursorPosition::default() });
+                self.hidden.len() - 1
+            },
+        };
+        self.show_hidden(idx);
+        Ok(())
+    }
+
+    fn jump_to_location(&mut self, location: &Location) -> EditorResult<()> {
+        self.active_frame().remember_jump();
+        self.show_file(&location.file)?;
+        let frame = self.active_frame();
+        frame.move_to(CursorPosition { line: location.position.line, column: 0 });
+        let column = from_utf16(frame.current_line_text().unwrap_or(""), location.position.column);
+        frame.move_to(CursorPosition { line: location.position.line, column });
+        Ok(())
+    }
+
+    fn location_listing(&mut self, locations: &[Location]) -> Vec<Message> {
+        let mut files: HashMap<PathBuf, Vec<String>> = HashMap::new();
+        for location in locations {
+            if files.contains_key(&location.file) {
+                continue;
+            }
+            let loaded = self.buffers_mut()
+                .into_iter()
+                .find(|buffer| buffer.file_path.as_ref().is_some_and(|path| same_file(path, &location.file)))
+                .map(|buffer| buffer.text_lines().map(String::from).collect());
+            let lines = loaded.unwrap_or_else(|| {
+                fs::read_to_string(&location.file).map_or(vec![], |text| text.lines().map(String::from).collect())
+            });
+            files.insert(location.file.clone(), lines);
+        }
+
+        let cwd = env::current_dir().unwrap_or_default();
+        locations.iter()
+            .map(|location| {
+                let text = files[&location.file].get(location.position.line).map_or("", String::as_str);
+                let column = from_utf16(text, location.position.column);
+                let file = location.file.strip_prefix(&cwd).unwrap_or(&location.file);
+                Message {
+                    level: MessageLevel::Info,
+                    text: format!("{}:{}:{}: {}", file.display(), location.position.line + 1, column + 1, text.trim()),
+                }
+            })
+            .collect()
+    }
+
+    fn apply_workspace_edit(&mut self, files: Vec<(PathBuf, Vec<TextEdit>)>) {
+        let (mut changed_files, mut changes) = (0, 0);
+        for (file, edits) in files {
+            let count = edits.len();
+            let shows_file = |path: Option<&PathBuf>| path.is_some_and(|path| same_file(path, &file));
+            let mut results = vec![];
+            for frame in self.frames.iter_mut().filter(|frame| shows_file(frame.file_path())) {
+                results.push(frame.apply_edits(edits.clone()));
+            }
+            for hidden in self.hidden.iter_mut().filter(|hidden| shows_file(hidden.buffer.file_path.as_ref())) {
+                results.push(hidden.buffer.apply_edits(edits.clone()));
+            }
+            if results.is_empty() {
+                let opened = Buffer::open(file.clone()).and_then(|mut buffer| {
+                    buffer.apply_edits(edits)?;
+                    self.hidden.push(HiddenBuffer { buffer, position: CursorPosition::default() });
+                    Ok(())
+                });
+                results.push(opened);
+            }
+
+            match results.into_iter().find(Result::is_err) {
+                Some(Err(err)) => self.messages.error(err),
+                _ => {
+                    changed_files += 1;
+                    changes += count;
+                },
+            }
+        }
+        self.messages.info(format!("Applied {} changes in {} files", changes, changed_files));
+    }
+
+    pub fn run(&mut self) -> crossterm::Result<bool> {
+        self.attach_swap_files();
+        self.watch_files();
+        self.sync_language_servers();
+        self.update_make();
+        self.update_grep();
+        self.sync_git();
+        self.align_blame_windows();
+        self.sync_diff();
+        self.sync_conflicts();
+        self.refresh_screen()?;
+        self.process_keypress()
+    }
+}
+
+// Used by the test harness to run without a terminal.
+#[cfg(test)]
+impl Editor {
+    pub fn headless(args: Args, size: (u16, u16), keys: Vec<KeyEvent>) -> Self {
+        let mut editor = Self::new(args, size);
+        editor.reader.set_script(keys);
+        editor.editor_contents = EditorContents::headless(size.0 as usize, size.1 as usize);
+        editor
+    }
+
+    pub fn feed_keys(&mut self, keys: Vec<KeyEvent>) {
+        self.reader.set_script(keys);
+    }
+
+    pub fn screen(&self) -> Option<&Screen> {
+        self.editor_contents.screen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn lists_and_switches_buffers() {
+        let dir = TempDir::new("buffers");
+        let a = dir.write("a.txt", "alpha");
+        let b = dir.write("b.txt", "beta");
+        let mut harness = Harness::new(80, 14, "");
+        harness.keys(&format!(":e {}<CR>:vs {}<CR>:ls<CR>", a.display(), b.display()));
+        assert_eq!(harness.screen().row(10).trim_end(), format!("%a \"{}\" line 1", b.display()));
+        assert_eq!(harness.screen().row(11).trim_end(), format!(" a \"{}\" line 1", a.display()));
+        assert_eq!(harness.screen().row(12).trim_end(), " a \"[No name]\" line 1");
+
+        harness.keys("<CR>:b a.txt<CR>");
+        assert_eq!(harness.buffer_text(), "alpha");
+        assert_eq!(harness.screen().cursor(), (26, 0));
+        harness.keys(":b b.t<CR>");
+        assert_eq!(harness.buffer_text(), "beta");
+    }
+
+    #[test]
+    fn refuses_to_change_readonly_buffers() {
+        let dir = TempDir::new("readonly");
+        let file = dir.write("b.txt", "beta\n");
+        let mut harness = Harness::new(80, 14, "");
+        harness.keys(&format!(":e {}<CR>:view<CR>x", file.display()));
+        assert_eq!(harness.buffer_text(), "beta");
+        assert!(harness.screen().row(12).starts_with("b.txt [RO]"));
+        assert_eq!(harness.screen().row(13).trim_end(), "Cannot change a readonly buffer, use :set noreadonly first");
+        harness.keys(":w<CR>");
+        assert_eq!(harness.screen().row(13).trim_end(), "'readonly' option is set (add ! to override)");
+
+        harness.keys(":w!<CR>");
+        assert_eq!(harness.screen().row(13).trim_end(), format!("\"{}\" 1L, 5B written [utf-8 unix]", file.display()));
+
+        harness.keys(":set noreadonly<CR>x:w<CR>");
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "eta\n");
+    }
+}
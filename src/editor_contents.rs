@@ -1,17 +1,34 @@
 use crate::prelude::*;
 
-#[derive(Debug)]
 pub struct EditorContents {
     pub content: String,
+    #[cfg(test)]
+    screen: Option<Screen>,
 }
 
 impl EditorContents {
     pub fn new() -> Self {
         Self {
             content: String::new(),
+            #[cfg(test)]
+            screen: None,
         }
     }
 
+    #[cfg(test)]
+    pub fn headless(columns: usize, lines: usize) -> Self {
+        Self {
+            content: String::new(),
+            screen: Some(Screen::new(columns, lines)),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn screen(&self) -> Option<&Screen> {
+        self.screen.as_ref()
+    }
+
+    #[allow(dead_code)]
     pub fn push(&mut self, ch: char) {
         self.content.push(ch);
     }
@@ -20,11 +37,13 @@ impl EditorContents {
         self.content.push_str(string)
     }
 
+    #[allow(dead_code)]
     pub fn push_line(&mut self, string: &str) {
         self.content.push_str(string);
         self.push_str("\r\n");
     }
 
+    #[allow(dead_code)]
     pub fn push_welcome_message(&mut self, screen_columns: usize, screen_rows: usize) {
         let mut welcome = format!("Editor -- Version {}", VERSION);
         if welcome.len() > screen_columns {
@@ -60,7 +79,12 @@ impl io::Write for EditorContents {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        eprintln!("Buffer total size: {}", self.content.len());
+        #[cfg(test)]
+        if let Some(screen) = &mut self.screen {
+            screen.apply(&self.content);
+            self.content.clear();
+            return Ok(());
+        }
         let out = write!(stdout(), "{}", self.content);
         stdout().flush()?;
         self.content.clear();
@@ -0,0 +1,379 @@
+use crate::prelude::*;
+use std::str::FromStr;
+
+pub const UTF8_BOM: &[u8] = &[0xef, 0xbb, 0xbf];
+const UTF16LE_BOM: &[u8] = &[0xff, 0xfe];
+const UTF16BE_BOM: &[u8] = &[0xfe, 0xff];
+const UTF16_DETECT_LENGTH: usize = 8000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileFormat {
+    Unix,
+    Dos,
+    Mac,
+}
+
+impl FileFormat {
+    pub fn line_ending(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+            FileFormat::Mac => "\r",
+        }
+    }
+
+    fn detect(text: &str) -> Self {
+        let newlines = text.matches('\n').count();
+        if newlines == 0 {
+            return if text.contains('\r') { FileFormat::Mac } else { FileFormat::Unix };
+        }
+        // Mixed endings are read as unix so no carriage return is silently dropped.
+        if text.matches("\r\n").count() == newlines {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        }
+    }
+}
+
+impl FromStr for FileFormat {
+    type Err = EditorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "unix" => Ok(FileFormat::Unix),
+            "dos" => Ok(FileFormat::Dos),
+            "mac" => Ok(FileFormat::Mac),
+            _ => Err(EditorError::InvalidArgument(format!("fileformat={}", value))),
+        }
+    }
+}
+
+impl fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileFormat::Unix => write!(f, "unix"),
+            FileFormat::Dos => write!(f, "dos"),
+            FileFormat::Mac => write!(f, "mac"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileEncoding {
+    Utf8,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl FileEncoding {
+    fn bom(&self) -> &'static [u8] {
+        match self {
+            FileEncoding::Utf8 => UTF8_BOM,
+            FileEncoding::Latin1 => &[],
+            FileEncoding::Utf16Le => UTF16LE_BOM,
+            FileEncoding::Utf16Be => UTF16BE_BOM,
+        }
+    }
+
+    fn from_bom(bytes: &[u8]) -> Option<Self> {
+        [FileEncoding::Utf8, FileEncoding::Utf16Le, FileEncoding::Utf16Be]
+            .into_iter()
+            .find(|candidate| bytes.starts_with(candidate.bom()))
+    }
+
+    // Without a BOM, UTF-16 text is mostly ASCII with a NUL in every other
+    // byte: the odd ones for little endian, the even ones for big endian.
+    fn guess_utf16(bytes: &[u8]) -> Option<Self> {
+        let head = &bytes[..cmp::min(bytes.len(), UTF16_DETECT_LENGTH)];
+        if head.len() < 2 || !bytes.len().is_multiple_of(2) {
+            return None;
+        }
+        let nuls = |parity: usize| head.iter().skip(parity).step_by(2).filter(|byte| **byte == 0).count();
+        let (even, odd, half) = (nuls(0), nuls(1), head.len() / 2);
+        match (even, odd) {
+            (0, odd) if odd * 4 >= half * 3 => Some(FileEncoding::Utf16Le),
+            (even, 0) if even * 4 >= half * 3 => Some(FileEncoding::Utf16Be),
+            _ => None,
+        }
+    }
+
+    // The encoding the bytes announce themselves, which binary detection must
+    // not override since UTF-16 is full of NUL bytes.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        Self::from_bom(bytes).or_else(|| Self::guess_utf16(bytes))
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        self.decode_checked(bytes).0
+    }
+
+    // Bytes that can't be decoded show up as their hex value, such as <ff>.
+    // Also returns where in the text the first of those is.
+    fn decode_checked(&self, bytes: &[u8]) -> (String, Option<usize>) {
+        let mut text = String::with_capacity(bytes.len());
+        let mut invalid = None;
+        let mut push_invalid = |text: &mut String, shown: String| {
+            invalid.get_or_insert(text.len());
+            text.push_str(&shown);
+        };
+        match self {
+            FileEncoding::Utf8 => {
+                for chunk in bytes.utf8_chunks() {
+                    text.push_str(chunk.valid());
+                    chunk.invalid().iter().for_each(|byte| push_invalid(&mut text, format!("<{:02x}>", byte)));
+                }
+            },
+            FileEncoding::Latin1 => text.extend(bytes.iter().map(|byte| *byte as char)),
+            FileEncoding::Utf16Le | FileEncoding::Utf16Be => {
+                let units = bytes.chunks_exact(2).map(|pair| match self {
+                    FileEncoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]]),
+                });
+                for unit in char::decode_utf16(units) {
+                    match unit {
+                        Ok(c) => text.push(c),
+                        Err(err) => push_invalid(&mut text, format!("<{:04x}>", err.unpaired_surrogate())),
+                    }
+                }
+                if let [byte] = bytes.chunks_exact(2).remainder() {
+                    push_invalid(&mut text, format!("<{:02x}>", byte));
+                }
+            },
+        }
+        (text, invalid)
+    }
+
+    fn encode(&self, text: &str) -> Result<Vec<u8>, char> {
+        match self {
+            FileEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            FileEncoding::Latin1 => text.chars()
+                .map(|c| u8::try_from(c as u32).map_err(|_| c))
+                .collect(),
+            FileEncoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+            FileEncoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+        }
+    }
+}
+
+impl FromStr for FileEncoding {
+    type Err = EditorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(FileEncoding::Utf8),
+            "latin1" | "iso-8859-1" => Ok(FileEncoding::Latin1),
+            "utf-16le" | "utf-16" | "ucs-2le" => Ok(FileEncoding::Utf16Le),
+            "utf-16be" | "ucs-2" => Ok(FileEncoding::Utf16Be),
+            _ => Err(EditorError::InvalidArgument(format!("fileencoding={}", value))),
+        }
+    }
+}
+
+impl fmt::Display for FileEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileEncoding::Utf8 => write!(f, "utf-8"),
+            FileEncoding::Latin1 => write!(f, "latin1"),
+            FileEncoding::Utf16Le => write!(f, "utf-16le"),
+            FileEncoding::Utf16Be => write!(f, "utf-16be"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct FileOptions {
+    pub fileformat: FileFormat,
+    pub fileencoding: FileEncoding,
+    pub bomb: bool,
+    pub endofline: bool,
+    // The first line with bytes that didn't decode, which writing can't restore.
+    pub conversion_error: Option<usize>,
+}
+
+impl Default for FileOptions {
+    fn default() -> Self {
+        Self {
+            fileformat: FileFormat::Unix,
+            fileencoding: FileEncoding::Utf8,
+            bomb: false,
+            endofline: true,
+            conversion_error: None,
+        }
+    }
+}
+
+impl FileOptions {
+    // Without a forced encoding this tries a BOM, then UTF-16, then UTF-8, then
+    // falls back to Latin-1, which accepts any byte sequence.
+    pub fn decode(bytes: &[u8], encoding: Option<FileEncoding>) -> (Self, Vec<String>) {
+        let bom_encoding = FileEncoding::from_bom(bytes);
+        let fileencoding = match (encoding, FileEncoding::sniff(bytes)) {
+            (Some(encoding), _) => encoding,
+            (None, Some(encoding)) => encoding,
+            (None, None) if std::str::from_utf8(bytes).is_ok() => FileEncoding::Utf8,
+            (None, None) => FileEncoding::Latin1,
+        };
+        let bomb = bom_encoding == Some(fileencoding);
+        let body = if bomb { &bytes[fileencoding.bom().len()..] } else { bytes };
+
+        let (text, invalid) = fileencoding.decode_checked(body);
+        let fileformat = FileFormat::detect(&text);
+        let ending = fileformat.line_ending();
+        let conversion_error = invalid.map(|offset| text[..offset].matches(ending).count());
+        let endofline = text.is_empty() || text.ends_with(ending);
+
+        let body = text.strip_suffix(ending).unwrap_or(&text);
+        let lines = if text.is_empty() {
+            vec![]
+        } else {
+            body.split(ending).map(String::from).collect()
+        };
+
+        (Self { fileformat, fileencoding, bomb, endofline, conversion_error }, lines)
+    }
+
+    pub fn encode<'a>(&self, lines: impl Iterator<Item = &'a str>) -> EditorResult<Vec<u8>> {
+        let ending = self.fileformat.line_ending();
+        let lines: Vec<&str> = lines.collect();
+        let mut text = lines.join(ending);
+        if self.endofline && !(text.is_empty() && lines.len() <= 1) {
+            text.push_str(ending);
+        }
+
+        let mut bytes = if self.bomb { self.fileencoding.bom().to_vec() } else { vec![] };
+        let encoded = self.fileencoding.encode(&text).map_err(|c| {
+            EditorError::InvalidArgument(format!("cannot convert {:?} to {}", c, self.fileencoding))
+        })?;
+        bytes.extend(encoded);
+        Ok(bytes)
+    }
+
+    pub fn set(&mut self, arg: &str) -> EditorResult<()> {
+        if let Some((name, value)) = arg.split_once('=') {
+            match name {
+                "fileformat" | "ff" => self.fileformat = value.parse()?,
+                "fileencoding" | "fenc" => self.fileencoding = value.parse()?,
+                _ => return Err(EditorError::UnknownOption(name.to_string())),
+            }
+            return Ok(());
+        }
+
+        let (name, value) = match arg.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        match name {
+            "bomb" => self.bomb = value,
+            "endofline" | "eol" => self.endofline = value,
+            _ => return Err(EditorError::UnknownOption(arg.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} {}", self.fileencoding, self.fileformat);
+        if self.bomb {
+            description.push_str(" bom");
+        }
+        if !self.endofline {
+            description.push_str(" noeol");
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn decode_and_encode_round_trip() {
+        let files: &[&[u8]] = &[
+            b"one\r\ntwo\r\n",
+            b"one\ntwo",
+            b"one\rtwo\r",
+            b"\xef\xbb\xbfutf-8 with bom\n",
+            b"caf\xe9\n",
+            b"\xff\xfeh\x00i\x00\n\x00",
+            b"",
+        ];
+        for bytes in files {
+            let (options, lines) = FileOptions::decode(bytes, None);
+            let encoded = options.encode(lines.iter().map(String::as_str)).unwrap();
+            assert_eq!(&encoded, bytes, "{:?}", String::from_utf8_lossy(bytes));
+        }
+
+        let (options, lines) = FileOptions::decode(b"caf\xe9\n", None);
+        assert_eq!((options.fileencoding, lines), (FileEncoding::Latin1, vec!["caf\u{e9}".to_string()]));
+
+        let (options, lines) = FileOptions::decode(b"ok\na\xffb\n", Some(FileEncoding::Utf8));
+        assert_eq!(lines, vec!["ok".to_string(), "a<ff>b".to_string()]);
+        assert_eq!(options.conversion_error, Some(1));
+    }
+
+    #[test]
+    fn opens_utf16_files_as_text() {
+        let dir = TempDir::new("utf16");
+        let text = "héllo\nwörld\n";
+        let le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let files = [
+            ([UTF16LE_BOM, &le].concat(), FileEncoding::Utf16Le, true),
+            (le, FileEncoding::Utf16Le, false),
+            (be, FileEncoding::Utf16Be, false),
+        ];
+        for (idx, (bytes, fileencoding, bomb)) in files.into_iter().enumerate() {
+            let file = dir.write(&format!("{}.txt", idx), &bytes);
+            let mut buffer = Buffer::open(file.clone()).unwrap();
+            assert!(!buffer.is_hex());
+            assert_eq!(buffer.text_lines().collect::<Vec<_>>(), vec!["héllo", "wörld"]);
+            assert_eq!((buffer.file_options.fileencoding, buffer.file_options.bomb), (fileencoding, bomb));
+            buffer.write(None, false).unwrap();
+            assert_eq!(fs::read(&file).unwrap(), bytes);
+        }
+        assert!(Buffer::open(dir.write("binary", b"\x7fELF\x02\x01\x00\x00")).unwrap().is_hex());
+    }
+
+    #[test]
+    fn refuses_to_write_undecodable_bytes() {
+        let dir = TempDir::new("encoding");
+        let file = dir.write("mixed.txt", b"ok\na\xffb\n");
+        let mut harness = Harness::new(160, 12, "");
+        harness.keys(&format!(":e ++enc=utf-8 {}<CR>", file.display()));
+        assert_eq!(harness.buffer_text(), "ok\na<ff>b");
+        assert!(harness.screen().row(11).contains("[CONVERSION ERROR in line 2]"));
+
+        harness.keys(":w<CR>");
+        assert!(harness.screen().row(11).contains("Line 2 could not be decoded"));
+        assert_eq!(fs::read(&file).unwrap(), b"ok\na\xffb\n");
+
+        // Reading it again without forcing an encoding keeps every byte.
+        harness.keys(":e! ++enc=latin1<CR>:w<CR>");
+        assert_eq!(fs::read(&file).unwrap(), b"ok\na\xffb\n");
+        harness.keys(":e! ++enc=utf-8<CR>:w!<CR>");
+        assert_eq!(fs::read(&file).unwrap(), b"ok\na<ff>b\n");
+    }
+
+    #[test]
+    fn displays_latin1_files() {
+        let dir = TempDir::new("latin1");
+        let line = "crème brûlée à la française, s'il vous plaît";
+        let file = dir.write("menu.txt", [line.chars().map(|c| c as u8).collect::<Vec<_>>(), b"\n".to_vec()].concat());
+        let chars = |from: usize, to: usize| line.chars().skip(from).take(to - from).collect::<String>();
+        let mut harness = Harness::new(60, 14, "");
+        harness.keys(&format!(":e {}<CR>", file.display()));
+        assert_eq!(harness.buffer_text(), line);
+        assert!(harness.screen().row(0).starts_with(&format!("    1 {} ", chars(0, 23))));
+        assert!(harness.screen().row(12).starts_with("menu.txt -- 1 lines [latin1"));
+
+        harness.keys("40l");
+        assert_eq!(harness.screen().cursor(), (28, 0));
+        assert!(harness.screen().row(0).starts_with(&format!("    1 {} ", chars(18, 41))));
+        harness.keys(":set wrap<CR>");
+        assert!(harness.screen().row(1).starts_with(&format!("    ↪ {} ", chars(23, 44))));
+        assert_eq!(harness.screen().cursor(), (23, 1));
+    }
+}
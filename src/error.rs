@@ -0,0 +1,86 @@
+use crate::prelude::*;
+
+#[derive(Debug)]
+pub enum EditorError {
+    Io(PathBuf, io::Error),
+    UnknownCommand(String),
+    UnknownOption(String),
+    InvalidArgument(String),
+    InvalidMark(char),
+    MarkNotSet(char),
+    NoMatchingBuffer(String),
+    NoFileName,
+    PatternNotFound(String),
+    NoWriteSinceLastChange,
+    ReadOnly,
+    NotModifiable,
+    ChangeReadOnly,
+    HexMode,
+    NoLanguageServer,
+    NoRoom,
+    CloseLastFrame,
+    NoErrors,
+    NoMoreItems,
+    BuildRunning,
+    BuildFailed(String),
+    FileExists(PathBuf),
+    OutsideExplorerRoot(PathBuf),
+    NotInRepository,
+    NoHunk,
+    Git(String),
+    LargeFile,
+    NotInDiffMode,
+    NoOtherDiff,
+    AmbiguousDiff,
+    NoConflict,
+    NotInConflict,
+    NoBase,
+    ConversionError(usize),
+}
+
+pub type EditorResult<T> = Result<T, EditorError>;
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EditorError::Io(path, err) => write!(f, "\"{}\" {}", path.display(), err),
+            EditorError::UnknownCommand(name) => write!(f, "Not an editor command: {}", name),
+            EditorError::UnknownOption(name) => write!(f, "Unknown option: {}", name),
+            EditorError::InvalidArgument(arg) => write!(f, "Invalid argument: {}", arg),
+            EditorError::InvalidMark(name) => write!(f, "Invalid mark name: {}", name),
+            EditorError::MarkNotSet(name) => write!(f, "Mark not set: {}", name),
+            EditorError::NoMatchingBuffer(name) => write!(f, "No matching buffer for {}", name),
+            EditorError::NoFileName => write!(f, "No file name"),
+            EditorError::PatternNotFound(pattern) => write!(f, "Pattern not found: {}", pattern),
+            EditorError::NoWriteSinceLastChange => write!(f, "No write since last change (add ! to override)"),
+            EditorError::NotModifiable => write!(f, "Cannot make changes, 'modifiable' is off"),
+            EditorError::ChangeReadOnly => write!(f, "Cannot change a readonly buffer, use :set noreadonly first"),
+            EditorError::NoLanguageServer => write!(f, "No language server is attached to this buffer"),
+            EditorError::NoRoom => write!(f, "Not enough room"),
+            EditorError::CloseLastFrame => write!(f, "Cannot close last window"),
+            EditorError::NoErrors => write!(f, "No Errors"),
+            EditorError::NoMoreItems => write!(f, "No more items"),
+            EditorError::BuildRunning => write!(f, "A build is already running"),
+            EditorError::BuildFailed(status) => write!(f, "Build failed ({})", status),
+            EditorError::FileExists(path) => write!(f, "File exists: {}", path.display()),
+            EditorError::OutsideExplorerRoot(path) => write!(f, "\"{}\" is not under the explorer root", path.display()),
+            EditorError::NotInRepository => write!(f, "Not in a git repository"),
+            EditorError::NoHunk => write!(f, "No change at the cursor"),
+            EditorError::Git(message) => write!(f, "git: {}", message),
+            EditorError::LargeFile => write!(f, "Not available for large files"),
+            EditorError::NotInDiffMode => write!(f, "Current buffer is not in diff mode"),
+            EditorError::NoOtherDiff => write!(f, "No other buffer in diff mode"),
+            EditorError::AmbiguousDiff => write!(f, "More than two buffers in diff mode, give a count to pick one"),
+            EditorError::NoConflict => write!(f, "No merge conflict in this file"),
+            EditorError::NotInConflict => write!(f, "No conflict at the cursor"),
+            EditorError::NoBase => write!(f, "Conflict has no base section"),
+            EditorError::ConversionError(line) => {
+                write!(f, "Line {} could not be decoded and would be written differently (add ! to override)", line + 1)
+            },
+            EditorError::HexMode => write!(f, "Not allowed in hex mode, use r to replace bytes"),
+            EditorError::ReadOnly => write!(f, "'readonly' option is set (add ! to override)"),
+        }
+    }
+}
+
+impl std::error::Error for EditorError {}
@@ -0,0 +1,258 @@
+use crate::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::process::Command;
+
+pub const EXPLORER_NAME: &str = "[Explorer]";
+pub const EXPLORER_WIDTH: usize = 30;
+
+pub struct ExplorerEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    depth: usize,
+}
+
+// A tree of the files under `root`. Directories are only read once they are
+// expanded, and `entries` holds the rows currently shown, in display order.
+// Hidden files are left out, as the finder leaves them out.
+pub struct Explorer {
+    pub root: PathBuf,
+    expanded: HashSet<PathBuf>,
+    entries: Vec<ExplorerEntry>,
+    git_status: HashMap<PathBuf, char>,
+}
+
+impl Explorer {
+    pub fn new(root: &Path) -> EditorResult<Self> {
+        let root = fs::canonicalize(root).map_err(|err| EditorError::Io(root.to_path_buf(), err))?;
+        if !root.is_dir() {
+            return Err(EditorError::InvalidArgument(root.display().to_string()));
+        }
+        let mut explorer = Self {
+            expanded: HashSet::from([root.clone()]),
+            root,
+            entries: vec![],
+            git_status: HashMap::new(),
+        };
+        explorer.refresh();
+        Ok(explorer)
+    }
+
+    // Reads the expanded directories and the git status again.
+    pub fn refresh(&mut self) {
+        self.git_status = git_status(&self.root);
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        self.expanded.retain(|dir| dir.is_dir());
+        self.entries = vec![ExplorerEntry { path: self.root.clone(), is_dir: true, depth: 0 }];
+        self.list(&self.root.clone(), 1);
+    }
+
+    fn list(&mut self, dir: &Path, depth: usize) {
+        let mut children: Vec<(bool, PathBuf)> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+                .map(|entry| (entry.path().is_dir(), entry.path()))
+                .collect(),
+            Err(_) => return,
+        };
+        // Directories first, each group in name order.
+        children.sort_by(|(a_dir, a), (b_dir, b)| b_dir.cmp(a_dir).then_with(|| a.cmp(b)));
+        for (is_dir, path) in children {
+            let expanded = is_dir && self.expanded.contains(&path);
+            self.entries.push(ExplorerEntry { path: path.clone(), is_dir, depth });
+            if expanded {
+                self.list(&path, depth + 1);
+            }
+        }
+    }
+
+    pub fn entry(&self, row: usize) -> Option<&ExplorerEntry> {
+        self.entries.get(row)
+    }
+
+    pub fn row_of(&self, path: &Path) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.path == path)
+    }
+
+    pub fn toggle(&mut self, row: usize) {
+        let path = match self.entry(row) {
+            Some(entry) if entry.is_dir => entry.path.clone(),
+            _ => return,
+        };
+        if !self.expanded.remove(&path) {
+            self.expanded.insert(path);
+        }
+        self.rebuild();
+    }
+
+    // Collapses the directory on the row, or the one containing it, and
+    // returns the row the cursor should move to.
+    pub fn collapse(&mut self, row: usize) -> usize {
+        let path = match self.entry(row) {
+            Some(entry) if entry.is_dir && self.expanded.contains(&entry.path) && row > 0 => entry.path.clone(),
+            Some(entry) if entry.path != self.root => entry.path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            _ => return row,
+        };
+        if path != self.root {
+            self.expanded.remove(&path);
+            self.rebuild();
+        }
+        self.row_of(&path).unwrap_or(0)
+    }
+
+    // Expands every directory above `file` and returns its row.
+    pub fn reveal(&mut self, file: &Path) -> Option<usize> {
+        let file = canonical_path(file);
+        if !file.starts_with(&self.root) {
+            return None;
+        }
+        for dir in file.ancestors().skip(1).take_while(|dir| dir.starts_with(&self.root)) {
+            self.expanded.insert(dir.to_path_buf());
+        }
+        self.rebuild();
+        self.row_of(&file)
+    }
+
+    // One row per entry: a git status marker, then the name indented by depth.
+    pub fn lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let marker = self.git_status.get(&entry.path).copied().unwrap_or(' ');
+                let name = entry.path.file_name().map_or_else(|| entry.path.display().to_string(), |name| name.to_string_lossy().to_string());
+                let (icon, suffix) = match entry.is_dir {
+                    true if self.expanded.contains(&entry.path) => ("▾ ", "/"),
+                    true => ("▸ ", "/"),
+                    false => ("  ", ""),
+                };
+                format!("{} {}{}{}{}", marker, "  ".repeat(entry.depth), icon, name, suffix)
+            })
+            .collect()
+    }
+}
+
+// The path relative to the working directory where possible, as buffers
+// opened from the command line are named.
+pub fn relative_path(path: &Path) -> PathBuf {
+    let cwd = env::current_dir().map(|cwd| canonical_path(&cwd)).unwrap_or_default();
+    path.strip_prefix(&cwd).map(Path::to_path_buf).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn git_status(root: &Path) -> HashMap<PathBuf, char> {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .arg("-C")
+            .arg(root)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+    };
+    match (git(&["rev-parse", "--show-toplevel"]), git(&["status", "--porcelain"])) {
+        (Some(top), Some(status)) => parse_git_status(&canonical_path(Path::new(top.trim())), &status),
+        _ => HashMap::new(),
+    }
+}
+
+// Reads `git status --porcelain` output into a marker per path: the staged
+// status letter if there is one, otherwise the unstaged one, and '?' for
+// untracked files. Directories take the marker of the first change inside them.
+pub fn parse_git_status(top: &Path, status: &str) -> HashMap<PathBuf, char> {
+    let mut markers = HashMap::new();
+    for line in status.lines() {
+        let (code, path) = match (line.get(..2), line.get(3..)) {
+            (Some(code), Some(path)) => (code.as_bytes(), path),
+            _ => continue,
+        };
+        let path = path.rsplit(" -> ").next().unwrap_or(path).trim_matches('"').trim_end_matches('/');
+        let marker = match (code[0], code[1]) {
+            (b'?', _) => '?',
+            (b' ', unstaged) => unstaged as char,
+            (staged, _) => staged as char,
+        };
+        let file = top.join(path);
+        for path in file.ancestors().take_while(|path| path.starts_with(top) && *path != top) {
+            markers.entry(path.to_path_buf()).or_insert(marker);
+        }
+    }
+    markers
+}
+
+// Paths go into commands as space separated arguments, so spaces in them are
+// escaped as option values are.
+pub fn escape_spaces(path: &Path) -> String {
+    path.display().to_string().replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn browses_and_changes_files() {
+        let status = " M src/main.rs\nA  docs/guide.md\n?? notes/\nR  old.rs -> new.rs\n";
+        let markers = parse_git_status(Path::new("/repo"), status);
+        assert_eq!(markers.get(Path::new("/repo/src/main.rs")), Some(&'M'));
+        assert_eq!(markers.get(Path::new("/repo/src")), Some(&'M'));
+        assert_eq!(markers.get(Path::new("/repo/docs")), Some(&'A'));
+        assert_eq!(markers.get(Path::new("/repo/notes")), Some(&'?'));
+        assert_eq!(markers.get(Path::new("/repo/new.rs")), Some(&'R'));
+        assert_eq!(markers.get(Path::new("/repo")), None);
+
+        let dir = TempDir::new("tree");
+        dir.write("src/main.rs", "fn main() {}\n");
+        dir.write("src/lib.rs", "pub fn lib() {}\n");
+        dir.write("README.md", "readme\n");
+        let name = dir.path().file_name().unwrap().to_string_lossy().to_string();
+
+        let mut harness = Harness::new(80, 12, "scratch");
+        let right_of = |row: String, column: usize| row.chars().skip(column).collect::<String>();
+        harness.keys(&format!(":Explore {}<CR>", dir.path().display()));
+        assert!(harness.screen().row(0).starts_with(&format!("  ▾ {}/", name)));
+        assert!(harness.screen().row(1).starts_with("    ▸ src/ "));
+        assert!(harness.screen().row(2).starts_with("      README.md "));
+        assert!(right_of(harness.screen().row(0), 30).trim_start().starts_with("1 scratch"));
+
+        harness.keys("j<CR>");
+        assert!(harness.screen().row(1).starts_with("    ▾ src/ "));
+        assert!(harness.screen().row(3).starts_with("        main.rs "));
+        // Files open in the frame that was active before the explorer, the scratch one here.
+        harness.keys("jj<CR>");
+        assert_eq!(harness.buffer_text(), "fn main() {}");
+
+        harness.keys(":Explore<CR>");
+        assert_eq!(harness.cursor(), (3, 0));
+        harness.keys("anew.rs<CR>");
+        assert!(dir.join("src/new.rs").is_file());
+        assert_eq!(harness.cursor(), (4, 0));
+        harness.keys("r<BS><BS><BS><BS><BS><BS>old.rs<CR>");
+        assert!(dir.join("src/old.rs").is_file() && !dir.join("src/new.rs").exists());
+        assert!(harness.screen().row(4).starts_with("        old.rs "));
+
+        harness.keys("dn");
+        assert!(dir.join("src/old.rs").exists());
+        harness.keys("d");
+        assert!(harness.screen().text().contains("Delete \""));
+        harness.keys("y");
+        assert!(!dir.join("src/old.rs").exists());
+        assert!(harness.screen().row(4).starts_with("      README.md "));
+
+        // Moving onto an existing file asks first, and buffers follow the files they show.
+        harness.keys(&format!(":Explore move {0}/src/main.rs {0}/src/lib.rs<CR>", dir.path().display()));
+        assert!(harness.screen().text().contains("already exists"));
+        harness.keys("n");
+        harness.keys(&format!(":Explore move {0}/src/main.rs {0}/app.rs<CR>", dir.path().display()));
+        assert!(dir.join("app.rs").is_file());
+        assert!(right_of(harness.screen().row(10), 30).contains("app.rs"));
+
+        harness.keys("q");
+        assert!(harness.screen().row(0).trim_start().starts_with("1 fn main() {}"));
+        assert!(harness.screen().row(10).starts_with("app.rs"));
+        assert!(right_of(harness.screen().row(10), 40).starts_with("[No name]"));
+    }
+}
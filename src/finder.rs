@@ -0,0 +1,366 @@
+use crate::prelude::*;
+use std::io::Read;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const FINDER_REFRESH_TIME: Duration = Duration::from_millis(100);
+pub const FINDER_PROMPT: &str = "Files> ";
+const WALK_BATCH: usize = 256;
+const PREVIEW_BYTES: u64 = 1 << 16;
+
+// One pattern line from a .gitignore, kept relative to the directory the file
+// lives in ("" for the root, otherwise "dir/").
+#[derive(Clone)]
+struct IgnoreRule {
+    base: String,
+    pattern: Vec<char>,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRule {
+    fn parse(base: &str, line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // A slash anywhere but the end ties the pattern to the .gitignore's directory.
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        Some(Self { base: base.to_string(), pattern: line.chars().collect(), negated, directory_only, anchored })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Some(relative) => relative,
+            None => return false,
+        };
+        let text: Vec<char> = if self.anchored {
+            relative.chars().collect()
+        } else {
+            relative.rsplit('/').next().unwrap_or(relative).chars().collect()
+        };
+        glob_match(&self.pattern, &text)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Gitignore {
+    rules: Vec<IgnoreRule>,
+}
+
+impl Gitignore {
+    // The rules in effect below `base` once its .gitignore is read; later
+    // rules win, so deeper files override their parents.
+    pub fn with_file(&self, base: &str, contents: &str) -> Self {
+        let mut rules = self.rules.clone();
+        rules.extend(contents.lines().filter_map(|line| IgnoreRule::parse(base, line)));
+        Self { rules }
+    }
+
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        self.rules.iter().rev().find(|rule| rule.matches(path, is_dir)).is_some_and(|rule| !rule.negated)
+    }
+}
+
+// Gitignore-flavoured globbing: `*` and `?` stay within one path component,
+// `**` crosses them and `**/` may match no directories at all.
+pub fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => match pattern[2..].strip_prefix(&['/']) {
+            Some(rest) => {
+                glob_match(rest, text)
+                    || (0..text.len()).any(|idx| text[idx] == '/' && glob_match(rest, &text[idx + 1..]))
+            },
+            None => (0..=text.len()).any(|idx| glob_match(&pattern[2..], &text[idx..])),
+        },
+        Some('*') => (0..=text.len())
+            .take_while(|idx| *idx == 0 || text[idx - 1] != '/')
+            .any(|idx| glob_match(&pattern[1..], &text[idx..])),
+        Some('?') => text.first().is_some_and(|c| *c != '/') && glob_match(&pattern[1..], &text[1..]),
+        Some('[') => match (text.first(), class_match(&pattern[1..], text.first().copied())) {
+            (Some(_), Some((true, len))) => glob_match(&pattern[len + 1..], &text[1..]),
+            (_, Some((false, _))) | (None, Some(_)) => false,
+            (_, None) => text.first() == Some(&'[') && glob_match(&pattern[1..], &text[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..]),
+        Some(c) => text.first() == Some(c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+// Whether `c` is in the bracket expression starting after `[`, and how many
+// pattern chars the expression used including the closing `]`.
+fn class_match(pattern: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let (negated, start) = match pattern.first() {
+        Some('!' | '^') => (true, 1),
+        _ => (false, 0),
+    };
+    let end = start + 1 + pattern.get(start + 1..)?.iter().position(|p| *p == ']')?;
+    let members = &pattern[start..end];
+    let c = c?;
+    let mut matched = false;
+    let mut idx = 0;
+    while idx < members.len() {
+        if idx + 2 < members.len() && members[idx + 1] == '-' {
+            matched |= (members[idx]..=members[idx + 2]).contains(&c);
+            idx += 3;
+        } else {
+            matched |= members[idx] == c;
+            idx += 1;
+        }
+    }
+    Some((matched != negated, end + 1))
+}
+
+// Visits the files under `root` as paths relative to it, skipping hidden
+// entries and anything the .gitignore files along the way exclude. The walk
+// stops early once `visit` returns false.
+pub fn walk_files(root: &Path, mut visit: impl FnMut(String) -> bool) {
+    let mut stack = vec![(String::new(), Rc::new(Gitignore::default()))];
+    while let Some((dir, ignore)) = stack.pop() {
+        let path = root.join(&dir);
+        let ignore = match fs::read_to_string(path.join(".gitignore")) {
+            Ok(contents) => Rc::new(ignore.with_file(&dir, &contents)),
+            Err(_) => ignore,
+        };
+        let mut entries: Vec<(String, bool)> = match fs::read_dir(&path) {
+            Ok(entries) => entries
+                .flatten()
+                .filter_map(|entry| {
+                    let name = entry.file_name().into_string().ok()?;
+                    // Symlinked directories count as files so cycles can't trap the walk.
+                    Some((name, entry.file_type().ok()?.is_dir()))
+                })
+                .filter(|(name, _)| !name.starts_with('.'))
+                .collect(),
+            Err(_) => continue,
+        };
+        entries.sort();
+        let mut subdirectories = vec![];
+        for (name, is_dir) in entries {
+            let relative = format!("{}{}", dir, name);
+            if ignore.is_ignored(&relative, is_dir) {
+                continue;
+            }
+            if is_dir {
+                subdirectories.push((format!("{}/", relative), Rc::clone(&ignore)));
+            } else if !visit(relative) {
+                return;
+            }
+        }
+        // Reversed so the subdirectories pop off the stack in name order.
+        stack.extend(subdirectories.into_iter().rev());
+    }
+}
+
+// Collects the walked files on a background thread so huge trees don't block input.
+pub struct FileWalker {
+    files: Arc<Mutex<Vec<String>>>,
+    walking: Arc<AtomicBool>,
+}
+
+impl FileWalker {
+    pub fn start(root: PathBuf) -> Self {
+        let files = Arc::new(Mutex::new(vec![]));
+        let walking = Arc::new(AtomicBool::new(true));
+        let (shared_files, shared_walking) = (Arc::clone(&files), Arc::clone(&walking));
+        thread::spawn(move || {
+            let flush = |found: &mut Vec<String>| {
+                if let Ok(mut files) = shared_files.lock() {
+                    files.append(found);
+                }
+            };
+            let mut found = vec![];
+            walk_files(&root, |file| {
+                found.push(file);
+                if found.len() >= WALK_BATCH {
+                    flush(&mut found);
+                }
+                shared_walking.load(Ordering::Relaxed)
+            });
+            flush(&mut found);
+            shared_walking.store(false, Ordering::Relaxed);
+        });
+        Self { files, walking }
+    }
+
+    pub fn is_walking(&self) -> bool {
+        self.walking.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for FileWalker {
+    fn drop(&mut self) {
+        self.walking.store(false, Ordering::Relaxed);
+    }
+}
+
+// The `:Files` picker: a query typed against the walked paths, best matches
+// first, with a preview of the selected file.
+pub struct Finder {
+    walker: FileWalker,
+    root: PathBuf,
+    pub query: CommandLine,
+    files: Vec<String>,
+    matches: Vec<usize>,
+    ranked: Option<(String, usize)>,
+    pub selected: usize,
+    preview: Option<(usize, Vec<String>)>,
+}
+
+impl Finder {
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            walker: FileWalker::start(root.clone()),
+            root,
+            query: CommandLine::default(),
+            files: vec![],
+            matches: vec![],
+            ranked: None,
+            selected: 0,
+            preview: None,
+        }
+    }
+
+    pub fn is_walking(&self) -> bool {
+        self.walker.is_walking()
+    }
+
+    // Picks up newly walked files and re-ranks when they or the query changed.
+    pub fn update(&mut self) {
+        if let Ok(files) = self.walker.files.lock() {
+            self.files.extend_from_slice(&files[self.files.len()..]);
+        }
+        let query = self.query.text();
+        match &self.ranked {
+            Some((ranked, count)) if ranked == query && *count == self.files.len() => return,
+            Some((ranked, _)) if ranked == query => {},
+            _ => self.selected = 0,
+        }
+
+        let mut scored: Vec<(i64, usize)> = self.files.iter()
+            .enumerate()
+            .filter_map(|(idx, file)| Some((fuzzy_score(query, file)?, idx)))
+            .collect();
+        if !query.is_empty() {
+            scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.cmp(b)));
+        }
+        self.matches = scored.into_iter().map(|(_, idx)| idx).collect();
+        self.selected = cmp::min(self.selected, self.matches.len().saturating_sub(1));
+        self.ranked = Some((query.to_string(), self.files.len()));
+    }
+
+    pub fn counts(&self) -> (usize, usize) {
+        (self.matches.len(), self.files.len())
+    }
+
+    // The matches from `first` on, each with the char indices the query matched.
+    pub fn entries(&self, first: usize, count: usize) -> Vec<(usize, &str, Vec<usize>)> {
+        let query = self.query.text();
+        self.matches.iter()
+            .enumerate()
+            .skip(first)
+            .take(count)
+            .map(|(idx, file)| {
+                let file = &self.files[*file];
+                (idx, file.as_str(), fuzzy_match(query, file).map_or(vec![], |(_, positions)| positions))
+            })
+            .collect()
+    }
+
+    pub fn select(&mut self, direction: Direction) {
+        let len = self.matches.len();
+        if len > 0 {
+            self.selected = match direction {
+                Direction::Forward => (self.selected + 1) % len,
+                Direction::Backward => (self.selected + len - 1) % len,
+            };
+        }
+    }
+
+    pub fn selection(&self) -> Option<PathBuf> {
+        let file = self.matches.get(self.selected)?;
+        Some(self.root.join(&self.files[*file]))
+    }
+
+    pub fn preview(&mut self, lines: usize) -> &[String] {
+        let file = match self.matches.get(self.selected) {
+            Some(file) => *file,
+            None => return &[],
+        };
+        if self.preview.as_ref().is_none_or(|(cached, _)| *cached != file) {
+            let text = read_preview(&self.root.join(&self.files[file]), lines);
+            self.preview = Some((file, text));
+        }
+        self.preview.as_ref().map_or(&[], |(_, text)| &text[..])
+    }
+}
+
+fn read_preview(file: &Path, lines: usize) -> Vec<String> {
+    let mut bytes = vec![];
+    if let Err(err) = fs::File::open(file).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes)) {
+        return vec![err.to_string()];
+    }
+    if bytes.contains(&0) {
+        return vec![String::from("[binary file]")];
+    }
+    String::from_utf8_lossy(&bytes).lines().take(lines).map(|line| line.replace('\t', "    ")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn finds_files() {
+        let chars = |text: &str| text.chars().collect::<Vec<char>>();
+        assert!(glob_match(&chars("[a-c]?.rs"), &chars("b1.rs")));
+        assert!(!glob_match(&chars("*.rs"), &chars("src/main.rs")));
+        let ignore = Gitignore::default()
+            .with_file("", "target/\n*.log\n!keep.log\n/root.txt\n")
+            .with_file("src/", "gen/**\n");
+        assert!(ignore.is_ignored("target", true) && !ignore.is_ignored("target", false));
+        assert!(ignore.is_ignored("logs/a.log", false) && !ignore.is_ignored("logs/keep.log", false));
+        assert!(ignore.is_ignored("root.txt", false) && !ignore.is_ignored("sub/root.txt", false));
+        assert!(ignore.is_ignored("src/gen/x/y.rs", false) && !ignore.is_ignored("gen/y.rs", false));
+
+        let dir = TempDir::new("finder");
+        dir.write(".gitignore", "target/\n");
+        dir.write("README.md", "readme\n");
+        dir.write("src/main.rs", "fn main() {}\n");
+        dir.write("src/lib.rs", "pub mod deep;\n");
+        dir.write("src/deep/finder.rs", "\n");
+        dir.write("target/app", "\n");
+
+        let mut harness = Harness::new(80, 12, "");
+        let open = format!(":Files {}<CR>", dir.path().display());
+        harness.keys(&open).wait_for(|harness| harness.screen().row(11).contains(" 4/4"));
+        harness.keys("mainrs");
+        assert!(harness.screen().row(0).starts_with("> src/main.rs"));
+        assert!(harness.screen().row(0).contains("│ fn main() {}"));
+        assert!(harness.screen().row(11).contains(" 1/4"));
+        harness.keys("<C-v>");
+        assert_eq!(harness.buffer_text(), "fn main() {}");
+        assert!(harness.screen().row(10).starts_with("main.rs"));
+
+        harness.keys(&open).wait_for(|harness| harness.screen().row(11).contains(" 4/4"));
+        harness.keys("lib<CR>");
+        assert_eq!(harness.buffer_text(), "pub mod deep;");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::prelude::*;
+use std::str::FromStr;
+
+pub const SHIFT_WIDTH: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FoldMethod {
+    Manual,
+    Indent,
+    Syntax,
+}
+
+impl FromStr for FoldMethod {
+    type Err = EditorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "manual" => Ok(FoldMethod::Manual),
+            "indent" => Ok(FoldMethod::Indent),
+            "syntax" => Ok(FoldMethod::Syntax),
+            _ => Err(EditorError::InvalidArgument(format!("foldmethod={}", value))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fold {
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+impl Fold {
+    fn contains(&self, line: usize) -> bool {
+        self.start <= line && line <= self.end
+    }
+
+    fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[derive(Default)]
+pub struct Folds {
+    folds: Vec<Fold>,
+}
+
+impl Folds {
+    fn sort(&mut self) {
+        // Outer folds come before the folds nested inside them.
+        self.folds.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+    }
+
+    pub fn create(&mut self, start: usize, end: usize) {
+        let (mut start, mut end) = (start, end);
+
+        // Grow the new fold over any fold it partially overlaps so folds always nest.
+        while let Some(fold) = self.folds.iter().find(|fold| {
+            (fold.start < start && start <= fold.end && fold.end < end)
+                || (start < fold.start && fold.start <= end && end < fold.end)
+        }) {
+            start = cmp::min(start, fold.start);
+            end = cmp::max(end, fold.end);
+        }
+
+        if start < end {
+            self.folds.push(Fold { start, end, closed: true });
+            self.sort();
+        }
+    }
+
+    pub fn delete_at(&mut self, line: usize) {
+        if let Some(idx) = self.innermost_at(line) {
+            self.folds.remove(idx);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.folds.clear();
+    }
+
+    pub fn adjust(&mut self, at: usize, removed: usize, added: usize) {
+        for fold in self.folds.iter_mut() {
+            fold.start = adjust_line(fold.start, at, removed, added).unwrap_or(at);
+            fold.end = match adjust_line(fold.end, at, removed, added) {
+                Some(end) => end,
+                None if at > 0 => at - 1,
+                None => 0,
+            };
+        }
+        self.folds.retain(|fold| fold.start < fold.end);
+        self.sort();
+    }
+
+    pub fn compute<'a>(&mut self, method: &FoldMethod, lines: impl Iterator<Item = &'a str>) {
+        let folds = match method {
+            FoldMethod::Manual => return,
+            FoldMethod::Indent => Self::indent_folds(lines),
+            FoldMethod::Syntax => Self::syntax_folds(lines),
+        };
+
+        // Keep folds the user opened open after recomputing.
+        let open: Vec<usize> = self.folds.iter().filter(|fold| !fold.closed).map(|fold| fold.start).collect();
+        self.folds = folds;
+        self.folds.iter_mut()
+            .filter(|fold| open.contains(&fold.start))
+            .for_each(|fold| fold.closed = false);
+        self.sort();
+    }
+
+    fn indent_folds<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<Fold> {
+        let levels: Vec<Option<usize>> = lines
+            .map(|line| {
+                if line.trim().is_empty() {
+                    return None;
+                }
+                let indent: usize = line.chars()
+                    .take_while(|c| c.is_whitespace())
+                    .map(|c| if c == '\t' { SHIFT_WIDTH } else { 1 })
+                    .sum();
+                Some(indent / SHIFT_WIDTH)
+            })
+            .collect();
+
+        // Blank lines take the lower level of the lines around them.
+        let mut resolved = vec![0; levels.len()];
+        let mut previous = 0;
+        for (idx, level) in levels.iter().enumerate() {
+            resolved[idx] = match level {
+                Some(level) => *level,
+                None => {
+                    let next = levels[idx..].iter().flatten().next().copied().unwrap_or(0);
+                    cmp::min(previous, next)
+                },
+            };
+            if let Some(level) = level {
+                previous = *level;
+            }
+        }
+
+        let mut folds = vec![];
+        let mut open: Vec<usize> = vec![];
+        for (idx, level) in resolved.iter().chain(std::iter::once(&0)).enumerate() {
+            while open.len() > *level {
+                let start = open.pop().unwrap();
+                if start < idx - 1 {
+                    folds.push(Fold { start, end: idx - 1, closed: true });
+                }
+            }
+            while open.len() < *level {
+                open.push(idx);
+            }
+        }
+
+        folds
+    }
+
+    fn syntax_folds<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<Fold> {
+        let mut folds = vec![];
+        let mut open: Vec<usize> = vec![];
+        let mut in_comment = false;
+
+        for (idx, line) in lines.enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut in_string = false;
+            let mut at = 0;
+            while at < chars.len() {
+                let next = chars.get(at + 1);
+                match (in_comment, in_string, chars[at]) {
+                    (true, _, '*') if next == Some(&'/') => {
+                        in_comment = false;
+                        at += 1;
+                    },
+                    (true, _, _) => {},
+                    (_, true, '\\') => at += 1,
+                    (_, true, '"') => in_string = false,
+                    (_, true, _) => {},
+                    (_, _, '"') => in_string = true,
+                    (_, _, '/') if next == Some(&'/') => break,
+                    (_, _, '/') if next == Some(&'*') => {
+                        in_comment = true;
+                        at += 1;
+                    },
+                    (_, _, '\'') => at += Self::char_literal_end(&chars[at..]),
+                    (_, _, '{') => open.push(idx),
+                    (_, _, '}') => {
+                        if let Some(start) = open.pop() {
+                            if start < idx {
+                                folds.push(Fold { start, end: idx, closed: true });
+                            }
+                        }
+                    },
+                    _ => {},
+                }
+                at += 1;
+            }
+        }
+
+        folds
+    }
+
+    // The offset of the quote closing a char literal such as '{' or '\n', or
+    // zero when the quote starts a lifetime instead.
+    fn char_literal_end(chars: &[char]) -> usize {
+        match chars {
+            ['\'', '\\', _, rest @ ..] => rest.iter().position(|c| *c == '\'').map_or(0, |end| end + 3),
+            ['\'', _, '\'', ..] => 2,
+            _ => 0,
+        }
+    }
+
+    fn innermost_at(&self, line: usize) -> Option<usize> {
+        self.folds.iter()
+            .enumerate()
+            .filter(|(_, fold)| fold.contains(line))
+            .min_by_key(|(_, fold)| fold.len())
+            .map(|(idx, _)| idx)
+    }
+
+    pub fn closed_at(&self, line: usize) -> Option<&Fold> {
+        self.folds.iter().find(|fold| fold.closed && fold.contains(line))
+    }
+
+    pub fn level_at(&self, line: usize) -> usize {
+        self.folds.iter().filter(|fold| fold.contains(line)).count()
+    }
+
+    pub fn column_marker(&self, line: usize) -> char {
+        if self.closed_at(line).is_some() {
+            '+'
+        } else if self.folds.iter().any(|fold| fold.start == line) {
+            '-'
+        } else if self.level_at(line) > 0 {
+            '|'
+        } else {
+            ' '
+        }
+    }
+
+    pub fn open(&mut self, line: usize) {
+        if let Some(fold) = self.folds.iter_mut().find(|fold| fold.closed && fold.contains(line)) {
+            fold.closed = false;
+        }
+    }
+
+    pub fn close(&mut self, line: usize) {
+        if let Some(fold) = self.folds.iter_mut()
+            .filter(|fold| !fold.closed && fold.contains(line))
+            .min_by_key(|fold| fold.len()) {
+            fold.closed = true;
+        }
+    }
+
+    pub fn toggle(&mut self, line: usize) {
+        if self.closed_at(line).is_some() {
+            self.open(line);
+        } else {
+            self.close(line);
+        }
+    }
+
+    pub fn set_all(&mut self, closed: bool) {
+        self.folds.iter_mut().for_each(|fold| fold.closed = closed);
+    }
+
+    pub fn next_start(&self, line: usize) -> Option<usize> {
+        self.folds.iter().map(|fold| fold.start).filter(|start| *start > line).min()
+    }
+
+    pub fn previous_end(&self, line: usize) -> Option<usize> {
+        self.folds.iter().map(|fold| fold.end).filter(|end| *end < line).max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+
+    #[test]
+    fn folds_by_syntax_and_indent() {
+        let text = "\
+fn main() {
+    let open = '{';
+    let close = '}'; // a } in a comment
+    /* a { in a
+       block } comment */
+    let quote = '\\'';
+    let escaped = \"\\\"{\";
+}
+fn with<'a>(x: &'a str) {
+    x
+}";
+        let spans = |method: FoldMethod| {
+            let mut folds = Folds::default();
+            folds.compute(&method, text.lines());
+            folds.folds.iter().map(|fold| (fold.start, fold.end)).collect::<Vec<_>>()
+        };
+        assert_eq!(spans(FoldMethod::Syntax), vec![(0, 7), (8, 10)]);
+        assert_eq!(spans(FoldMethod::Indent), vec![(1, 6)]);
+
+        let mut harness = Harness::new(80, 12, text);
+        harness.keys(":set foldmethod=syntax<CR>");
+        assert!(harness.screen().row(0).contains("1 +--  8 lines: fn main() {"));
+        assert!(harness.screen().row(1).contains("9 +--  3 lines: fn with"));
+        harness.keys("j");
+        assert_eq!(harness.cursor(), (8, 0));
+        harness.keys("kzo");
+        assert!(harness.screen().row(1).contains("2     let open = '{';"));
+        assert!(harness.screen().row(8).contains("9 +--  3 lines"));
+        harness.keys("zc");
+        assert!(harness.screen().row(1).contains("9 +--  3 lines"));
+        harness.keys("zR");
+        assert!(harness.screen().row(9).contains("10     x"));
+    }
+}
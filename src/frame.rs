@@ -1,4 +1,46 @@
-use crate::{prelude::*, editor_contents};
+use crate::prelude::*;
+
+const DIFF_FILLER: char = '-';
+const DIFF_FILLER_COLOR: style::Color = style::Color::DarkGrey;
+
+fn diff_background(line: &DiffLine, column: usize) -> style::Color {
+    match line {
+        DiffLine::Added => style::Color::DarkGreen,
+        DiffLine::Changed(ranges) if ranges.iter().any(|(start, end)| *start <= column && column < *end) => style::Color::DarkRed,
+        DiffLine::Changed(_) => style::Color::DarkBlue,
+    }
+}
+
+fn conflict_background(section: ConflictSection) -> style::Color {
+    match section {
+        ConflictSection::Marker => style::Color::DarkGrey,
+        ConflictSection::Ours => style::Color::DarkGreen,
+        ConflictSection::Base => style::Color::DarkMagenta,
+        ConflictSection::Theirs => style::Color::DarkBlue,
+    }
+}
+
+// Columns are byte offsets while every character takes one cell on screen,
+// so laying out rows steps over characters.
+fn advance_chars(line: &str, start: usize, count: usize) -> usize {
+    let start = line.floor_char_boundary(start);
+    line[start..].char_indices().nth(count).map_or(line.len(), |(idx, _)| start + idx)
+}
+
+fn retreat_chars(line: &str, end: usize, count: usize) -> usize {
+    let end = line.floor_char_boundary(end);
+    match count {
+        0 => end,
+        count => line[..end].char_indices().rev().nth(count - 1).map_or(0, |(idx, _)| idx),
+    }
+}
+
+fn chars_between(line: &str, from: usize, to: usize) -> usize {
+    if to <= from {
+        return 0;
+    }
+    line[line.floor_char_boundary(from)..line.floor_char_boundary(to)].chars().count()
+}
 
 #[derive(Debug)]
 pub struct FrameSize {
@@ -18,8 +60,8 @@ impl FrameSize {
             start_line,
             columns,
             lines,
-            gutter_width: GUTTER_WIDTH,
-            text_columns: columns - GUTTER_WIDTH - 1,
+            gutter_width: 0,
+            text_columns: columns - 1,
             text_lines: lines,
         }
     }
@@ -31,60 +73,292 @@ pub struct Frame {
     active_buffer: Buffer,
     line_offset: usize,
     column_offset: usize,
+    scroll_lines: usize,
+    pub options: WindowOptions,
+    folds: Folds,
+    jump_list: JumpList,
+    // Insert mode lets the cursor sit just past the end of the line.
+    inserting: bool,
+    diff: Option<DiffView>,
+    // The 'wrap' option from before diff mode turned it off.
+    wrap_before_diff: bool,
+    // Filler rows above the top line scrolled out of view, with that line.
+    hidden_fillers: (usize, usize),
 }
 
 impl Frame {
-    pub fn new(start_column: usize, start_line: usize ,columns: usize, lines: usize, file: Option<PathBuf>) -> Self {
+    pub fn new(start_column: usize, start_line: usize ,columns: usize, lines: usize, buffer: Buffer) -> Self {
         let size = FrameSize::new(start_column, start_line, columns, lines);
-        Self {
+        let mut frame = Self {
             cursor_controller: CursorController::new((size.text_columns, size.text_lines)),
-            active_buffer: Buffer::new(file),
+            active_buffer: buffer,
             size,
             line_offset: 0,
             column_offset: 0,
+            scroll_lines: 0,
+            options: WindowOptions::default(),
+            folds: Folds::default(),
+            jump_list: JumpList::default(),
+            inserting: false,
+            diff: None,
+            wrap_before_diff: false,
+            hidden_fillers: (0, 0),
+        };
+        frame.update_gutter();
+        frame
+    }
+
+    fn number_width(&self) -> usize {
+        if !self.options.number && !self.options.relativenumber {
+            return 0;
         }
+        let digits = self.active_buffer.number_of_lines().to_string().len();
+        cmp::max(NUMBER_WIDTH, digits + 1)
     }
 
-    pub fn draw_rows(&self) -> crossterm::Result<EditorContents> {
-        let mut editor_contents = EditorContents::new();
+    fn sign_width(&self) -> usize {
+        match self.options.signcolumn {
+            SignColumn::Yes => SIGN_WIDTH,
+            SignColumn::No => 0,
+            SignColumn::Auto if self.active_buffer.signs.is_empty() => 0,
+            SignColumn::Auto => SIGN_WIDTH,
+        }
+    }
+
+    pub fn update_gutter(&mut self) {
+        let gutter_width = self.options.foldcolumn + self.sign_width() + self.number_width();
+        if gutter_width == self.size.gutter_width {
+            return;
+        }
 
-        for i in 0..self.size.text_lines {
-            if let Some(buffer_line) = self.active_buffer.get_line(i + self.line_offset) {
-                let line =  buffer_line.line_slice(self.column_offset, self.column_offset + self.size.text_columns);
+        self.size.gutter_width = gutter_width;
+        self.size.text_columns = cmp::max(self.size.columns.saturating_sub(gutter_width + 1), 1);
+        self.cursor_controller.gutter_width = gutter_width;
+        self.cursor_controller.frame_columns = self.size.text_columns;
+        self.set_cursor_column(self.cursor_column());
+    }
+
+    pub fn signs(&mut self) -> &mut Signs {
+        &mut self.active_buffer.signs
+    }
+
+    fn line_number_label(&self, line_idx: usize, buffer_line: &BufferLine) -> String {
+        let width = self.number_width().saturating_sub(1);
+        let cursor_line = self.cursor_line();
+
+        if width == 0 {
+            String::new()
+        } else if self.options.relativenumber && line_idx != cursor_line {
+            format!("{:>width$} ", cmp::max(line_idx, cursor_line) - cmp::min(line_idx, cursor_line), width = width)
+        } else if self.options.relativenumber && !self.options.number {
+            format!("{:>width$} ", 0, width = width)
+        } else if self.options.relativenumber {
+            format!("{:<width$} ", buffer_line.line_number, width = width)
+        } else {
+            format!("{:>width$} ", buffer_line.line_number, width = width)
+        }
+    }
+
+    fn draw_gutter(&self, editor_contents: &mut EditorContents, line_idx: usize, row: usize) -> crossterm::Result<()> {
+        let sign_width = self.sign_width();
+        let number_width = self.number_width();
 
-                let render_line = format!(
-                    "{:width$} {}",
-                    buffer_line.line_number,
-                    &line,
-                    width = self.size.gutter_width - 1,
-                );
-                
+        if self.options.foldcolumn > 0 {
+            let marker = match self.folds.column_marker(line_idx) {
+                '+' | '-' if row > 0 => '|',
+                marker => marker,
+            };
+            queue!(
+                editor_contents,
+                style::Print(format!("{:width$}", marker, width = self.options.foldcolumn)),
+            )?;
+        }
+
+        match self.active_buffer.signs.get(line_idx).filter(|_| row == 0 && sign_width > 0) {
+            Some(sign) => {
+                if let Some(color) = sign.color {
+                    queue!(editor_contents, style::SetForegroundColor(color))?;
+                }
+                queue!(
+                    editor_contents,
+                    style::Print(format!("{:width$}", sign.text, width = sign_width)),
+                    style::ResetColor,
+                )?;
+            },
+            None => queue!(editor_contents, style::Print(" ".repeat(sign_width)))?,
+        }
 
+        match self.active_buffer.get_line(line_idx) {
+            Some(buffer_line) if row == 0 => {
+                queue!(editor_contents, style::Print(self.line_number_label(line_idx, buffer_line)))?;
+            },
+            _ if number_width > 0 => {
+                queue!(
+                    editor_contents,
+                    style::Print(format!("{:>width$} ", WRAP_MARKER, width = number_width - 1)),
+                )?;
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+
+    fn diagnostic_ranges(&self, line_idx: usize, buffer_line: &BufferLine) -> Vec<(usize, usize)> {
+        self.active_buffer.diagnostics.iter()
+            .filter(|diagnostic| diagnostic.covers_line(line_idx))
+            .map(|diagnostic| {
+                let start = if diagnostic.start.line == line_idx { diagnostic.start.column } else { 0 };
+                let end = if diagnostic.end.line == line_idx { diagnostic.end.column } else { buffer_line.line.len() };
+                // Diagnostics without a width still mark the character they point at.
+                (start, cmp::max(end, start + 1))
+            })
+            .collect()
+    }
+
+    fn draw_text(&self, editor_contents: &mut EditorContents, line_idx: usize, buffer_line: &BufferLine, start: usize, end: usize, width: usize) -> crossterm::Result<()> {
+        let text = buffer_line.line_slice(start, end);
+        let ranges = self.diagnostic_ranges(line_idx, buffer_line);
+        let diff_line = self.diff.as_ref().and_then(|diff| diff.line(line_idx));
+        let section = self.active_buffer.conflict_section(line_idx);
+        let background_at = |column| match diff_line {
+            Some(line) => Some(diff_background(line, column)),
+            None => section.map(conflict_background),
+        };
+        if ranges.is_empty() && background_at(usize::MAX).is_none() {
+            return queue!(editor_contents, style::Print(text));
+        }
+
+        let mut underlined = false;
+        let mut background = None;
+        let mut pending = String::new();
+        for (idx, c) in text.char_indices() {
+            let column = start + idx;
+            let inside = ranges.iter().any(|(from, to)| *from <= column && column < *to);
+            if inside != underlined {
+                let attribute = if inside { style::Attribute::Underlined } else { style::Attribute::NoUnderline };
+                queue!(editor_contents, style::Print(std::mem::take(&mut pending)), style::SetAttribute(attribute))?;
+                underlined = inside;
+            }
+            let color = background_at(column);
+            if color != background {
+                queue!(editor_contents, style::Print(std::mem::take(&mut pending)))?;
+                queue!(editor_contents, style::SetBackgroundColor(color.unwrap_or(style::Color::Reset)))?;
+                background = color;
+            }
+            pending.push(c);
+        }
+        queue!(editor_contents, style::Print(pending), style::SetAttribute(style::Attribute::NoUnderline))?;
+        // Diff and conflict highlights run to the edge of the frame.
+        if let Some(color) = background_at(usize::MAX) {
+            queue!(
+                editor_contents,
+                style::SetBackgroundColor(color),
+                style::Print(" ".repeat(width.saturating_sub(text.chars().count()))),
+                style::ResetColor,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn draw_filler(&self, editor_contents: &mut EditorContents, row: usize) -> crossterm::Result<()> {
+        queue!(
+            editor_contents,
+            cursor::MoveTo((self.size.start_column) as u16, (self.size.start_line + row) as u16),
+            style::Print(" ".repeat(self.size.gutter_width)),
+            style::SetForegroundColor(DIFF_FILLER_COLOR),
+            style::Print(DIFF_FILLER.to_string().repeat(self.size.text_columns)),
+            style::ResetColor,
+            cursor::MoveTo((self.size.start_column + self.size.gutter_width + self.size.text_columns) as u16, (self.size.start_line + row) as u16),
+            style::SetAttribute(style::Attribute::Reverse),
+            style::Print(' '),
+            style::SetAttribute(style::Attribute::Reset),
+        )
+    }
+
+    pub fn draw_rows(&self) -> crossterm::Result<EditorContents> {
+        let mut editor_contents = EditorContents::new();
+        let mut line_idx = self.line_offset;
+        let mut i = 0;
+        let mut end_fillers = self.filler_rows(self.active_buffer.number_of_lines());
+
+        while i < self.size.text_lines {
+            if let Some(fold) = self.folds.closed_at(line_idx) {
                 queue!(
                     editor_contents,
                     cursor::MoveTo((self.size.start_column) as u16, (self.size.start_line + i) as u16),
-                    style::Print(&render_line),
-                    cursor::SavePosition,
-                    cursor::MoveTo((self.size.columns) as u16, i as u16),
+                )?;
+                self.draw_gutter(&mut editor_contents, line_idx, 0)?;
+                queue!(
+                    editor_contents,
+                    style::Print(self.fold_summary(fold)),
+                    cursor::MoveTo((self.size.start_column + self.size.gutter_width + self.size.text_columns) as u16, (self.size.start_line + i) as u16),
                     style::SetAttribute(style::Attribute::Reverse),
                     style::Print(' '),
                     style::SetAttribute(style::Attribute::Reset),
                 )?;
+                line_idx = fold.end + 1;
+                i += 1;
+            } else if let Some(buffer_line) = self.active_buffer.get_line(line_idx) {
+                let (segments, indent) = self.wrap_segments(&buffer_line.line);
+                let hidden = if line_idx == self.line_offset { self.hidden_fillers() } else { 0 };
+                for _ in hidden..self.filler_rows(line_idx) {
+                    if i >= self.size.text_lines {
+                        break;
+                    }
+                    self.draw_filler(&mut editor_contents, i)?;
+                    i += 1;
+                }
 
+                for (row, (start, end)) in segments.into_iter().enumerate() {
+                    if i >= self.size.text_lines {
+                        break;
+                    }
+
+                    let (text_start, text_end, indent) = if row == 0 {
+                        let text_start = buffer_line.line.floor_char_boundary(start + self.column_offset);
+                        (text_start, cmp::min(end, advance_chars(&buffer_line.line, text_start, self.size.text_columns)), 0)
+                    } else {
+                        (start, end, indent)
+                    };
+
+                    queue!(
+                        editor_contents,
+                        cursor::MoveTo((self.size.start_column) as u16, (self.size.start_line + i) as u16),
+                    )?;
+                    self.draw_gutter(&mut editor_contents, line_idx, row)?;
+                    queue!(editor_contents, style::Print(" ".repeat(indent)))?;
+                    self.draw_text(&mut editor_contents, line_idx, buffer_line, text_start, text_end, self.size.text_columns - indent)?;
+                    queue!(
+                        editor_contents,
+                        cursor::SavePosition,
+                        cursor::MoveTo((self.size.start_column + self.size.gutter_width + self.size.text_columns) as u16, (self.size.start_line + i) as u16),
+                        style::SetAttribute(style::Attribute::Reverse),
+                        style::Print(' '),
+                        style::SetAttribute(style::Attribute::Reset),
+                    )?;
+                    i += 1;
+                }
+                line_idx += 1;
+            } else if end_fillers > 0 {
+                self.draw_filler(&mut editor_contents, i)?;
+                end_fillers -= 1;
+                i += 1;
             } else {
                 queue!(
                     editor_contents,
+                    cursor::MoveTo((self.size.start_column) as u16, (self.size.start_line + i) as u16),
                     style::Print("~"),
-                    cursor::MoveToNextLine(1),
                 )?;
+                i += 1;
             }
-
         }
 
         Ok(editor_contents)
     }
 
-    pub fn draw_status_bar(&self) -> crossterm::Result<EditorContents> {
+    // The indicator, e.g. for a running build, is shown at the right end.
+    pub fn draw_status_bar(&self, indicator: &str) -> crossterm::Result<EditorContents> {
         let mut editor_contents = EditorContents::new();
 
         editor_contents.push_str(&style::Attribute::Reverse.to_string());
@@ -92,23 +366,42 @@ impl Frame {
         let filename = self.active_buffer.file_path.as_ref()
             .and_then(|path| path.file_name())
             .and_then(|name| name.to_str())
+            .or(self.active_buffer.name.as_deref())
             .unwrap_or("[No name]");
 
         let render_line = format!(
-            "{} -- {} lines  {}/{} -- {}/{}",
+            "{}{}{}{}{}{} -- {}{} lines [{}]  {}/{} -- {}/{}",
             filename,
+            if self.active_buffer.modified { " [+]" } else { "" },
+            if self.active_buffer.modifiable { "" } else { " [-]" },
+            if self.active_buffer.read_only { " [RO]" } else { "" },
+            self.active_buffer.git.as_ref().map_or(String::new(), |git| format!(" [{}]", git.branch)),
+            match self.active_buffer.conflicts.len() {
+                0 => String::new(),
+                1 => " [1 conflict]".to_string(),
+                count => format!(" [{} conflicts]", count),
+            },
             self.active_buffer.number_of_lines(),
-            self.cursor_controller.position.line + self.line_offset + 1,
-            self.cursor_controller.position.column + 1,
+            if self.active_buffer.is_indexing() { "+" } else { "" },
+            self.active_buffer.format_info(),
+            self.cursor_line() + 1,
+            self.cursor_column() + 1,
             self.cursor_controller.frame_columns,
             self.cursor_controller.frame_lines,
         );
 
+        let indicator = if indicator.is_empty() { String::new() } else { format!(" {} ", indicator) };
+        let indicator: String = indicator.chars().take(self.size.columns).collect();
+        let width = self.size.columns - indicator.chars().count();
+        let render_line: String = render_line.chars().take(width).collect();
+        let padding = width.saturating_sub(render_line.chars().count());
+
         queue!( 
             editor_contents,
-            cursor::MoveTo((self.size.start_column) as u16, (self.size.lines) as u16),
+            cursor::MoveTo((self.size.start_column) as u16, (self.size.start_line + self.size.lines) as u16),
             style::Print(&render_line),
-            style::Print(" ".repeat(self.size.columns - render_line.len())),
+            style::Print(" ".repeat(padding)),
+            style::Print(indicator),
             style::SetAttribute(style::Attribute::Reset),
         )?;
 
@@ -116,96 +409,871 @@ impl Frame {
         Ok(editor_contents)
     }
 
-    pub fn clear_screen() -> crossterm::Result<()> {
-        execute!(stdout(), terminal::Clear(ClearType::All))?;
-        execute!(stdout(), cursor::MoveTo(GUTTER_WIDTH as u16, 0 as u16))
+    fn current_buffer_line(&self) -> Option<&BufferLine> {
+        self.active_buffer.get_line(self.cursor_line())
+    }
+
+    fn cursor_line(&self) -> usize {
+        self.cursor_controller.text_position.line
     }
 
-    fn current_buffer_line(&self) -> Option<&BufferLine> {
-        self.active_buffer.get_line(self.cursor_controller.position.line + self.line_offset)
+    fn cursor_column(&self) -> usize {
+        self.cursor_controller.text_position.column
     }
 
-    fn snap_to_eol(&mut self) {
-        if let Some(current_line) = self.current_buffer_line() {
-            if current_line.line.len() == 0 {
-                self.cursor_controller.move_cursor_to_column(0);
-            } else if self.cursor_controller.position.column > current_line.line.len() - 1 {
-                self.cursor_controller.move_cursor_to_column(current_line.line.len() - 1);
+    fn last_line(&self) -> usize {
+        self.active_buffer.number_of_lines().saturating_sub(1)
+    }
+
+    fn scrolloff(&self) -> usize {
+        cmp::min(CURSOR_MARGIN, self.size.text_lines.saturating_sub(1) / 2)
+    }
+
+    pub fn set_option(&mut self, arg: &str) -> EditorResult<()> {
+        let foldmethod = self.options.foldmethod;
+        match self.options.set(arg) {
+            Err(EditorError::UnknownOption(_)) => return self.active_buffer.set_option(arg),
+            result => result?,
+        }
+        if self.options.foldmethod != foldmethod {
+            self.update_folds();
+            self.cursor_controller.text_position.line = self.fold_start(self.cursor_line());
+        }
+        if self.options.wrap {
+            self.column_offset = 0;
+        }
+        self.update_gutter();
+        self.set_cursor_line(self.cursor_line());
+        Ok(())
+    }
+
+    fn wrap_segments(&self, line: &str) -> (Vec<(usize, usize)>, usize) {
+        let width = self.size.text_columns;
+        if !self.options.wrap || line.len() <= width || line.chars().count() <= width {
+            return (vec![(0, line.len())], 0);
+        }
+
+        let indent = if self.options.breakindent {
+            // Keep at least half of the text area for the continuation rows.
+            cmp::min(line.chars().take_while(|c| c.is_whitespace()).count(), width / 2)
+        } else {
+            0
+        };
+
+        let mut segments = vec![];
+        let mut start = 0;
+        while start < line.len() {
+            let row_width = if segments.is_empty() { width } else { width - indent };
+            let mut end = advance_chars(line, start, row_width);
+
+            if self.options.linebreak && end < line.len() {
+                if let Some(idx) = line[start..end].rfind(char::is_whitespace) {
+                    if idx > 0 {
+                        end = start + idx + 1;
+                    }
+                }
             }
+
+            segments.push((start, end));
+            start = end;
+        }
+
+        (segments, indent)
+    }
+
+    pub fn update_folds(&mut self) {
+        // Computing folds needs every line, which large buffers never hold.
+        if self.active_buffer.is_large() {
+            return;
         }
+        self.folds.compute(&self.options.foldmethod, self.active_buffer.text_lines());
     }
 
-    pub fn move_cursor_down(&mut self) {
-        if self.cursor_controller.position.line >= self.size.text_lines - 1 {
-            self.line_offset += 1;
+    fn fold_summary(&self, fold: &Fold) -> String {
+        let text = self.active_buffer.get_line(fold.start).map_or("", |line| line.line.trim());
+        let summary = format!(
+            "+-{}{:>3} lines: {}",
+            "-".repeat(self.folds.level_at(fold.start)),
+            fold.end - fold.start + 1,
+            text,
+        );
+        format!("{:-<width$}", summary, width = self.size.text_columns)
+            .chars()
+            .take(self.size.text_columns)
+            .collect()
+    }
+
+    fn fold_start(&self, line_idx: usize) -> usize {
+        self.folds.closed_at(line_idx).map_or(line_idx, |fold| fold.start)
+    }
+
+    fn next_visible_line(&self, line_idx: usize) -> Option<usize> {
+        let next = self.folds.closed_at(line_idx).map_or(line_idx, |fold| fold.end) + 1;
+        if next > self.last_line() {
+            None
+        } else {
+            Some(next)
+        }
+    }
+
+    fn previous_visible_line(&self, line_idx: usize) -> Option<usize> {
+        let start = self.fold_start(line_idx);
+        if start == 0 {
+            None
         } else {
-            self.cursor_controller.move_cursor_down();
+            Some(self.fold_start(start - 1))
+        }
+    }
+
+    fn step_visible_lines(&self, line_idx: usize, count: usize, forward: bool) -> usize {
+        let mut line_idx = self.fold_start(line_idx);
+        for _ in 0..count {
+            let next = if forward {
+                self.next_visible_line(line_idx)
+            } else {
+                self.previous_visible_line(line_idx)
+            };
+            match next {
+                Some(next) => line_idx = next,
+                None => break,
+            }
+        }
+        line_idx
+    }
+
+    fn line_rows(&self, line_idx: usize) -> usize {
+        match self.folds.closed_at(line_idx) {
+            Some(fold) if fold.start == line_idx => 1,
+            Some(_) => 0,
+            None => self.active_buffer.get_line(line_idx)
+                .map_or(1, |line| self.filler_rows(line_idx) + self.wrap_segments(&line.line).0.len()),
+        }
+    }
+
+    fn rows_between(&self, from: usize, to: usize) -> usize {
+        (from..to).map(|line_idx| self.line_rows(line_idx)).sum()
+    }
+
+    fn position_in_line(&self, line_idx: usize, column: usize) -> (usize, usize) {
+        if self.folds.closed_at(line_idx).is_some() {
+            return (0, 0);
+        }
+
+        match self.active_buffer.get_line(line_idx) {
+            Some(line) => {
+                let (segments, indent) = self.wrap_segments(&line.line);
+                let row = segments.iter().rposition(|(start, _)| *start <= column).unwrap_or(0);
+                let indent = if row == 0 { 0 } else { indent };
+                (row, indent + chars_between(&line.line, cmp::max(segments[row].0, self.column_offset), column))
+            },
+            None => (0, column.saturating_sub(self.column_offset)),
+        }
+    }
+
+    fn column_at_row(&self, line_idx: usize, row: usize, screen_column: usize) -> usize {
+        match self.active_buffer.get_line(line_idx) {
+            Some(line) => {
+                let (segments, indent) = self.wrap_segments(&line.line);
+                let row = cmp::min(row, segments.len() - 1);
+                let (start, end) = segments[row];
+                let indent = if row == 0 { 0 } else { indent };
+                let last_column = line.line[start..end].char_indices().last().map_or(start, |(idx, _)| start + idx);
+                cmp::min(advance_chars(&line.line, start, screen_column.saturating_sub(indent)), last_column)
+            },
+            None => 0,
+        }
+    }
+
+    fn last_visible_line(&self) -> usize {
+        let mut rows = 0;
+        let mut line_idx = self.line_offset;
+        while let Some(next) = self.next_visible_line(line_idx) {
+            rows += self.line_rows(line_idx);
+            if rows + self.line_rows(next) > self.size.text_lines {
+                break;
+            }
+            line_idx = next;
+        }
+        line_idx
+    }
+
+    fn offset_with_rows_above(&self, line_idx: usize, rows: usize) -> usize {
+        let mut offset = line_idx;
+        let mut above = 0;
+        while offset > 0 && above + self.line_rows(offset - 1) <= rows {
+            offset -= 1;
+            above += self.line_rows(offset);
+        }
+        self.fold_start(offset)
+    }
+
+    fn rows_after(&self, line_idx: usize, row: usize, limit: usize) -> usize {
+        let below = self.line_rows(line_idx) - 1 - row;
+        let next = cmp::min(line_idx + 1 + limit, self.last_line() + 1);
+        cmp::min(below + self.rows_between(line_idx + 1, next), limit)
+    }
+
+    fn update_screen_position(&mut self) {
+        let line = self.cursor_line();
+        let (row, screen_column) = self.position_in_line(line, self.cursor_column());
+        let screen_line = (self.rows_between(self.line_offset, line) + self.filler_rows(line) + row).saturating_sub(self.hidden_fillers());
+
+        self.cursor_controller.move_cursor_to_line(cmp::min(screen_line, self.size.text_lines - 1));
+        self.cursor_controller.move_cursor_to_column(screen_column);
+    }
+
+    fn scroll_to_cursor(&mut self) {
+        let line = self.cursor_line();
+        let scrolloff = self.scrolloff();
+        let (row, _) = self.position_in_line(line, self.cursor_column());
+        let row = self.filler_rows(line) + row;
+
+        if line < self.line_offset {
+            self.line_offset = line;
+        } else if line - self.line_offset > self.size.text_lines {
+            // Skip over lines that can't be on screen anyway instead of scrolling one line at a time.
+            self.line_offset = cmp::max(self.line_offset, self.offset_with_rows_above(line, self.size.text_lines));
         }
+        self.line_offset = self.fold_start(self.line_offset);
+
+        while self.rows_between(self.line_offset, line) + row < scrolloff {
+            match self.previous_visible_line(self.line_offset) {
+                Some(previous) => self.line_offset = previous,
+                None => break,
+            }
+        }
+
+        let rows_after = self.rows_after(line, row, scrolloff);
+        while self.line_offset < line
+            && self.rows_between(self.line_offset, line) + row + rows_after >= self.size.text_lines {
+            self.line_offset = self.step_visible_lines(self.line_offset, 1, true);
+        }
+    }
+
+    fn set_cursor_line(&mut self, line: usize) {
+        self.cursor_controller.text_position.line = self.fold_start(cmp::min(line, self.last_line()));
         self.snap_to_eol();
+        self.scroll_to_cursor();
+        self.update_screen_position();
     }
 
-    pub fn move_cursor_up(&mut self) {
-        if self.cursor_controller.position.line <= 0 {
-            if self.line_offset == 0 {
-                return
+    fn set_line_offset(&mut self, offset: usize) {
+        let offset = self.fold_start(cmp::min(offset, self.last_line()));
+        let scrolloff = self.scrolloff();
+        let top_margin = if offset == 0 { 0 } else { scrolloff };
+        let mut line = self.fold_start(cmp::min(cmp::max(self.cursor_line(), offset), offset + self.size.text_lines));
+
+        while self.rows_between(offset, line) < top_margin {
+            match self.next_visible_line(line) {
+                Some(next) => line = next,
+                None => break,
             }
-            self.line_offset -= 1;
-        } else {
-            self.cursor_controller.move_cursor_up();
         }
+        while line > offset
+            && self.rows_between(offset, line + 1) + self.rows_after(line, self.line_rows(line) - 1, scrolloff) > self.size.text_lines {
+            line = self.previous_visible_line(line).unwrap_or(offset);
+        }
+
+        self.line_offset = offset;
+        self.cursor_controller.text_position.line = line;
         self.snap_to_eol();
+        self.update_screen_position();
+    }
+
+    fn set_cursor_column(&mut self, column: usize) {
+        if !self.options.wrap {
+            let line = self.current_line_text().unwrap_or("");
+            if column < self.column_offset {
+                self.column_offset = column;
+            } else if chars_between(line, self.column_offset, column) >= self.size.text_columns {
+                self.column_offset = retreat_chars(line, column, self.size.text_columns.saturating_sub(1));
+            }
+        }
+
+        self.cursor_controller.text_position.column = column;
+        self.scroll_to_cursor();
+        self.update_screen_position();
+    }
+
+    fn set_column_offset(&mut self, offset: usize) {
+        if self.options.wrap {
+            return;
+        }
+
+        let line = self.current_line_text().unwrap_or("");
+        let offset = line.floor_char_boundary(cmp::min(offset, line.len().saturating_sub(1)));
+        let last_column = advance_chars(line, offset, self.size.text_columns.saturating_sub(1));
+        let column = line.floor_char_boundary(cmp::min(cmp::max(self.cursor_column(), offset), last_column));
+
+        self.column_offset = offset;
+        self.cursor_controller.text_position.column = column;
+        self.update_screen_position();
+    }
+
+    // Keeps the cursor on the line and at the start of a character, since
+    // columns are byte offsets.
+    fn snap_to_eol(&mut self) {
+        let column = match self.current_buffer_line().map(|buffer_line| &buffer_line.line) {
+            Some(line) => {
+                let last_column = if self.inserting { line.len() } else { line.char_indices().last().map_or(0, |(idx, _)| idx) };
+                let column = cmp::min(self.cursor_column(), last_column);
+                (0..=column).rev().find(|idx| line.is_char_boundary(*idx)).unwrap_or(0)
+            },
+            None => return,
+        };
+        if column != self.cursor_column() {
+            self.set_cursor_column(column);
+        }
+    }
+
+    pub fn move_cursor_down(&mut self) {
+        if let Some(next) = self.next_visible_line(self.cursor_line()) {
+            self.set_cursor_line(next);
+        }
+    }
+
+    pub fn move_cursor_up(&mut self) {
+        if let Some(previous) = self.previous_visible_line(self.cursor_line()) {
+            self.set_cursor_line(previous);
+        }
+    }
+
+    pub fn move_cursor_display_down(&mut self) {
+        if !self.options.wrap {
+            return self.move_cursor_down();
+        }
+
+        let line = self.cursor_line();
+        let (row, screen_column) = self.position_in_line(line, self.cursor_column());
+        if row + 1 + self.filler_rows(line) < self.line_rows(line) {
+            self.set_cursor_column(self.column_at_row(line, row + 1, screen_column));
+        } else if let Some(next) = self.next_visible_line(line) {
+            self.cursor_controller.text_position.line = next;
+            self.set_cursor_column(self.column_at_row(next, 0, screen_column));
+        }
+    }
+
+    pub fn move_cursor_display_up(&mut self) {
+        if !self.options.wrap {
+            return self.move_cursor_up();
+        }
+
+        let line = self.cursor_line();
+        let (row, screen_column) = self.position_in_line(line, self.cursor_column());
+        if row > 0 {
+            self.set_cursor_column(self.column_at_row(line, row - 1, screen_column));
+        } else if let Some(previous) = self.previous_visible_line(line) {
+            self.cursor_controller.text_position.line = previous;
+            self.set_cursor_column(self.column_at_row(previous, self.line_rows(previous) - 1, screen_column));
+        }
     }
 
     pub fn move_cursor_left(&mut self) {
-        if self.cursor_controller.position.column == 0 {
-            if self.column_offset == 0 {
-                return
+        let column = self.cursor_column();
+        let previous = self.current_buffer_line()
+            .and_then(|buffer_line| buffer_line.line.get(..column))
+            .and_then(|before| before.char_indices().last());
+        if let Some((idx, _)) = previous {
+            self.set_cursor_column(idx);
+        }
+    }
+
+    pub fn move_cursor_right(&mut self) {
+        let column = self.cursor_column();
+        let next = self.current_buffer_line()
+            .and_then(|buffer_line| buffer_line.line.get(column..))
+            .and_then(|rest| rest.char_indices().nth(1));
+        if let Some((idx, _)) = next {
+            self.set_cursor_column(column + idx);
+        }
+    }
+
+    pub fn scroll(&mut self, scroll: Scroll, count: Option<usize>) {
+        let line = self.cursor_line();
+        let text_lines = self.size.text_lines;
+        let page = cmp::max(text_lines.saturating_sub(2), 1) * count.unwrap_or(1);
+
+        match scroll {
+            Scroll::HalfPageDown | Scroll::HalfPageUp => {
+                if let Some(count) = count {
+                    self.scroll_lines = count;
+                }
+                let amount = match self.scroll_lines {
+                    0 => cmp::max(text_lines / 2, 1),
+                    lines => lines,
+                };
+
+                if let Scroll::HalfPageDown = scroll {
+                    let bottom_offset = cmp::max((self.last_line() + 1).saturating_sub(text_lines), self.line_offset);
+                    self.line_offset = self.fold_start(cmp::min(self.step_visible_lines(self.line_offset, amount, true), bottom_offset));
+                    self.set_cursor_line(self.step_visible_lines(line, amount, true));
+                } else {
+                    self.line_offset = self.step_visible_lines(self.line_offset, amount, false);
+                    self.set_cursor_line(self.step_visible_lines(line, amount, false));
+                }
+            },
+            Scroll::PageDown => self.set_line_offset(self.step_visible_lines(self.line_offset, page, true)),
+            Scroll::PageUp => self.set_line_offset(self.step_visible_lines(self.line_offset, page, false)),
+            Scroll::LineDown => {
+                self.set_line_offset(self.step_visible_lines(self.line_offset, count.unwrap_or(1), true))
+            },
+            Scroll::LineUp => {
+                self.set_line_offset(self.step_visible_lines(self.line_offset, count.unwrap_or(1), false))
+            },
+            Scroll::CursorToTop | Scroll::CursorToCenter | Scroll::CursorToBottom => {
+                if let Some(count) = count {
+                    self.set_cursor_line(count - 1);
+                }
+                let line = self.cursor_line();
+                let (row, _) = self.position_in_line(line, self.cursor_column());
+                let row = self.filler_rows(line) + row;
+                let rows_above = match scroll {
+                    Scroll::CursorToTop => self.scrolloff(),
+                    Scroll::CursorToCenter => text_lines.saturating_sub(self.line_rows(line)) / 2,
+                    _ => text_lines
+                        .saturating_sub(self.line_rows(line) + self.rows_after(line, row, self.scrolloff())),
+                };
+                let offset = self.offset_with_rows_above(line, rows_above);
+                self.set_line_offset(offset);
+            },
+            Scroll::ViewLeft => {
+                let offset = retreat_chars(self.current_line_text().unwrap_or(""), self.column_offset, count.unwrap_or(1));
+                self.set_column_offset(offset)
+            },
+            Scroll::ViewRight => {
+                let offset = advance_chars(self.current_line_text().unwrap_or(""), self.column_offset, count.unwrap_or(1));
+                self.set_column_offset(offset)
+            },
+            Scroll::CursorToScreenStart => self.set_column_offset(self.cursor_column()),
+            Scroll::CursorToScreenEnd => {
+                let text_columns = self.size.text_columns;
+                let offset = retreat_chars(self.current_line_text().unwrap_or(""), self.cursor_column(), text_columns.saturating_sub(1));
+                self.set_column_offset(offset)
+            },
+        }
+    }
+
+    pub fn jump_to_screen_line(&mut self, screen_line: ScreenLine, count: Option<usize>) {
+        let scrolloff = self.scrolloff();
+        let last_visible = self.last_visible_line();
+        let from_edge = count.unwrap_or(1).saturating_sub(1);
+
+        let line = match screen_line {
+            ScreenLine::Top => {
+                let margin = if self.line_offset == 0 { 0 } else { scrolloff };
+                self.step_visible_lines(self.line_offset, cmp::max(from_edge, margin), true)
+            },
+            ScreenLine::Middle => {
+                let mut visible = 0;
+                let mut line_idx = self.line_offset;
+                while line_idx < last_visible {
+                    visible += 1;
+                    line_idx = self.step_visible_lines(line_idx, 1, true);
+                }
+                self.step_visible_lines(self.line_offset, visible / 2, true)
+            },
+            ScreenLine::Bottom => {
+                let margin = if self.next_visible_line(last_visible).is_none() { 0 } else { scrolloff };
+                cmp::max(self.step_visible_lines(last_visible, cmp::max(from_edge, margin), false), self.line_offset)
+            },
+        };
+
+        self.set_cursor_line(line);
+    }
+
+    pub fn fold(&mut self, command: FoldCommand, count: usize) {
+        let line = self.cursor_line();
+
+        match command {
+            FoldCommand::Toggle => self.folds.toggle(line),
+            FoldCommand::Open => (0..count).for_each(|_| self.folds.open(line)),
+            FoldCommand::Close => (0..count).for_each(|_| self.folds.close(line)),
+            FoldCommand::OpenAll => self.folds.set_all(false),
+            FoldCommand::CloseAll => self.folds.set_all(true),
+            FoldCommand::NextStart | FoldCommand::PreviousEnd => {
+                let mut target = line;
+                for _ in 0..count {
+                    let next = match command {
+                        FoldCommand::NextStart => self.folds.next_start(target),
+                        _ => self.folds.previous_end(target),
+                    };
+                    match next {
+                        Some(next) => target = next,
+                        None => break,
+                    }
+                }
+                self.cursor_controller.text_position.line = target;
+            },
+            FoldCommand::Create => self.folds.create(line, cmp::min(line + count - 1, self.last_line())),
+            FoldCommand::Delete => self.folds.delete_at(line),
+            FoldCommand::DeleteAll => self.folds.clear(),
+        }
+
+        self.set_cursor_line(self.cursor_line());
+    }
+
+    pub fn create_fold(&mut self, motion: Motion) {
+        let line = self.cursor_line();
+        let target = match motion {
+            Motion::Down(count) => self.step_visible_lines(line, count, true),
+            Motion::Up(count) => self.step_visible_lines(line, count, false),
+        };
+        let start = cmp::min(line, target);
+        let end = self.folds.closed_at(cmp::max(line, target)).map_or(cmp::max(line, target), |fold| fold.end);
+
+        self.folds.create(start, end);
+        self.set_cursor_line(start);
+    }
+
+    pub fn file_path(&self) -> Option<&PathBuf> {
+        self.active_buffer.file_path.as_ref()
+    }
+
+    pub fn buffer_name(&self) -> String {
+        self.active_buffer.display_name()
+    }
+
+    pub fn buffer(&mut self) -> &mut Buffer {
+        &mut self.active_buffer
+    }
+
+    pub fn replace_buffer(&mut self, buffer: Buffer) -> Buffer {
+        let previous = std::mem::replace(&mut self.active_buffer, buffer);
+        self.cursor_controller.text_position = CursorPosition::default();
+        self.line_offset = 0;
+        self.column_offset = 0;
+        // A diff is computed again for the new buffer.
+        if let Some(diff) = self.diff.as_mut() {
+            *diff = DiffView::default();
+        }
+        self.folds.clear();
+        self.update_folds();
+        self.update_gutter();
+        self.set_cursor_line(0);
+        previous
+    }
+
+    pub fn resize(&mut self, start_column: usize, start_line: usize, columns: usize, lines: usize) {
+        self.size = FrameSize::new(start_column, start_line, columns, lines);
+        self.cursor_controller.frame_columns = self.size.text_columns;
+        self.cursor_controller.frame_lines = self.size.text_lines;
+        self.cursor_controller.gutter_width = 0;
+        self.update_gutter();
+        self.set_cursor_line(self.cursor_line());
+    }
+
+    pub fn load_visible_lines(&mut self) {
+        let start = cmp::min(self.line_offset, self.cursor_line());
+        let end = cmp::max(self.line_offset + self.size.text_lines, self.cursor_line() + 1);
+        self.active_buffer.load_lines(start, end);
+    }
+
+    pub fn edit(&mut self, file: PathBuf, encoding: Option<FileEncoding>) -> EditorResult<()> {
+        let buffer = Buffer::open_with(file, encoding, self.options.largefile)?;
+        self.replace_buffer(buffer);
+        Ok(())
+    }
+
+    pub fn reload(&mut self, encoding: Option<FileEncoding>) -> EditorResult<()> {
+        self.active_buffer.reload(encoding)?;
+        self.lines_changed(0, 0, 0);
+        self.move_to(self.cursor_position());
+        Ok(())
+    }
+
+    pub fn toggle_hex(&mut self) -> EditorResult<()> {
+        self.active_buffer.toggle_hex()?;
+        self.lines_changed(0, 0, 0);
+        self.move_to(CursorPosition { line: 0, column: 0 });
+        Ok(())
+    }
+
+    pub fn replace_chars(&mut self, c: char, count: usize) -> EditorResult<()> {
+        let position = self.active_buffer.replace_chars(self.cursor_line(), self.cursor_column(), c, count)?;
+        self.move_to(position);
+        Ok(())
+    }
+
+    pub fn replace_lines(&mut self, start: usize, count: usize, lines: &[String]) {
+        self.active_buffer.replace_lines(start, count, lines);
+        self.lines_changed(0, 0, 0);
+        self.move_to(CursorPosition { line: start, column: 0 });
+    }
+
+    pub fn line_offset(&self) -> usize {
+        self.line_offset
+    }
+
+    // Shows the same lines as a frame this one is kept aligned with.
+    pub fn align_to(&mut self, line_offset: usize, line: usize) {
+        self.move_to(CursorPosition { line, column: 0 });
+        self.line_offset = cmp::min(line_offset, self.cursor_line());
+    }
+
+    fn filler_rows(&self, line_idx: usize) -> usize {
+        self.diff.as_ref().map_or(0, |diff| diff.fillers(line_idx))
+    }
+
+    fn hidden_fillers(&self) -> usize {
+        match self.hidden_fillers {
+            (line, count) if line == self.line_offset => count,
+            _ => 0,
+        }
+    }
+
+    pub fn diff(&self) -> Option<&DiffView> {
+        self.diff.as_ref()
+    }
+
+    // Diff mode lines rows up across frames, so long lines are not wrapped.
+    pub fn start_diff(&mut self) -> EditorResult<()> {
+        if self.active_buffer.is_large() {
+            return Err(EditorError::LargeFile);
+        }
+        if self.diff.is_none() {
+            self.diff = Some(DiffView::default());
+            self.wrap_before_diff = self.options.wrap;
+            self.options.wrap = false;
+            self.set_cursor_line(self.cursor_line());
+            self.set_cursor_column(self.cursor_column());
+        }
+        Ok(())
+    }
+
+    pub fn stop_diff(&mut self) {
+        if self.diff.take().is_some() {
+            self.options.wrap = self.wrap_before_diff;
+            self.set_cursor_line(self.cursor_line());
+        }
+    }
+
+    pub fn set_diff(&mut self, view: DiffView) {
+        if let Some(diff) = self.diff.as_mut() {
+            *diff = view;
+            self.set_cursor_line(self.cursor_line());
+        }
+    }
+
+    // The aligned rows at the top of the frame and at the cursor.
+    pub fn diff_rows(&self) -> Option<(usize, usize)> {
+        let diff = self.diff.as_ref()?;
+        let top = diff.row_of(self.line_offset)? - diff.fillers(self.line_offset) + self.hidden_fillers();
+        Some((top, diff.row_of(self.cursor_line())?))
+    }
+
+    // Scrolls to show the same rows as another frame in the diff.
+    pub fn align_diff(&mut self, top: usize, cursor: usize) {
+        let (offset, hidden, line) = match self.diff.as_ref() {
+            Some(diff) => {
+                let (offset, hidden) = diff.line_at(top).unwrap_or((self.last_line(), 0));
+                (offset, hidden, diff.line_at(cursor).map_or(self.last_line(), |(line, _)| line))
+            },
+            None => return,
+        };
+        self.move_to(CursorPosition { line, column: self.cursor_column() });
+        self.line_offset = cmp::min(offset, self.cursor_line());
+        self.hidden_fillers = (offset, hidden);
+        self.update_screen_position();
+    }
+
+    pub fn apply_edits(&mut self, edits: Vec<TextEdit>) -> EditorResult<()> {
+        self.active_buffer.apply_edits(edits)?;
+        self.lines_changed(0, 0, 0);
+        self.move_to(self.cursor_position());
+        Ok(())
+    }
+
+    pub fn recover(&mut self, lines: &[String]) {
+        self.active_buffer.recover(lines);
+        self.lines_changed(0, 0, 0);
+        self.move_to(self.cursor_position());
+    }
+
+    pub fn current_line_text(&self) -> Option<&str> {
+        self.current_buffer_line().map(|line| line.line.as_str())
+    }
+
+    pub fn line_down_first_non_blank(&mut self, count: usize) {
+        let line = self.step_visible_lines(self.cursor_line(), count, true);
+        self.set_cursor_line(line);
+        self.set_cursor_column(self.active_buffer.first_non_blank(self.cursor_line()));
+    }
+
+    pub fn cursor_position(&self) -> CursorPosition {
+        self.cursor_controller.text_position
+    }
+
+    fn current_jump(&self) -> Jump {
+        Jump {
+            file: self.active_buffer.file_path.clone(),
+            position: self.cursor_position(),
+        }
+    }
+
+    pub fn move_to(&mut self, position: CursorPosition) {
+        self.set_cursor_line(position.line);
+        if self.cursor_line() == position.line {
+            self.set_cursor_column(position.column);
+            self.snap_to_eol();
+        }
+    }
+
+    pub fn remember_jump(&mut self) {
+        self.jump_list.push(self.current_jump());
+        self.active_buffer.marks.set('`', self.cursor_position());
+    }
+
+    pub fn jump_to(&mut self, position: CursorPosition) {
+        self.remember_jump();
+        self.move_to(position);
+    }
+
+    pub fn jump_to_file(&mut self, file: PathBuf, mut position: CursorPosition, linewise: bool) -> EditorResult<()> {
+        self.remember_jump();
+        self.go_to_jump(Jump { file: Some(file), position })?;
+        if linewise {
+            position.column = self.active_buffer.first_non_blank(position.line);
+            self.move_to(position);
+        }
+        Ok(())
+    }
+
+    pub fn go_to_line(&mut self, line: Option<usize>) {
+        let line = line.map_or(self.last_line(), |line| line.saturating_sub(1));
+        let column = self.active_buffer.first_non_blank(line);
+        self.jump_to(CursorPosition { line, column });
+    }
+
+    pub fn set_mark(&mut self, name: char) -> EditorResult<()> {
+        match name {
+            'a'..='z' | 'A'..='Z' | '`' | '\'' | '[' | ']' | '^' | '.' => {
+                let name = if name == '\'' { '`' } else { name };
+                self.active_buffer.marks.set(name, self.cursor_position());
+                Ok(())
+            },
+            _ => Err(EditorError::InvalidMark(name)),
+        }
+    }
+
+    pub fn mark(&self, name: char) -> Option<CursorPosition> {
+        let name = if name == '\'' { '`' } else { name };
+        self.active_buffer.marks.get(name)
+    }
+
+    pub fn jump_to_mark(&mut self, name: char, linewise: bool) -> EditorResult<()> {
+        let mut position = self.mark(name).ok_or(EditorError::MarkNotSet(name))?;
+        if linewise {
+            position.column = self.active_buffer.first_non_blank(position.line);
+        }
+        self.jump_to(position);
+        Ok(())
+    }
+
+    pub fn go_to_jump(&mut self, jump: Jump) -> EditorResult<()> {
+        if let Some(file) = jump.file {
+            if !self.file_path().is_some_and(|current| same_file(current, &file)) {
+                self.edit(file, None)?;
             }
+        }
+        self.move_to(jump.position);
+        Ok(())
+    }
 
-            self.column_offset -= 1;
-        } else {
-            self.cursor_controller.move_cursor_left();
+    pub fn jump_older(&mut self, count: usize) -> EditorResult<()> {
+        match self.jump_list.back(self.current_jump(), count) {
+            Some(jump) => self.go_to_jump(jump),
+            None => Ok(()),
         }
     }
 
+    pub fn jump_newer(&mut self, count: usize) -> EditorResult<()> {
+        match self.jump_list.forward(count) {
+            Some(jump) => self.go_to_jump(jump),
+            None => Ok(()),
+        }
+    }
+
+    pub fn jumps(&self) -> &[Jump] {
+        self.jump_list.entries()
+    }
+
+    pub fn load_jumps(&mut self, jumps: Vec<Jump>) {
+        jumps.into_iter().for_each(|jump| self.jump_list.push(jump));
+    }
+
+    pub fn change_older(&mut self, count: usize) {
+        if let Some(position) = self.active_buffer.changes.older(count) {
+            self.move_to(position);
+        }
+    }
+
+    pub fn change_newer(&mut self, count: usize) {
+        if let Some(position) = self.active_buffer.changes.newer(count) {
+            self.move_to(position);
+        }
+    }
+
+    fn lines_changed(&mut self, at: usize, removed: usize, added: usize) {
+        self.folds.adjust(at, removed, added);
+        self.update_folds();
+        self.jump_list.adjust(&self.active_buffer.file_path, at, removed, added);
+
+        let line = cmp::min(self.cursor_line(), self.last_line());
+        self.cursor_controller.text_position.line = self.fold_start(line);
+        self.line_offset = self.fold_start(cmp::min(self.line_offset, self.last_line()));
+        self.update_gutter();
+    }
+
+    pub fn delete_lines(&mut self, motion: Motion) {
+        let line = self.cursor_line();
+        let target = match motion {
+            Motion::Down(count) => self.step_visible_lines(line, count, true),
+            Motion::Up(count) => self.step_visible_lines(line, count, false),
+        };
+        let start = cmp::min(line, target);
+        let end = self.folds.closed_at(cmp::max(line, target)).map_or(cmp::max(line, target), |fold| fold.end);
+
+        self.active_buffer.delete_lines(start, end + 1);
+        self.lines_changed(start, end + 1 - start, 0);
+        self.set_cursor_line(start);
+        self.set_cursor_column(self.active_buffer.first_non_blank(self.cursor_line()));
+    }
+
+    pub fn delete_chars(&mut self, count: usize) {
+        let (line, column) = (self.cursor_line(), self.cursor_column());
+        let end = match self.current_buffer_line() {
+            Some(buffer_line) => {
+                let rest = buffer_line.line.get(column..).unwrap_or("");
+                rest.char_indices().nth(count).map_or(buffer_line.line.len(), |(idx, _)| column + idx)
+            },
+            None => return,
+        };
+        self.active_buffer.delete_text(line, column, end);
+        self.snap_to_eol();
+        self.update_screen_position();
+    }
+
     pub fn jump_to_start_of_word_forward(&mut self) {
         if let Some(current_line) = self.current_buffer_line() {
-            if current_line.line.len() > 0 {
-                let current_cursor_column = self.cursor_controller.position.column;
+            if !current_line.line.is_empty() {
+                let current_cursor_column = self.cursor_column();
                 let from_cursor = &current_line.line[current_cursor_column..];
                 let jump_to_idx = from_cursor.find(' ');
                 if let Some(idx) = jump_to_idx {
-                    self.cursor_controller.position.column += idx + 1;
+                    self.set_cursor_column(current_cursor_column + idx + 1);
                 }
             }
         }
     }
 
-    pub fn move_cursor_right(&mut self) {
-        if let Some(current_line) = self.current_buffer_line() {
-            if  current_line.line.len() > 0
-                && self.cursor_controller.position.column < current_line.line.len() - 1 {
-                    if self.cursor_controller.position.column + 1 == self.cursor_controller.frame_columns {
-                        eprintln!("Line len {}, Col offset {}", current_line.line.len(), self.column_offset);
-
-                        if self.column_offset + self.cursor_controller.position.column < current_line.line.len() - 1 {
-                            self.column_offset += 1;
-                        }
-                    } else {
-                        self.cursor_controller.move_cursor_right();
-                    }
-                }
-        }
-    }
-
     pub fn jump_to_start_of_word_backward(&mut self) {
         if let Some(current_line) = self.current_buffer_line() {
-            if current_line.line.len() > 0 {
-                let current_cursor_column = self.cursor_controller.position.column;
+            if !current_line.line.is_empty() {
+                let current_cursor_column = self.cursor_column();
                 let until_cursor = &current_line.line[..current_cursor_column];
                 let jump_to_idx = until_cursor.rfind(' ');
                 if let Some(idx) = jump_to_idx {
-                    self.cursor_controller.position.column = idx + 1;
+                    self.set_cursor_column(idx + 1);
                 }
             }
         }
@@ -213,13 +1281,226 @@ impl Frame {
 
     pub fn jump_to_end_of_word_forward(&mut self) {
         if let Some(current_line) = self.current_buffer_line() {
-            if current_line.line.len() > 0 {
-                let current_cursor_column = self.cursor_controller.position.column;
+            if !current_line.line.is_empty() {
+                let current_cursor_column = self.cursor_column();
                 let from_cursor = &current_line.line[current_cursor_column..];
-                let jump_to_idx = from_cursor.find(' ');
-                if let Some(idx) = jump_to_idx {
-                    self.cursor_controller.position.column += idx - 1;
+                // Moves on from the end of a word or from blanks to the last
+                // character of the next word.
+                let word_start = from_cursor.char_indices().skip(1).find(|(_, c)| *c != ' ').map(|(idx, _)| idx);
+                if let Some(start) = word_start {
+                    let word_end = from_cursor[start..].find(' ').map_or(from_cursor.len(), |idx| start + idx);
+                    let last = from_cursor[..word_end].char_indices().last().map_or(start, |(idx, _)| idx);
+                    self.set_cursor_column(current_cursor_column + last);
+                }
+            }
+        }
+    }
+
+    fn previous_char_column(&self) -> usize {
+        let text = self.current_line_text().unwrap_or("");
+        text[..cmp::min(self.cursor_column(), text.len())].char_indices().last().map_or(0, |(idx, _)| idx)
+    }
+
+    fn next_char_column(&self) -> usize {
+        let text = self.current_line_text().unwrap_or("");
+        let column = cmp::min(self.cursor_column(), text.len());
+        text[column..].chars().next().map_or(column, |c| column + c.len_utf8())
+    }
+
+    pub fn start_insert(&mut self, position: InsertPosition) {
+        self.inserting = true;
+        let line = self.cursor_line();
+        match position {
+            InsertPosition::BeforeCursor => {},
+            InsertPosition::AfterCursor => self.set_cursor_column(self.next_char_column()),
+            InsertPosition::LineStart => self.set_cursor_column(self.active_buffer.first_non_blank(line)),
+            InsertPosition::LineEnd => {
+                let line_len = self.current_line_text().map_or(0, str::len);
+                self.set_cursor_column(line_len);
+            },
+            InsertPosition::LineBelow => {
+                let line_end = CursorPosition { line, column: self.current_line_text().map_or(0, str::len) };
+                self.move_to(line_end);
+                self.replace_to_cursor(line_end, "\n");
+            },
+            InsertPosition::LineAbove => {
+                let line_start = CursorPosition { line, column: 0 };
+                self.replace_range(line_start, line_start, "\n");
+                self.move_to(line_start);
+            },
+        }
+    }
+
+    pub fn stop_insert(&mut self) {
+        self.inserting = false;
+        self.set_cursor_column(self.previous_char_column());
+        self.snap_to_eol();
+    }
+
+    fn replace_range(&mut self, start: CursorPosition, end: CursorPosition, text: &str) -> CursorPosition {
+        let text_end = self.active_buffer.replace_text(start, end, text);
+        let (removed, added) = (end.line - start.line, text.matches('\n').count());
+        if removed + added > 0 {
+            self.lines_changed(start.line + 1, removed, added);
+        }
+        text_end
+    }
+
+    // Replaces the text from start up to the cursor and leaves the cursor after the new text.
+    pub fn replace_to_cursor(&mut self, start: CursorPosition, text: &str) {
+        let text_end = self.replace_range(start, self.cursor_position(), text);
+        self.move_to(text_end);
+    }
+
+    pub fn insert_edit(&mut self, edit: InsertEdit) {
+        let position = self.cursor_position();
+        match edit {
+            InsertEdit::Insert(c) => self.replace_to_cursor(position, &c.to_string()),
+            InsertEdit::NewLine => self.replace_to_cursor(position, "\n"),
+            InsertEdit::DeleteBackward if position.column > 0 => {
+                let start = CursorPosition { line: position.line, column: self.previous_char_column() };
+                self.replace_to_cursor(start, "");
+            },
+            InsertEdit::DeleteBackward if position.line > 0 => {
+                let line = position.line - 1;
+                let column = self.active_buffer.get_line(line).map_or(0, |line| line.line.len());
+                self.replace_to_cursor(CursorPosition { line, column }, "");
+            },
+            InsertEdit::DeleteBackward => {},
+            InsertEdit::DeleteForward => {
+                let end = match self.next_char_column() {
+                    column if column > position.column => CursorPosition { line: position.line, column },
+                    _ if position.line < self.last_line() => CursorPosition { line: position.line + 1, column: 0 },
+                    _ => return,
+                };
+                self.replace_range(position, end, "");
+                self.move_to(position);
+            },
+            InsertEdit::Left => self.set_cursor_column(self.previous_char_column()),
+            InsertEdit::Right => self.set_cursor_column(self.next_char_column()),
+            InsertEdit::Up | InsertEdit::Down => {
+                if matches!(edit, InsertEdit::Up) {
+                    self.move_cursor_up();
+                } else {
+                    self.move_cursor_down();
+                }
+                // Typing has to happen on a character boundary.
+                let text = self.current_line_text().unwrap_or("");
+                let mut column = cmp::min(self.cursor_column(), text.len());
+                while !text.is_char_boundary(column) {
+                    column -= 1;
+                }
+                self.set_cursor_column(column);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::Harness;
+
+    #[test]
+    fn scrolls_with_a_margin() {
+        let text = (1..=100).map(|n| format!("line {}", n)).collect::<Vec<_>>().join("\n");
+        let mut harness = Harness::new(60, 14, &text);
+        harness.keys("11j");
+        assert_eq!(harness.screen().cursor(), (6, 6));
+        harness.keys("j");
+        assert_eq!(harness.cursor(), (12, 0));
+        assert_eq!(harness.screen().cursor(), (6, 6));
+
+        harness.keys("<C-d><C-d>");
+        assert_eq!(harness.cursor(), (24, 0));
+        assert_eq!(harness.screen().cursor(), (6, 6));
+        assert!(harness.screen().row(0).starts_with("   19 line 19"));
+        assert!(harness.screen().row(11).starts_with("   30 line 30"));
+        harness.keys("zz");
+        assert_eq!(harness.screen().cursor(), (6, 5));
+        assert!(harness.screen().row(0).starts_with("   20 line 20"));
+        harness.keys("zb");
+        assert_eq!(harness.screen().cursor(), (6, 6));
+        harness.keys("<C-u>");
+        assert_eq!(harness.cursor(), (18, 0));
+        harness.keys("G");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((99, 0), (6, 11)));
+    }
+
+    #[test]
+    fn moves_through_wrapped_lines() {
+        let long = "abcdefghijklmnopqrstuvwxyz0123456789".repeat(4);
+        let mut harness = Harness::new(60, 14, &format!("short\n{}\nend", long));
+        harness.keys(":set wrap<CR>j");
+        assert_eq!(harness.screen().row(1).trim_end(), "    2 abcdefghijklmnopqrstuvw     2 ~");
+        assert!(harness.screen().row(2).starts_with("    ↪ xyz0123456789abcdefghij"));
+        assert_eq!(harness.screen().cursor(), (6, 1));
+
+        harness.keys("gj");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((1, 23), (6, 2)));
+        harness.keys("gj");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((1, 46), (6, 3)));
+        harness.keys("gk");
+        assert_eq!(harness.cursor(), (1, 23));
+        harness.keys("j");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((2, 2), (8, 8)));
+        harness.keys("gk");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((1, 140), (8, 7)));
+    }
+
+    #[test]
+    fn renders_long_lines_of_multibyte_text() {
+        let line = "àéîõü".repeat(10);
+        let chars = |from: usize, to: usize| line.chars().skip(from).take(to - from).collect::<String>();
+        let mut harness = Harness::new(60, 14, &format!("{}\nend", line));
+        harness.keys("");
+        assert!(harness.screen().row(0).starts_with(&format!("    1 {} ", chars(0, 23))));
+
+        harness.keys("30l");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((0, 60), (28, 0)));
+        assert!(harness.screen().row(0).starts_with(&format!("    1 {} ", chars(8, 31))));
+        harness.keys("j");
+        assert_eq!(harness.screen().row(1).trim_end(), "    2 d                           2 ~");
+        harness.keys("k60l");
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((0, 98), (28, 0)));
+        assert!(harness.screen().row(0).starts_with(&format!("    1 {} ", chars(27, 50))));
+        harness.keys("3zh");
+        assert!(harness.screen().row(0).starts_with(&format!("    1 {} ", chars(24, 47))));
+        assert_eq!(harness.cursor(), (0, 92));
+
+        harness.keys(":set wrap<CR>gggj");
+        assert_eq!(harness.screen().row(1), format!("    ↪ {}     2 ~", chars(23, 46)));
+        assert!(harness.screen().row(2).starts_with(&format!("    ↪ {} ", chars(46, 50))));
+        assert_eq!((harness.cursor(), harness.screen().cursor()), ((0, 46), (6, 1)));
+    }
+
+    #[test]
+    fn jumps_to_the_end_of_words() {
+        let mut harness = Harness::new(60, 14, "  wörd end\nnext");
+        harness.keys("e");
+        assert_eq!(harness.cursor(), (0, 6));
+        harness.keys("e");
+        assert_eq!(harness.cursor(), (0, 10));
+        harness.keys("e");
+        assert_eq!(harness.cursor(), (0, 10));
+        harness.keys("bhe");
+        assert_eq!(harness.cursor(), (0, 10));
+    }
+
+    #[test]
+    fn survives_motions_over_multibyte_lines() {
+        let text = "日本語のテキスト ça va très bien, naïve café déjà vu\n\tétroit\n€";
+        for columns in [24, 40, 61] {
+            for wrap in [":set nowrap<CR>", ":set wrap<CR>:set linebreak<CR>"] {
+                let mut harness = Harness::new(columns, 10, text);
+                harness.keys(wrap);
+                for keys in ["l", "e", "w", "zl", "ze", "gj", "j", "k", "zh", "zs", "b", "gk", "h"] {
+                    for _ in 0..12 {
+                        harness.keys(keys);
+                    }
                 }
+                harness.keys("5x");
+                assert_eq!(harness.buffer_text().lines().count(), 3);
+                assert!(harness.screen().row(9).trim().is_empty(), "{}", harness.screen().row(9));
             }
         }
     }
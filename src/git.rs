@@ -0,0 +1,336 @@
+use crate::prelude::*;
+use git2::{Oid, Repository};
+use std::collections::HashMap;
+
+pub const GIT_SIGN_GROUP: &str = "git";
+pub const BLAME_NAME: &str = "[Blame]";
+pub const OURS_NAME: &str = "[Ours]";
+pub const THEIRS_NAME: &str = "[Theirs]";
+const NOT_COMMITTED: &str = "00000000 Not Committed Yet";
+const BLAME_AUTHOR_WIDTH: usize = 12;
+// Lines around an edit diffed along with it, so a change can line up with
+// its neighbours rather than only with itself.
+const DIFF_CONTEXT: usize = 3;
+
+impl From<git2::Error> for EditorError {
+    fn from(err: git2::Error) -> Self {
+        EditorError::Git(err.message().to_string())
+    }
+}
+
+// A buffer's file as its git repository sees it: the branch checked out, the
+// text HEAD has for the file and the hunks where the buffer differs from it.
+pub struct GitFile {
+    repo: Repository,
+    path: PathBuf,
+    pub branch: String,
+    head_id: Option<Oid>,
+    head: Vec<String>,
+    pub hunks: Vec<Hunk>,
+    // The buffer lines the hunks were computed for.
+    lines: Vec<String>,
+    tick: Option<usize>,
+}
+
+impl GitFile {
+    // None when the file is not inside a work tree.
+    pub fn open(file: &Path) -> Option<Self> {
+        let file = match fs::canonicalize(file) {
+            Ok(file) => file,
+            Err(_) => canonical_path(file.parent()?).join(file.file_name()?),
+        };
+        let repo = Repository::discover(file.parent()?).ok()?;
+        let workdir = canonical_path(repo.workdir()?);
+        let path = file.strip_prefix(&workdir).ok()?.to_path_buf();
+        let mut git = Self { repo, path, branch: String::new(), head_id: None, head: vec![], hunks: vec![], lines: vec![], tick: None };
+        git.refresh_head();
+        Some(git)
+    }
+
+    // Picks up commits and branch switches made outside the editor.
+    pub fn refresh_head(&mut self) {
+        self.branch = branch_name(&self.repo);
+        let head_id = self.repo.head()
+            .and_then(|head| head.peel_to_tree())
+            .and_then(|tree| tree.get_path(&self.path))
+            .map(|entry| entry.id())
+            .ok();
+        if head_id != self.head_id {
+            self.head_id = head_id;
+            self.head = head_id
+                .and_then(|id| self.repo.find_blob(id).ok())
+                .map_or(vec![], |blob| split_lines(blob.content()));
+            self.tick = None;
+        }
+    }
+
+    // Diffs the buffer against HEAD again if it changed since the last call,
+    // returning whether the hunks were recomputed. Only the first call after
+    // opening or a new HEAD diffs the whole file; later ones redo the lines
+    // that changed since.
+    pub fn update(&mut self, buffer: &Buffer) -> bool {
+        if self.tick == Some(buffer.change_tick) {
+            return false;
+        }
+        let lines: Vec<&str> = buffer.text_lines().collect();
+        if self.head_id.is_none() {
+            // Untracked files have nothing to compare with.
+            self.hunks = vec![];
+        } else if self.tick.is_none() {
+            let head: Vec<&str> = self.head.iter().map(String::as_str).collect();
+            self.hunks = diff(&head, &lines);
+            self.lines = lines.iter().map(|line| line.to_string()).collect();
+        } else {
+            self.rediff(&lines);
+        }
+        self.tick = Some(buffer.change_tick);
+        true
+    }
+
+    // Finds the lines that changed since the last diff, widens them to the
+    // hunks they touch plus some context, and diffs only that part again.
+    // Hunks before it are kept and the ones after it shifted.
+    fn rediff(&mut self, lines: &[&str]) {
+        let previous = &self.lines;
+        let prefix = previous.iter().zip(lines).take_while(|(a, b)| a == *b).count();
+        let suffix = previous[prefix..].iter().rev().zip(lines[prefix..].iter().rev()).take_while(|(a, b)| a == *b).count();
+        if prefix + suffix == previous.len() && previous.len() == lines.len() {
+            return;
+        }
+
+        let mut start = prefix.saturating_sub(DIFF_CONTEXT);
+        let mut end = cmp::min(previous.len() - suffix + DIFF_CONTEXT, previous.len());
+        let before = self.hunks.iter().take_while(|hunk| hunk.new_end() < start).count();
+        let after = self.hunks.iter().rev().take_while(|hunk| hunk.new_start > end).count();
+        let touched = &self.hunks[before..self.hunks.len() - after];
+        start = touched.first().map_or(start, |hunk| cmp::min(start, hunk.new_start));
+        end = touched.last().map_or(end, |hunk| cmp::max(end, hunk.new_end()));
+
+        // Outside the touched hunks lines match, so HEAD is off by what the hunks before added or removed.
+        let shift = |hunks: &[Hunk], line: usize| hunks.iter().fold(line, |line, hunk| line + hunk.old_count - hunk.new_count);
+        let (old_start, old_end) = (shift(&self.hunks[..before], start), shift(&self.hunks[..self.hunks.len() - after], end));
+        let new_end = end + lines.len() - previous.len();
+
+        let head: Vec<&str> = self.head[old_start..old_end].iter().map(String::as_str).collect();
+        let changed = diff(&head, &lines[start..new_end]).into_iter()
+            .map(|hunk| Hunk { old_start: hunk.old_start + old_start, new_start: hunk.new_start + start, ..hunk });
+        let moved = self.hunks[self.hunks.len() - after..].iter()
+            .map(|hunk| Hunk { new_start: hunk.new_start + lines.len() - previous.len(), ..*hunk });
+        let mut hunks = self.hunks[..before].to_vec();
+        hunks.extend(changed);
+        hunks.extend(moved);
+        self.hunks = hunks;
+
+        let replaced = prefix..previous.len() - suffix;
+        self.lines.splice(replaced, lines[prefix..lines.len() - suffix].iter().map(|line| line.to_string()));
+    }
+
+    // Changed lines get '~', added ones '+', and a deletion marks the line
+    // above the gap with '_' ('‾' on the first line when nothing is above).
+    pub fn signs(&self) -> Vec<(usize, Sign)> {
+        let sign = |text: &str, color| Sign { color: Some(color), ..Sign::new(text, GIT_SIGN_GROUP) };
+        let mut signs = vec![];
+        for hunk in &self.hunks {
+            let changed = cmp::min(hunk.old_count, hunk.new_count);
+            for line in hunk.new_start..hunk.new_end() {
+                match line - hunk.new_start {
+                    offset if offset + 1 == changed && hunk.old_count > hunk.new_count => {
+                        signs.push((line, sign("~_", style::Color::Yellow)))
+                    },
+                    offset if offset < changed => signs.push((line, sign("~", style::Color::Yellow))),
+                    _ => signs.push((line, sign("+", style::Color::Green))),
+                }
+            }
+            if hunk.new_count == 0 {
+                let text = if hunk.new_start == 0 { "‾" } else { "_" };
+                signs.push((hunk.line(), sign(text, style::Color::Red)));
+            }
+        }
+        signs
+    }
+
+    pub fn hunk_at(&self, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().find(|hunk| hunk.contains_line(line))
+    }
+
+    pub fn head_lines(&self, hunk: &Hunk) -> &[String] {
+        &self.head[hunk.old_start..hunk.old_end()]
+    }
+
+    // Writes the change under `line` to the index, keeping whatever else is
+    // staged for the file. The buffer does not need to be saved first.
+    pub fn stage_hunk(&self, buffer: &Buffer, line: usize) -> EditorResult<()> {
+        let mut index = self.repo.index()?;
+        // Picks up anything staged outside the editor since the last call.
+        index.read(false)?;
+        let entry = index.get_path(&self.path, 0)
+            .ok_or_else(|| EditorError::Git(format!("{} is not tracked", self.path.display())))?;
+        let blob = self.repo.find_blob(entry.id)?;
+        let mut staged = split_lines(blob.content());
+        let lines: Vec<String> = buffer.text_lines().map(String::from).collect();
+        let hunk = diff(&staged, &lines)
+            .into_iter()
+            .find(|hunk| hunk.contains_line(line))
+            .ok_or(EditorError::NoHunk)?;
+        staged.splice(hunk.old_start..hunk.old_end(), lines[hunk.new_start..hunk.new_end()].iter().cloned());
+
+        let newline = if blob.content().windows(2).any(|pair| pair == b"\r\n") { "\r\n" } else { "\n" };
+        let mut contents = staged.join(newline);
+        if blob.content().is_empty() || blob.content().ends_with(b"\n") {
+            contents.push_str(newline);
+        }
+        index.add_frombuffer(&entry, contents.as_bytes())?;
+        index.write()?;
+        Ok(())
+    }
+
+    // Our and their versions of a file with a merge conflict, as the index
+    // holds them. A side that deleted the file has no lines.
+    pub fn conflict(&self) -> EditorResult<(Vec<String>, Vec<String>)> {
+        let mut index = self.repo.index()?;
+        index.read(false)?;
+        let version = |stage| {
+            index.get_path(&self.path, stage)
+                .and_then(|entry| self.repo.find_blob(entry.id).ok())
+                .map(|blob| split_lines(blob.content()))
+        };
+        match (version(2), version(3)) {
+            (None, None) => Err(EditorError::NoConflict),
+            (ours, theirs) => Ok((ours.unwrap_or_default(), theirs.unwrap_or_default())),
+        }
+    }
+
+    // One line per buffer line naming the commit, author and date that last
+    // changed it. Lines changed in the buffer count as not committed yet.
+    pub fn blame(&self, buffer: &Buffer) -> EditorResult<Vec<String>> {
+        let contents: String = buffer.text_lines().map(|line| format!("{}\n", line)).collect();
+        let committed = self.repo.blame_file(&self.path, None)?;
+        let blame = committed.blame_buffer(contents.as_bytes())?;
+        // Hunks from a buffer blame may carry no signature, so the author comes
+        // from the commit itself.
+        let mut commits: HashMap<Oid, String> = HashMap::new();
+        let mut lines = vec![];
+        for line in 0..buffer.number_of_lines() {
+            let id = match blame.get_line(line + 1) {
+                Some(hunk) if !hunk.final_commit_id().is_zero() => hunk.final_commit_id(),
+                _ => {
+                    lines.push(String::from(NOT_COMMITTED));
+                    continue;
+                },
+            };
+            let line = match commits.get(&id) {
+                Some(line) => line.clone(),
+                None => {
+                    let commit = self.repo.find_commit(id)?;
+                    let author = commit.author();
+                    let name: String = author.name().unwrap_or("").chars().take(BLAME_AUTHOR_WIDTH).collect();
+                    let line = format!("{} {:<width$} {}", &id.to_string()[..8], name, date(author.when().seconds()), width = BLAME_AUTHOR_WIDTH);
+                    commits.insert(id, line.clone());
+                    line
+                },
+            };
+            lines.push(line);
+        }
+        Ok(lines)
+    }
+}
+
+fn branch_name(repo: &Repository) -> String {
+    match repo.head() {
+        Ok(head) if head.is_branch() => head.shorthand().unwrap_or("HEAD").to_string(),
+        Ok(head) => head.target().map_or(String::new(), |id| id.to_string()[..7].to_string()),
+        // A branch without commits yet.
+        Err(_) => repo.find_reference("HEAD")
+            .ok()
+            .and_then(|head| head.symbolic_target().map(|target| target.trim_start_matches("refs/heads/").to_string()))
+            .unwrap_or_default(),
+    }
+}
+
+fn split_lines(contents: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(contents).lines().map(String::from).collect()
+}
+
+// Seconds since the epoch as a UTC "YYYY-MM-DD" date.
+fn date(seconds: i64) -> String {
+    // Howard Hinnant's days-to-civil conversion.
+    let days = seconds.div_euclid(86400) + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn shows_changes_against_head() {
+        assert_eq!(date(1700000000), "2023-11-14");
+        assert_eq!(date(0), "1970-01-01");
+
+        let dir = TempDir::new("git");
+        let repo = Repository::init(dir.path()).unwrap();
+        dir.write("file.txt", "one\ntwo\nthree\nfour\n");
+        dir.write("long.txt", (1..=40).map(|line| format!("line {}\n", line)).collect::<String>());
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("file.txt")).unwrap();
+        index.add_path(Path::new("long.txt")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::new("Tester", "tester@example.com", &git2::Time::new(1700000000, 0)).unwrap();
+        let commit = repo.commit(Some("refs/heads/main"), &signature, &signature, "Add file", &tree, &[]).unwrap();
+        repo.set_head("refs/heads/main").unwrap();
+
+        let mut harness = Harness::new(160, 12, "");
+        harness.keys(&format!(":e {}<CR>", dir.join("file.txt").display()));
+        assert!(harness.screen().row(10).starts_with("file.txt [main] -- 4 lines"));
+        harness.keys("jxGofive<Esc>");
+        assert!(harness.screen().row(1).starts_with("~     2 wo"));
+        assert!(harness.screen().row(4).starts_with("+     5 five"));
+
+        harness.keys("gg]c");
+        assert_eq!(harness.cursor(), (1, 0));
+        harness.keys("]c");
+        assert_eq!(harness.cursor(), (4, 0));
+        assert!(harness.keys("]c").screen().row(11).contains("No more items"));
+        harness.keys("[c");
+        assert_eq!(harness.cursor(), (1, 0));
+
+        harness.keys(":Ghunk stage<CR>");
+        let mut index = repo.index().unwrap();
+        index.read(true).unwrap();
+        let staged = repo.find_blob(index.get_path(Path::new("file.txt"), 0).unwrap().id).unwrap();
+        assert_eq!(staged.content(), b"one\nwo\nthree\nfour\n");
+        harness.keys(":Ghunk reset<CR>");
+        assert_eq!(harness.buffer_text(), "one\ntwo\nthree\nfour\nfive");
+        assert!(harness.screen().row(1).starts_with("      2 two"));
+        assert!(harness.keys("jdd").screen().row(1).starts_with("_     2 two"));
+
+        harness.keys(":Gblame<CR>");
+        let committed = format!("{} Tester       2023-11-14", &commit.to_string()[..8]);
+        assert!(harness.screen().row(0).starts_with(&committed));
+        assert!(harness.screen().row(3).starts_with("00000000 Not Committed Yet"));
+        assert!(harness.screen().row(10).contains("file.txt [+] [main]"));
+        harness.keys("<C-w>h:q<CR>");
+        assert!(harness.screen().row(0).starts_with("      1 one"));
+
+        // Diffing only what changed ends up with the hunks a full diff finds.
+        harness.keys(&format!(":e! {}<CR>", dir.join("long.txt").display()));
+        for keys in ["5Gccfive<Esc>", "30Gdd", "15Gonew<Esc>", "3Gdd", "uu", "20Gyy10p", "Goend<Esc>", "ggOstart<Esc>", "ggdG"] {
+            harness.keys(keys);
+            let buffer = harness.editor().active_frame().buffer();
+            let lines: Vec<&str> = buffer.text_lines().collect();
+            let git = buffer.git.as_ref().unwrap();
+            let head: Vec<&str> = git.head.iter().map(String::as_str).collect();
+            assert_eq!(git.hunks, diff(&head, &lines), "after {}", keys);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+// Turns vim style key notation such as "dd:w<CR>" or "<C-w>l" into key events.
+pub fn parse_keys(keys: &str) -> Vec<KeyEvent> {
+    let mut events = vec![];
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        let special = rest.strip_prefix('<')
+            .and_then(|tail| tail.split_once('>'))
+            .and_then(|(name, tail)| special_key(name).map(|event| (event, tail)));
+        match special {
+            Some((event, tail)) => {
+                events.push(event);
+                rest = tail;
+            },
+            None => {
+                events.push(char_key(c, KeyModifiers::NONE));
+                rest = &rest[c.len_utf8()..];
+            },
+        }
+    }
+    events
+}
+
+fn char_key(c: char, modifiers: KeyModifiers) -> KeyEvent {
+    let modifiers = if c.is_uppercase() { modifiers | KeyModifiers::SHIFT } else { modifiers };
+    KeyEvent::new(KeyCode::Char(c), modifiers)
+}
+
+fn special_key(name: &str) -> Option<KeyEvent> {
+    let code = match name.to_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "bs" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        _ => {
+            let c = name.strip_prefix("C-").or_else(|| name.strip_prefix("c-"))?;
+            let mut chars = c.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(char_key(c, KeyModifiers::CONTROL)),
+                _ => None,
+            };
+        },
+    };
+    Some(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+// Drives a headless editor with the first frame showing a scratch buffer.
+pub struct Harness {
+    editor: Editor,
+}
+
+impl Harness {
+    pub fn new(columns: u16, lines: u16, text: &str) -> Self {
+        let args = Args { clean: true, ..Args::default() };
+        let mut editor = Editor::headless(args, (columns, lines), vec![]);
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        editor.active_frame().replace_buffer(Buffer::scratch("test", &lines));
+        Self { editor }
+    }
+
+    pub fn keys(&mut self, keys: &str) -> &mut Self {
+        self.editor.feed_keys(parse_keys(keys));
+        while self.editor.run().unwrap() {}
+        self.editor.refresh_screen().unwrap();
+        self
+    }
+
+    // Keeps processing server events until the condition holds, failing after a few seconds.
+    pub fn wait_for(&mut self, condition: impl Fn(&mut Self) -> bool) -> &mut Self {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition(self) {
+            assert!(Instant::now() < deadline, "timed out, screen:\n{}", self.screen().text());
+            thread::sleep(Duration::from_millis(10));
+            self.keys("");
+        }
+        self
+    }
+
+    pub fn buffer_text(&mut self) -> String {
+        self.editor.active_frame().buffer().text_lines().collect::<Vec<&str>>().join("\n")
+    }
+
+    pub fn cursor(&mut self) -> (usize, usize) {
+        let position = self.editor.active_frame().cursor_position();
+        (position.line, position.column)
+    }
+
+    pub fn editor(&mut self) -> &mut Editor {
+        &mut self.editor
+    }
+
+    pub fn screen(&self) -> &Screen {
+        self.editor.screen().unwrap()
+    }
+}
+
+// A fresh directory under the system temp dir for test fixtures. It is
+// removed on drop, so a failing assertion doesn't leave it behind.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "editor-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path: fs::canonicalize(path).unwrap() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+
+    // Writes a file relative to the directory, creating its parents first.
+    pub fn write(&self, name: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_notation() {
+        let keys = parse_keys("dW<C-w>l:q<CR><lt><Esc>");
+        assert_eq!(keys, vec![
+            KeyEvent::new(KeyCode::Char('d'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char('W'), KeyModifiers::SHIFT),
+            KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL),
+            KeyEvent::new(KeyCode::Char('l'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char(':'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char('q'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Char('<'), KeyModifiers::NONE),
+            KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+        ]);
+    }
+
+    #[test]
+    fn edits_the_buffer() {
+        let mut harness = Harness::new(80, 12, "one\ntwo\nthree\nfour");
+        harness.keys("jdd");
+        assert_eq!(harness.buffer_text(), "one\nthree\nfour");
+        assert_eq!(harness.cursor(), (1, 0));
+        harness.keys("x");
+        assert_eq!(harness.buffer_text(), "one\nhree\nfour");
+        assert_eq!(harness.screen().row(1).trim_end(), "    2 hree                                  2 ~");
+        assert_eq!(harness.screen().row(3).trim_end(), "~                                           4 ~");
+
+        let mut harness = Harness::new(80, 12, "été à Noël\n日本語");
+        harness.keys("x");
+        assert_eq!(harness.buffer_text(), "té à Noël\n日本語");
+        harness.keys("l3x");
+        assert_eq!(harness.buffer_text(), "t Noël\n日本語");
+        harness.keys("jl");
+        assert_eq!(harness.cursor(), (1, 3));
+        harness.keys("h5x");
+        assert_eq!(harness.buffer_text(), "t Noël\n");
+    }
+
+    #[test]
+    fn renders_the_screen() {
+        let mut harness = Harness::new(40, 8, "alpha\nbeta");
+        harness.keys("jl");
+        assert_eq!(harness.screen().row(0), "    1 alpha             1 ~");
+        assert_eq!(harness.screen().row(1), "    2 beta              2 ~");
+        assert!(harness.screen().row(6).starts_with("test -- 2 lines"));
+        assert_eq!(harness.screen().cursor(), (7, 1));
+
+        harness.keys(":set nonumber<CR>");
+        assert_eq!(harness.screen().row(0), "alpha                   1 ~");
+        assert_eq!(harness.screen().row(1), "beta                    2 ~");
+        assert_eq!(harness.screen().cursor(), (1, 1));
+    }
+}
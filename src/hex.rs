@@ -0,0 +1,122 @@
+use crate::prelude::*;
+
+pub const HEX_BYTES_PER_LINE: usize = 16;
+const BINARY_DETECT_LENGTH: usize = 8000;
+const OFFSET_WIDTH: usize = 10;
+const HEX_WIDTH: usize = HEX_BYTES_PER_LINE * 2 + HEX_BYTES_PER_LINE / 2 - 1;
+const ASCII_COLUMN: usize = OFFSET_WIDTH + HEX_WIDTH + 2;
+
+#[derive(Clone, Copy)]
+pub enum HexPane {
+    Hex { low_nibble: bool },
+    Ascii,
+}
+
+pub struct HexView {
+    pub bytes: Vec<u8>,
+}
+
+impl HexView {
+    // Same heuristic as git and grep: a NUL byte near the start means binary.
+    pub fn is_binary(bytes: &[u8]) -> bool {
+        bytes[..cmp::min(bytes.len(), BINARY_DETECT_LENGTH)].contains(&0)
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.bytes.chunks(HEX_BYTES_PER_LINE)
+            .enumerate()
+            .map(|(idx, chunk)| Self::format_line(idx * HEX_BYTES_PER_LINE, chunk))
+            .collect()
+    }
+
+    pub fn line(&self, idx: usize) -> String {
+        let start = idx * HEX_BYTES_PER_LINE;
+        let end = cmp::min(start + HEX_BYTES_PER_LINE, self.bytes.len());
+        Self::format_line(start, &self.bytes[start..end])
+    }
+
+    fn format_line(offset: usize, chunk: &[u8]) -> String {
+        let mut hex = String::new();
+        for (idx, byte) in chunk.iter().enumerate() {
+            if idx > 0 && idx % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let ascii: String = chunk.iter()
+            .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+            .collect();
+        format!("{:08x}: {:hex_width$}  {}", offset, hex, ascii, hex_width = HEX_WIDTH)
+    }
+
+    pub fn position(&self, line: usize, column: usize) -> Option<(usize, HexPane)> {
+        let (byte, pane) = if column >= ASCII_COLUMN {
+            (column - ASCII_COLUMN, HexPane::Ascii)
+        } else {
+            let column = column.checked_sub(OFFSET_WIDTH)?;
+            let (group, within) = (column / 5, column % 5);
+            if within == 4 {
+                return None;
+            }
+            (group * 2 + within / 2, HexPane::Hex { low_nibble: within % 2 == 1 })
+        };
+        let offset = line * HEX_BYTES_PER_LINE + byte;
+        (byte < HEX_BYTES_PER_LINE && offset < self.bytes.len()).then_some((offset, pane))
+    }
+
+    pub fn column(offset: usize, pane: &HexPane) -> CursorPosition {
+        let byte = offset % HEX_BYTES_PER_LINE;
+        let column = match pane {
+            HexPane::Hex { low_nibble } => OFFSET_WIDTH + byte * 2 + byte / 2 + *low_nibble as usize,
+            HexPane::Ascii => ASCII_COLUMN + byte,
+        };
+        CursorPosition { line: offset / HEX_BYTES_PER_LINE, column }
+    }
+
+    pub fn replace(&mut self, offset: usize, pane: &HexPane, c: char) -> EditorResult<()> {
+        let byte = &mut self.bytes[offset];
+        match pane {
+            HexPane::Hex { low_nibble } => {
+                let nibble = c.to_digit(16).ok_or_else(|| EditorError::InvalidArgument(c.to_string()))? as u8;
+                *byte = if *low_nibble { (*byte & 0xf0) | nibble } else { (*byte & 0x0f) | (nibble << 4) };
+            },
+            HexPane::Ascii if c.is_ascii() => *byte = c as u8,
+            HexPane::Ascii => return Err(EditorError::InvalidArgument(c.to_string())),
+        }
+        Ok(())
+    }
+
+    // Moves one nibble or byte forward within the pane, like typing over text.
+    pub fn next(&self, offset: usize, pane: HexPane) -> Option<(usize, HexPane)> {
+        let next = match pane {
+            HexPane::Hex { low_nibble: false } => (offset, HexPane::Hex { low_nibble: true }),
+            HexPane::Hex { low_nibble: true } => (offset + 1, HexPane::Hex { low_nibble: false }),
+            HexPane::Ascii => (offset + 1, HexPane::Ascii),
+        };
+        (next.0 < self.bytes.len()).then_some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::Harness;
+
+    #[test]
+    fn replaces_bytes_in_hex_view() {
+        let mut harness = Harness::new(80, 10, "hello");
+        harness.keys(":hex<CR>");
+        assert_eq!(harness.buffer_text(), "00000000: 6865 6c6c 6f0a                           hello.");
+
+        // Asking for more nibbles than are left changes none of them.
+        harness.keys("10l20r0");
+        assert_eq!(harness.buffer_text(), "00000000: 6865 6c6c 6f0a                           hello.");
+        assert!(!harness.editor().active_frame().buffer().modified);
+
+        harness.keys("3r4");
+        assert_eq!(harness.buffer_text(), "00000000: 4445 6c6c 6f0a                           DEllo.");
+        assert_eq!(harness.cursor(), (0, 12));
+        harness.keys(":hex<CR>");
+        assert_eq!(harness.buffer_text(), "DEllo");
+        assert!(harness.editor().active_frame().buffer().modified);
+    }
+}
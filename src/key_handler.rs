@@ -1,41 +1,266 @@
 use crate::prelude::*;
-use crate::Mode;
 
-pub struct KeyHandler;
+#[derive(Default)]
+pub struct KeyHandler {
+    pending: Option<KeyEvent>,
+    count: Option<usize>,
+    operator: Option<(Operator, Option<usize>)>,
+}
 
 pub enum Direction {
     Forward,
     Backward,
 }
 
+#[derive(Clone, Copy)]
+pub enum FrameDirection {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+pub enum Scroll {
+    HalfPageDown,
+    HalfPageUp,
+    PageDown,
+    PageUp,
+    LineDown,
+    LineUp,
+    CursorToTop,
+    CursorToCenter,
+    CursorToBottom,
+    ViewLeft,
+    ViewRight,
+    CursorToScreenStart,
+    CursorToScreenEnd,
+}
+
+pub enum Operator {
+    Fold,
+    Delete,
+}
+
+pub enum Motion {
+    Up(usize),
+    Down(usize),
+}
+
+pub enum FoldCommand {
+    Toggle,
+    Open,
+    Close,
+    OpenAll,
+    CloseAll,
+    NextStart,
+    PreviousEnd,
+    Create,
+    Delete,
+    DeleteAll,
+}
+
+pub enum CommandLineEdit {
+    Insert(char),
+    DeleteBackward,
+    DeleteForward,
+    DeleteWord,
+    DeleteToStart,
+    Left,
+    Right,
+    Home,
+    End,
+    HistoryOlder,
+    HistoryNewer,
+}
+
+pub enum InsertPosition {
+    BeforeCursor,
+    AfterCursor,
+    LineStart,
+    LineEnd,
+    LineBelow,
+    LineAbove,
+}
+
+pub enum InsertEdit {
+    Insert(char),
+    NewLine,
+    DeleteBackward,
+    DeleteForward,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+pub enum ScreenLine {
+    Top,
+    Middle,
+    Bottom,
+}
+
+pub enum ExplorerAction {
+    Open,
+    Collapse,
+    Create,
+    Rename,
+    Move,
+    Delete,
+    Refresh,
+    Close,
+}
+
 pub enum EditorCommand {
     QuitProgram,
-    MoveCursorRight,
-    MoveCursorLeft,
-    MoveCursorUp,
-    MoveCursorDown,
+    MoveCursorRight(usize),
+    MoveCursorLeft(usize),
+    MoveCursorUp(usize),
+    MoveCursorDown(usize),
+    MoveCursorDisplayUp(usize),
+    MoveCursorDisplayDown(usize),
     SetCommandMode,
     SetNormalMode,
     Noop,
-    WriteCommand(char),
-    DeleteCommandChar,
+    EditCommandLine(CommandLineEdit),
+    CompleteCommand(Direction),
     ExecuteCommand,
-    JumpStartOfWord(Direction),
-    JumpEndOfWord(Direction),
-    GoToRightFrame,
-    GoToLeftFrame,
+    OpenCommandWindow,
+    Enter(usize),
+    JumpStartOfWord(Direction, usize),
+    JumpEndOfWord(Direction, usize),
+    Scroll(Scroll, Option<usize>),
+    JumpToScreenLine(ScreenLine, Option<usize>),
+    ApplyOperator(Operator, Motion),
+    Fold(FoldCommand, usize),
+    GoToFrame(FrameDirection),
+    GoToNextFrame,
+    SetMark(char),
+    JumpToMark(char, bool),
+    JumpOlder(usize),
+    JumpNewer(usize),
+    ChangeOlder(usize),
+    ChangeNewer(usize),
+    GoToLine(Option<usize>),
+    DeleteChars(usize),
+    ReplaceChars(char, usize),
+    Hover,
+    GoToDefinition,
+    FindReferences,
+    StartInsert(InsertPosition),
+    EditText(InsertEdit),
+    Complete(Direction),
+    AcceptCompletion,
+    CancelCompletion,
+    OpenFinder,
+    EditFinderQuery(CommandLineEdit),
+    SelectFinderEntry(Direction),
+    OpenFinderSelection(Option<Split>),
+    Explorer(ExplorerAction),
+    JumpHunk(Direction, usize),
+    JumpConflict(Direction, usize),
+    DiffGet(Option<usize>),
+    DiffPut(Option<usize>),
 }
 
 impl KeyHandler {
-    pub fn process_key(key_event: KeyEvent, mode: &Mode) -> EditorCommand {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process_key(&mut self, key_event: KeyEvent, mode: &Mode) -> EditorCommand {
         match mode {
-            Mode::Normal => Self::process_normal_mode_key(key_event),
+            Mode::Normal => self.process_normal_mode_key(key_event),
             Mode::Command => Self::process_command_mode_key(key_event),
+            Mode::Insert => Self::process_insert_mode_key(key_event),
+            Mode::Finder => Self::process_finder_key(key_event),
         }
     }
 
-    pub fn process_normal_mode_key(key_event: KeyEvent) -> EditorCommand {
-        eprintln!("{:?}", key_event);
+    // True when no count, operator or prefix key is waiting for more input.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_none() && self.count.is_none() && self.operator.is_none()
+    }
+
+    fn push_count_digit(&mut self, digit: usize) {
+        self.count = Some(self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+    }
+
+    pub fn process_normal_mode_key(&mut self, key_event: KeyEvent) -> EditorCommand {
+        if let Some(prefix) = self.pending.take() {
+            let count = self.count.take();
+            return self.process_prefixed_key(prefix, key_event, count);
+        }
+
+        match key_event {
+            KeyEvent {
+                code: KeyCode::Char(c @ '1'..='9'),
+                modifiers: event::KeyModifiers::NONE,
+            } => {
+                self.push_count_digit(c as usize - '0' as usize);
+                return EditorCommand::Noop;
+            },
+            KeyEvent {
+                code: KeyCode::Char('0'),
+                modifiers: event::KeyModifiers::NONE,
+            } if self.count.is_some() => {
+                self.push_count_digit(0);
+                return EditorCommand::Noop;
+            },
+            _ => {},
+        }
+
+        if let Some((operator, operator_count)) = self.operator.take() {
+            let given = match (operator_count, self.count.take()) {
+                (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+                (a, b) => a.or(b),
+            };
+            let count = given.unwrap_or(1);
+            return match key_event {
+                KeyEvent {
+                    code: KeyCode::Char('j'),
+                    modifiers: event::KeyModifiers::NONE,
+                } => EditorCommand::ApplyOperator(operator, Motion::Down(count)),
+                KeyEvent {
+                    code: KeyCode::Char('k'),
+                    modifiers: event::KeyModifiers::NONE,
+                } => EditorCommand::ApplyOperator(operator, Motion::Up(count)),
+                KeyEvent {
+                    code: KeyCode::Char('d'),
+                    modifiers: event::KeyModifiers::NONE,
+                } if matches!(operator, Operator::Delete) => {
+                    EditorCommand::ApplyOperator(operator, Motion::Down(count - 1))
+                },
+                // In diff mode "do" and "dp" take or put the change under the cursor.
+                KeyEvent {
+                    code: KeyCode::Char('o'),
+                    modifiers: event::KeyModifiers::NONE,
+                } if matches!(operator, Operator::Delete) => EditorCommand::DiffGet(given),
+                KeyEvent {
+                    code: KeyCode::Char('p'),
+                    modifiers: event::KeyModifiers::NONE,
+                } if matches!(operator, Operator::Delete) => EditorCommand::DiffPut(given),
+                _ => EditorCommand::Noop,
+            };
+        }
+
+        match key_event {
+            KeyEvent {
+                code: KeyCode::Char('z' | 'g' | 'm' | 'q' | 'r' | '\'' | '`' | '[' | ']'),
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => {
+                self.pending = Some(key_event);
+                return EditorCommand::Noop;
+            },
+            _ => {},
+        }
+
+        let count = self.count.take();
+        let repeat = count.unwrap_or(1);
+
         match key_event {
             KeyEvent {
                 code: KeyCode::Char('q'),
@@ -44,19 +269,19 @@ impl KeyHandler {
             KeyEvent {
                 code: KeyCode::Char('h'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::MoveCursorLeft,
+            } => EditorCommand::MoveCursorLeft(repeat),
             KeyEvent {
                 code: KeyCode::Char('j'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::MoveCursorDown,
+            } => EditorCommand::MoveCursorDown(repeat),
             KeyEvent {
                 code: KeyCode::Char('k'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::MoveCursorUp,
+            } => EditorCommand::MoveCursorUp(repeat),
             KeyEvent {
                 code: KeyCode::Char('l'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::MoveCursorRight,
+            } => EditorCommand::MoveCursorRight(repeat),
             KeyEvent {
                 code: KeyCode::Char(':'),
                 modifiers: event::KeyModifiers::NONE,
@@ -64,23 +289,217 @@ impl KeyHandler {
             KeyEvent {
                 code: KeyCode::Char('w'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::JumpStartOfWord(Direction::Forward),
+            } => EditorCommand::JumpStartOfWord(Direction::Forward, repeat),
             KeyEvent {
                 code: KeyCode::Char('b'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::JumpStartOfWord(Direction::Backward),
+            } => EditorCommand::JumpStartOfWord(Direction::Backward, repeat),
             KeyEvent {
                 code: KeyCode::Char('e'),
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::JumpEndOfWord(Direction::Forward),
+            } => EditorCommand::JumpEndOfWord(Direction::Forward, repeat),
+            KeyEvent {
+                code: KeyCode::Char('d'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Scroll(Scroll::HalfPageDown, count),
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Scroll(Scroll::HalfPageUp, count),
+            KeyEvent {
+                code: KeyCode::Char('f'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Scroll(Scroll::PageDown, count),
+            KeyEvent {
+                code: KeyCode::Char('b'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Scroll(Scroll::PageUp, count),
+            KeyEvent {
+                code: KeyCode::Char('e'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Scroll(Scroll::LineDown, count),
             KeyEvent {
                 code: KeyCode::Char('y'),
                 modifiers: event::KeyModifiers::CONTROL,
-            } => EditorCommand::GoToRightFrame,
+            } => EditorCommand::Scroll(Scroll::LineUp, count),
+            KeyEvent {
+                code: KeyCode::Char('H'),
+                ..
+            } => EditorCommand::JumpToScreenLine(ScreenLine::Top, count),
+            KeyEvent {
+                code: KeyCode::Char('M'),
+                ..
+            } => EditorCommand::JumpToScreenLine(ScreenLine::Middle, count),
+            KeyEvent {
+                code: KeyCode::Char('L'),
+                ..
+            } => EditorCommand::JumpToScreenLine(ScreenLine::Bottom, count),
+            KeyEvent {
+                code: KeyCode::Char('G'),
+                ..
+            } => EditorCommand::GoToLine(count),
+            KeyEvent {
+                code: KeyCode::Char('K'),
+                ..
+            } => EditorCommand::Hover,
             KeyEvent {
                 code: KeyCode::Char('p'),
                 modifiers: event::KeyModifiers::CONTROL,
-            } => EditorCommand::GoToLeftFrame,
+            } => EditorCommand::OpenFinder,
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::JumpOlder(repeat),
+            KeyEvent {
+                code: KeyCode::Tab,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::JumpNewer(repeat),
+            KeyEvent {
+                code: KeyCode::Char('x'),
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::DeleteChars(repeat),
+            KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::Enter(repeat),
+            KeyEvent {
+                code: KeyCode::Char('d'),
+                modifiers: event::KeyModifiers::NONE,
+            } => {
+                self.operator = Some((Operator::Delete, count));
+                EditorCommand::Noop
+            },
+            KeyEvent {
+                code: KeyCode::Char('i'),
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::StartInsert(InsertPosition::BeforeCursor),
+            KeyEvent {
+                code: KeyCode::Char('a'),
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::StartInsert(InsertPosition::AfterCursor),
+            KeyEvent {
+                code: KeyCode::Char('I'),
+                ..
+            } => EditorCommand::StartInsert(InsertPosition::LineStart),
+            KeyEvent {
+                code: KeyCode::Char('A'),
+                ..
+            } => EditorCommand::StartInsert(InsertPosition::LineEnd),
+            KeyEvent {
+                code: KeyCode::Char('o'),
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::StartInsert(InsertPosition::LineBelow),
+            KeyEvent {
+                code: KeyCode::Char('O'),
+                ..
+            } => EditorCommand::StartInsert(InsertPosition::LineAbove),
+            _ => EditorCommand::Noop,
+        }
+    }
+
+    fn process_prefixed_key(&mut self, prefix: KeyEvent, key_event: KeyEvent, count: Option<usize>) -> EditorCommand {
+        let repeat = count.unwrap_or(1);
+
+        match (prefix.code, prefix.modifiers, key_event) {
+            (KeyCode::Char('z'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            }) => match c {
+                'z' => EditorCommand::Scroll(Scroll::CursorToCenter, count),
+                't' => EditorCommand::Scroll(Scroll::CursorToTop, count),
+                'b' => EditorCommand::Scroll(Scroll::CursorToBottom, count),
+                'h' => EditorCommand::Scroll(Scroll::ViewLeft, count),
+                'l' => EditorCommand::Scroll(Scroll::ViewRight, count),
+                's' => EditorCommand::Scroll(Scroll::CursorToScreenStart, count),
+                'e' => EditorCommand::Scroll(Scroll::CursorToScreenEnd, count),
+                'a' => EditorCommand::Fold(FoldCommand::Toggle, repeat),
+                'o' => EditorCommand::Fold(FoldCommand::Open, repeat),
+                'c' => EditorCommand::Fold(FoldCommand::Close, repeat),
+                'R' => EditorCommand::Fold(FoldCommand::OpenAll, repeat),
+                'M' => EditorCommand::Fold(FoldCommand::CloseAll, repeat),
+                'j' => EditorCommand::Fold(FoldCommand::NextStart, repeat),
+                'k' => EditorCommand::Fold(FoldCommand::PreviousEnd, repeat),
+                'F' => EditorCommand::Fold(FoldCommand::Create, repeat),
+                'd' => EditorCommand::Fold(FoldCommand::Delete, repeat),
+                'E' => EditorCommand::Fold(FoldCommand::DeleteAll, repeat),
+                'f' => {
+                    self.operator = Some((Operator::Fold, count));
+                    EditorCommand::Noop
+                },
+                _ => EditorCommand::Noop,
+            },
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('j'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::MoveCursorDisplayDown(repeat),
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('k'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::MoveCursorDisplayUp(repeat),
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('g'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::GoToLine(Some(repeat)),
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('d'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::GoToDefinition,
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::FindReferences,
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(';'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::ChangeOlder(repeat),
+            (KeyCode::Char('g'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(','),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::ChangeNewer(repeat),
+            (KeyCode::Char(prefix @ (']' | '[')), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::JumpHunk(if prefix == ']' { Direction::Forward } else { Direction::Backward }, repeat),
+            (KeyCode::Char(prefix @ (']' | '[')), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char('x'),
+                modifiers: event::KeyModifiers::NONE,
+            }) => EditorCommand::JumpConflict(if prefix == ']' { Direction::Forward } else { Direction::Backward }, repeat),
+            (KeyCode::Char('q'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(':'),
+                ..
+            }) => EditorCommand::OpenCommandWindow,
+            (KeyCode::Char('m'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            }) => EditorCommand::SetMark(c),
+            (KeyCode::Char('r'), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            }) => EditorCommand::ReplaceChars(c, repeat),
+            (KeyCode::Char(prefix @ ('\'' | '`')), event::KeyModifiers::NONE, KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            }) => EditorCommand::JumpToMark(c, prefix == '\''),
+            (KeyCode::Char('w'), event::KeyModifiers::CONTROL, KeyEvent {
+                code: KeyCode::Char('l'),
+                ..
+            }) => EditorCommand::GoToFrame(FrameDirection::Right),
+            (KeyCode::Char('w'), event::KeyModifiers::CONTROL, KeyEvent {
+                code: KeyCode::Char('h'),
+                ..
+            }) => EditorCommand::GoToFrame(FrameDirection::Left),
+            (KeyCode::Char('w'), event::KeyModifiers::CONTROL, KeyEvent {
+                code: KeyCode::Char('j'),
+                ..
+            }) => EditorCommand::GoToFrame(FrameDirection::Down),
+            (KeyCode::Char('w'), event::KeyModifiers::CONTROL, KeyEvent {
+                code: KeyCode::Char('k'),
+                ..
+            }) => EditorCommand::GoToFrame(FrameDirection::Up),
+            (KeyCode::Char('w'), event::KeyModifiers::CONTROL, KeyEvent {
+                code: KeyCode::Char('w'),
+                ..
+            }) => EditorCommand::GoToNextFrame,
             _ => EditorCommand::Noop,
         }
     }
@@ -91,19 +510,241 @@ impl KeyHandler {
                 code: KeyCode::Esc,
                 modifiers: event::KeyModifiers::NONE,
             } => EditorCommand::SetNormalMode,
+            KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::ExecuteCommand,
+            KeyEvent {
+                code: KeyCode::Tab,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::CompleteCommand(Direction::Forward),
+            KeyEvent {
+                code: KeyCode::BackTab,
+                ..
+            }
+            | KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::CompleteCommand(Direction::Backward),
             KeyEvent {
                 code: KeyCode::Backspace,
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::DeleteCommandChar,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('h'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::DeleteBackward),
             KeyEvent {
-                code: KeyCode::Enter,
+                code: KeyCode::Delete,
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::ExecuteCommand,
-            KeyEvent { 
+            } => EditorCommand::EditCommandLine(CommandLineEdit::DeleteForward),
+            KeyEvent {
+                code: KeyCode::Char('w'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::DeleteWord),
+            KeyEvent {
+                code: KeyCode::Char('u'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::DeleteToStart),
+            KeyEvent {
+                code: KeyCode::Left,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::Left),
+            KeyEvent {
+                code: KeyCode::Right,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::Right),
+            KeyEvent {
+                code: KeyCode::Home,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('b'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::Home),
+            KeyEvent {
+                code: KeyCode::End,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('e'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::End),
+            KeyEvent {
+                code: KeyCode::Up,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::HistoryOlder),
+            KeyEvent {
+                code: KeyCode::Down,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::HistoryNewer),
+            KeyEvent {
                 code: KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            } => EditorCommand::EditCommandLine(CommandLineEdit::Insert(c)),
+            _ => EditorCommand::Noop,
+        }
+    }
+
+    pub fn process_insert_mode_key(key_event: KeyEvent) -> EditorCommand {
+        match key_event {
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::SetNormalMode,
+            KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditText(InsertEdit::NewLine),
+            KeyEvent {
+                code: KeyCode::Backspace,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('h'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::EditText(InsertEdit::DeleteBackward),
+            KeyEvent {
+                code: KeyCode::Delete,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditText(InsertEdit::DeleteForward),
+            KeyEvent {
+                code: KeyCode::Tab,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditText(InsertEdit::Insert('\t')),
+            KeyEvent {
+                code: KeyCode::Left,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditText(InsertEdit::Left),
+            KeyEvent {
+                code: KeyCode::Right,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditText(InsertEdit::Right),
+            KeyEvent {
+                code: KeyCode::Up,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::EditText(InsertEdit::Up),
+            KeyEvent {
+                code: KeyCode::Down,
                 modifiers: event::KeyModifiers::NONE,
-            } => EditorCommand::WriteCommand(c),
+            } => EditorCommand::EditText(InsertEdit::Down),
+            KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Complete(Direction::Forward),
+            KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::Complete(Direction::Backward),
+            KeyEvent {
+                code: KeyCode::Char('y'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::AcceptCompletion,
+            KeyEvent {
+                code: KeyCode::Char('e'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::CancelCompletion,
+            KeyEvent {
+                code: KeyCode::Char(c),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            } => EditorCommand::EditText(InsertEdit::Insert(c)),
             _ => EditorCommand::Noop,
         }
     }
+
+    // Query editing works as on the command line; only moving the selection
+    // and opening the file are the finder's own.
+    pub fn process_finder_key(key_event: KeyEvent) -> EditorCommand {
+        match key_event {
+            KeyEvent {
+                code: KeyCode::Esc,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('c'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::SetNormalMode,
+            KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: event::KeyModifiers::NONE,
+            } => EditorCommand::OpenFinderSelection(None),
+            KeyEvent {
+                code: KeyCode::Char('s'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::OpenFinderSelection(Some(Split::Horizontal)),
+            KeyEvent {
+                code: KeyCode::Char('v'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::OpenFinderSelection(Some(Split::Vertical)),
+            KeyEvent {
+                code: KeyCode::Down,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('n'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::SelectFinderEntry(Direction::Forward),
+            KeyEvent {
+                code: KeyCode::Up,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('p'),
+                modifiers: event::KeyModifiers::CONTROL,
+            } => EditorCommand::SelectFinderEntry(Direction::Backward),
+            _ => match Self::process_command_mode_key(key_event) {
+                EditorCommand::EditCommandLine(edit) => EditorCommand::EditFinderQuery(edit),
+                _ => EditorCommand::Noop,
+            },
+        }
+    }
+
+    // Keys with their own meaning in the explorer window; anything else is
+    // handled as in any other frame.
+    pub fn process_explorer_key(key_event: KeyEvent) -> Option<EditorCommand> {
+        let action = match key_event {
+            KeyEvent {
+                code: KeyCode::Enter,
+                modifiers: event::KeyModifiers::NONE,
+            }
+            | KeyEvent {
+                code: KeyCode::Char('l' | 'o'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Open,
+            KeyEvent {
+                code: KeyCode::Char('h'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Collapse,
+            KeyEvent {
+                code: KeyCode::Char('a'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Create,
+            KeyEvent {
+                code: KeyCode::Char('r'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Rename,
+            KeyEvent {
+                code: KeyCode::Char('m'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Move,
+            KeyEvent {
+                code: KeyCode::Char('d'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Delete,
+            KeyEvent {
+                code: KeyCode::Char('R'),
+                modifiers: event::KeyModifiers::NONE | event::KeyModifiers::SHIFT,
+            } => ExplorerAction::Refresh,
+            KeyEvent {
+                code: KeyCode::Char('q'),
+                modifiers: event::KeyModifiers::NONE,
+            } => ExplorerAction::Close,
+            _ => return None,
+        };
+        Some(EditorCommand::Explorer(action))
+    }
 }
@@ -0,0 +1,184 @@
+use crate::prelude::*;
+use memmap2::Mmap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const LARGE_FILE_THRESHOLD_MB: usize = 100;
+pub const LARGE_FILE_MARGIN: usize = 200;
+pub const INDEX_REFRESH_TIME: Duration = Duration::from_millis(250);
+const INDEX_CHUNK: usize = 1 << 20;
+const DETECT_LENGTH: usize = 1 << 16;
+
+pub struct LargeFile {
+    file: Arc<fs::File>,
+    stamp: Option<FileStamp>,
+    map: Arc<Mmap>,
+    offsets: Arc<Mutex<Vec<usize>>>,
+    indexing: Arc<AtomicBool>,
+    pub file_options: FileOptions,
+    window_start: usize,
+}
+
+impl LargeFile {
+    pub fn should_map(file: &Path, threshold_mb: usize) -> bool {
+        fs::metadata(file).is_ok_and(|metadata| metadata.len() > (threshold_mb as u64) << 20)
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = Arc::new(fs::File::open(path)?);
+        let stamp = file.metadata().ok().map(FileStamp::from_metadata);
+        // SAFETY: the mapping is only valid while the file keeps at least its
+        // mapped length. Another process truncating it, as logrotate's
+        // copytruncate does, turns reads past the new end into SIGBUS, so every
+        // read of the map checks the open file with `truncated` first. The
+        // check can still race a truncation that lands between it and the
+        // read, which nothing short of copying the file would rule out.
+        let map = Arc::new(unsafe { Mmap::map(&*file)? });
+
+        let head = &map[..cmp::min(map.len(), DETECT_LENGTH)];
+        let head = head.iter().rposition(|byte| *byte == b'\n').map_or(head, |idx| &head[..=idx]);
+        let (mut file_options, _) = FileOptions::decode(head, None);
+        if !matches!(file_options.fileencoding, FileEncoding::Utf8 | FileEncoding::Latin1) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Large files must be UTF-8 or Latin-1"));
+        }
+        // Only the head was looked at, so don't trust its verdict on the final newline.
+        file_options.endofline = map.last() == Some(&b'\n');
+
+        let offsets = Arc::new(Mutex::new(vec![0]));
+        let indexing = Arc::new(AtomicBool::new(true));
+        let large = Self { file, stamp, map, offsets, indexing, file_options, window_start: 0 };
+        large.spawn_indexer();
+        Ok(large)
+    }
+
+    // Whether the file shrank below the mapped length since it was opened,
+    // going by the open file rather than the path, which may have been replaced.
+    fn truncated(file: &fs::File, stamp: Option<FileStamp>, mapped: usize) -> bool {
+        let current = file.metadata().ok().map(FileStamp::from_metadata);
+        current != stamp && current.is_none_or(|current| current.len() < mapped as u64)
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        Self::truncated(&self.file, self.stamp, self.map.len())
+    }
+
+    fn spawn_indexer(&self) {
+        let (file, stamp) = (Arc::clone(&self.file), self.stamp);
+        let (map, offsets, indexing) = (Arc::clone(&self.map), Arc::clone(&self.offsets), Arc::clone(&self.indexing));
+        thread::spawn(move || {
+            for (chunk_idx, chunk) in map.chunks(INDEX_CHUNK).enumerate() {
+                if !indexing.load(Ordering::Relaxed) || Self::truncated(&file, stamp, map.len()) {
+                    indexing.store(false, Ordering::Relaxed);
+                    return;
+                }
+                let base = chunk_idx * INDEX_CHUNK;
+                let found: Vec<usize> = chunk.iter()
+                    .enumerate()
+                    .filter(|(_, byte)| **byte == b'\n')
+                    .map(|(idx, _)| base + idx + 1)
+                    .filter(|start| *start < map.len())
+                    .collect();
+                if let Ok(mut offsets) = offsets.lock() {
+                    offsets.extend(found);
+                }
+            }
+            indexing.store(false, Ordering::Relaxed);
+        });
+    }
+
+    pub fn is_indexing(&self) -> bool {
+        self.indexing.load(Ordering::Relaxed)
+    }
+
+    pub fn number_of_lines(&self) -> usize {
+        self.offsets.lock().map_or(1, |offsets| offsets.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn bytes(&self) -> io::Result<&[u8]> {
+        if self.is_truncated() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "File was truncated while open"));
+        }
+        Ok(&self.map)
+    }
+
+    pub fn window_start(&self) -> usize {
+        self.window_start
+    }
+
+    // Gives nothing once the file was truncated, leaving it to the watcher to
+    // reload it.
+    pub fn read_lines(&mut self, start: usize, end: usize) -> Option<Vec<BufferLine>> {
+        if self.is_truncated() {
+            return None;
+        }
+        let offsets = self.offsets.lock().ok()?;
+        // Until indexing finishes the last known line may not have ended yet.
+        let complete = if self.is_indexing() { offsets.len() - 1 } else { offsets.len() };
+        let end = cmp::min(end, complete);
+        let start = cmp::min(start, end);
+        self.window_start = start;
+
+        let lines = (start..end)
+            .map(|idx| {
+                let line_end = offsets.get(idx + 1).map_or(self.map.len(), |next| next - 1);
+                let mut bytes = &self.map[offsets[idx]..cmp::max(line_end, offsets[idx])];
+                if idx == 0 && self.file_options.bomb {
+                    bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+                }
+                if self.file_options.fileformat == FileFormat::Dos {
+                    bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+                }
+                BufferLine { line_number: idx + 1, line: self.file_options.fileencoding.decode(bytes) }
+            })
+            .collect();
+        Some(lines)
+    }
+}
+
+impl Drop for LargeFile {
+    fn drop(&mut self) {
+        self.indexing.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn opens_files_above_the_threshold() {
+        let dir = TempDir::new("large");
+        let contents: String = (1..=30000).map(|idx| format!("line {:05} {}\n", idx, "x".repeat(28))).collect();
+        let file = dir.write("big.log", &contents);
+        let mut harness = Harness::new(80, 20, "");
+        harness.keys(&format!(":set largefile=1<CR>:e {}<CR>", file.display()));
+        assert!(harness.screen().row(19).contains("[large file] 1200000 bytes"));
+        harness.wait_for(|harness| !harness.editor().active_frame().buffer().is_indexing());
+        assert_eq!(harness.editor().active_frame().buffer().number_of_lines(), 30000);
+
+        harness.keys("G");
+        assert_eq!(harness.cursor().0, 29999);
+        assert!(harness.screen().text().contains("line 30000"));
+        harness.keys("gg<C-d>");
+        assert_eq!(harness.cursor(), (9, 0));
+        assert!(harness.screen().row(18).contains("30000 lines"));
+        harness.keys("x");
+        assert!(harness.screen().row(19).contains("modifiable"));
+
+        // Truncating the file under the mapping must not fault; the buffer follows it instead.
+        fs::write(&file, "short\n").unwrap();
+        harness.keys("G");
+        harness.wait_for(|harness| harness.screen().text().contains("reloaded"));
+        harness.wait_for(|harness| !harness.editor().active_frame().buffer().is_indexing());
+        harness.keys("");
+        assert_eq!(harness.editor().active_frame().buffer().number_of_lines(), 1);
+        assert_eq!(harness.cursor(), (0, 0));
+        assert!(harness.screen().row(0).contains("short"));
+    }
+}
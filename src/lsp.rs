@@ -0,0 +1,930 @@
+use crate::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Instant;
+
+pub const LSP_SIGN_GROUP: &str = "lsp";
+pub const SERVER_POLL_INTERVAL: Duration = Duration::from_millis(20);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_millis(200);
+
+pub struct ServerConfig {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub command: Vec<String>,
+    pub root_markers: Vec<String>,
+}
+
+impl ServerConfig {
+    fn new(name: &str, extensions: &[&str], command: &[&str], root_markers: &[&str]) -> Self {
+        let strings = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        Self {
+            name: name.to_string(),
+            extensions: strings(extensions),
+            command: strings(command),
+            root_markers: strings(root_markers),
+        }
+    }
+
+    fn defaults() -> Vec<Self> {
+        vec![
+            Self::new("rust", &["rs"], &["rust-analyzer"], &["Cargo.toml"]),
+            Self::new(
+                "typescript",
+                &["ts", "tsx", "js", "jsx", "mjs", "cjs"],
+                &["typescript-language-server", "--stdio"],
+                &["tsconfig.json", "jsconfig.json", "package.json"],
+            ),
+        ]
+    }
+
+    // Parses the arguments of :lsp add, e.g. "rust rs rust-analyzer".
+    pub fn parse(args: &str) -> EditorResult<Self> {
+        let mut parts = args.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(extensions)) => {
+                let command: Vec<String> = parts.map(String::from).collect();
+                if command.is_empty() {
+                    return Err(EditorError::InvalidArgument(args.to_string()));
+                }
+                Ok(Self {
+                    name: name.to_string(),
+                    extensions: extensions.split(',').map(String::from).collect(),
+                    command,
+                    root_markers: vec![String::from(".git")],
+                })
+            },
+            _ => Err(EditorError::InvalidArgument(args.to_string())),
+        }
+    }
+
+    fn handles(&self, file: &Path) -> bool {
+        file.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| self.extensions.iter().any(|handled| handled == extension))
+    }
+
+    // The nearest directory holding one of the root markers, or the file's own directory.
+    fn root(&self, file: &Path) -> PathBuf {
+        let file = canonical_path(file);
+        let dir = file.parent().map_or_else(|| PathBuf::from("/"), Path::to_path_buf);
+        dir.ancestors()
+            .find(|ancestor| self.root_markers.iter().any(|marker| ancestor.join(marker).exists()))
+            .map_or(dir.clone(), Path::to_path_buf)
+    }
+}
+
+pub fn language_id(file: &Path) -> String {
+    let extension = file.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    match extension {
+        "rs" => "rust",
+        "ts" => "typescript",
+        "tsx" => "typescriptreact",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "javascriptreact",
+        "py" => "python",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        _ => extension,
+    }.to_string()
+}
+
+// The protocol counts columns in UTF-16 code units, the buffer in bytes.
+pub fn to_utf16(text: &str, column: usize) -> usize {
+    let mut column = cmp::min(column, text.len());
+    while !text.is_char_boundary(column) {
+        column -= 1;
+    }
+    text[..column].chars().map(char::len_utf16).sum()
+}
+
+pub fn from_utf16(text: &str, units: usize) -> usize {
+    let mut counted = 0;
+    for (idx, c) in text.char_indices() {
+        if counted >= units {
+            return idx;
+        }
+        counted += c.len_utf16();
+    }
+    text.len()
+}
+
+pub fn file_uri(file: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in canonical_path(file).to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+pub fn uri_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = vec![];
+    let mut idx = 0;
+    while idx < path.len() {
+        let escaped = (path[idx] == b'%')
+            .then(|| path.get(idx + 1..idx + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                idx += 3;
+            },
+            None => {
+                bytes.push(path[idx]);
+                idx += 1;
+            },
+        }
+    }
+    Some(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+fn position_json(position: CursorPosition) -> Value {
+    json!({ "line": position.line, "character": position.column })
+}
+
+fn parse_position(value: &Value) -> CursorPosition {
+    let number = |name: &str| value[name].as_u64().unwrap_or(0) as usize;
+    CursorPosition { line: number("line"), column: number("character") }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+impl Severity {
+    fn from_lsp(value: &Value) -> Self {
+        match value.as_u64() {
+            Some(2) => Severity::Warning,
+            Some(3) => Severity::Information,
+            Some(4) => Severity::Hint,
+            _ => Severity::Error,
+        }
+    }
+
+    pub fn sign(&self) -> Sign {
+        let (text, color, priority) = match self {
+            Severity::Error => ("E>", style::Color::Red, 40),
+            Severity::Warning => ("W>", style::Color::Yellow, 30),
+            Severity::Information => ("I>", style::Color::Blue, 20),
+            Severity::Hint => ("H>", style::Color::DarkGrey, 20),
+        };
+        Sign { color: Some(color), priority, ..Sign::new(text, LSP_SIGN_GROUP) }
+    }
+
+    pub fn message_level(&self) -> MessageLevel {
+        match self {
+            Severity::Error => MessageLevel::Error,
+            Severity::Warning => MessageLevel::Warning,
+            Severity::Information | Severity::Hint => MessageLevel::Info,
+        }
+    }
+}
+
+// Positions are in UTF-16 units as received until a buffer converts them to bytes.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub start: CursorPosition,
+    pub end: CursorPosition,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn parse(value: &Value) -> Self {
+        Self {
+            start: parse_position(&value["range"]["start"]),
+            end: parse_position(&value["range"]["end"]),
+            severity: Severity::from_lsp(&value["severity"]),
+            message: value["message"].as_str().unwrap_or("").to_string(),
+        }
+    }
+
+    pub fn covers_line(&self, line: usize) -> bool {
+        self.start.line <= line && line <= self.end.line
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub start: CursorPosition,
+    pub end: CursorPosition,
+    pub text: String,
+}
+
+impl TextEdit {
+    fn parse(value: &Value) -> Self {
+        Self {
+            start: parse_position(&value["range"]["start"]),
+            end: parse_position(&value["range"]["end"]),
+            text: value["newText"].as_str().unwrap_or("").to_string(),
+        }
+    }
+}
+
+// Edits made to a buffer since it was last sent to its language server.
+pub enum TextChange {
+    Full,
+    Edit(TextEdit),
+}
+
+pub struct Location {
+    pub file: PathBuf,
+    pub position: CursorPosition,
+}
+
+impl Location {
+    fn parse(value: &Value) -> Option<Self> {
+        let uri = value["uri"].as_str().or_else(|| value["targetUri"].as_str())?;
+        let range = if value["targetSelectionRange"].is_object() { &value["targetSelectionRange"] } else { &value["range"] };
+        Some(Self { file: uri_path(uri)?, position: parse_position(&range["start"]) })
+    }
+
+    fn parse_list(value: &Value) -> Vec<Self> {
+        match value {
+            Value::Array(locations) => locations.iter().filter_map(Self::parse).collect(),
+            Value::Null => vec![],
+            location => Self::parse(location).into_iter().collect(),
+        }
+    }
+}
+
+pub enum ServerEvent {
+    Message(usize, Value),
+    Exited(usize),
+}
+
+pub enum ServerReply {
+    Diagnostics(PathBuf, Vec<Diagnostic>),
+    Hover(Vec<String>),
+    Definition(Vec<Location>),
+    References(Vec<Location>),
+    Edit(Vec<(PathBuf, Vec<TextEdit>)>),
+    CodeActions(Vec<String>),
+    // Items for the completion menu anchored at the given position.
+    Completion(CursorPosition, Vec<CompletionItem>),
+    Message(MessageLevel, String),
+}
+
+enum Request {
+    Initialize,
+    Hover,
+    Definition,
+    References,
+    Rename,
+    CodeActions,
+    Completion(CursorPosition),
+    Other,
+}
+
+struct LanguageServer {
+    id: usize,
+    config: String,
+    root: PathBuf,
+    child: Child,
+    stdin: ChildStdin,
+    next_request: u64,
+    pending: HashMap<u64, Request>,
+    initialized: bool,
+    queued: Vec<Value>,
+    incremental: bool,
+    documents: HashMap<PathBuf, i64>,
+    diagnostics: HashMap<PathBuf, Vec<Value>>,
+}
+
+impl LanguageServer {
+    fn start(id: usize, config: &ServerConfig, root: PathBuf, events: Sender<ServerEvent>) -> io::Result<Self> {
+        let mut child = Command::new(&config.command[0])
+            .args(&config.command[1..])
+            .current_dir(&root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("no stdout"))?;
+        thread::spawn(move || Self::read_messages(id, stdout, events));
+
+        let mut server = Self {
+            id,
+            config: config.name.clone(),
+            root,
+            child,
+            stdin,
+            next_request: 0,
+            pending: HashMap::new(),
+            initialized: false,
+            queued: vec![],
+            incremental: false,
+            documents: HashMap::new(),
+            diagnostics: HashMap::new(),
+        };
+        let root_uri = file_uri(&server.root);
+        let capabilities = json!({
+            "textDocument": {
+                "synchronization": { "dynamicRegistration": false },
+                "hover": { "contentFormat": ["plaintext", "markdown"] },
+                "completion": {
+                    "completionItem": { "snippetSupport": true, "documentationFormat": ["plaintext", "markdown"] },
+                },
+                "definition": { "linkSupport": true },
+                "references": {},
+                "rename": {},
+                "codeAction": {
+                    "codeActionLiteralSupport": {
+                        "codeActionKind": { "valueSet": ["", "quickfix", "refactor", "source"] },
+                    },
+                },
+                "publishDiagnostics": {},
+            },
+            "workspace": { "applyEdit": true, "workspaceEdit": { "documentChanges": true } },
+        });
+        server.request(Request::Initialize, "initialize", json!({
+            "processId": std::process::id(),
+            "rootUri": root_uri,
+            "capabilities": capabilities,
+            "workspaceFolders": [{ "uri": root_uri, "name": config.name }],
+        }))?;
+        Ok(server)
+    }
+
+    fn read_messages(id: usize, stdout: impl Read, events: Sender<ServerEvent>) {
+        let mut reader = BufReader::new(stdout);
+        loop {
+            let mut length = None;
+            loop {
+                let mut header = String::new();
+                match reader.read_line(&mut header) {
+                    Ok(0) | Err(_) => {
+                        let _ = events.send(ServerEvent::Exited(id));
+                        return;
+                    },
+                    Ok(_) => {},
+                }
+                let header = header.trim();
+                if header.is_empty() && length.is_some() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse::<usize>().ok();
+                    }
+                }
+            }
+
+            let mut body = vec![0; length.unwrap_or(0)];
+            if reader.read_exact(&mut body).is_err() {
+                let _ = events.send(ServerEvent::Exited(id));
+                return;
+            }
+            if let Ok(message) = serde_json::from_slice(&body) {
+                if events.send(ServerEvent::Message(id, message)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        // Nothing but the handshake may go out before the server answered initialize.
+        if !self.initialized && message["method"] != "initialize" {
+            self.queued.push(message);
+            return Ok(());
+        }
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.stdin.flush()
+    }
+
+    fn request(&mut self, kind: Request, method: &str, params: Value) -> io::Result<()> {
+        self.next_request += 1;
+        self.pending.insert(self.next_request, kind);
+        self.send(json!({ "jsonrpc": "2.0", "id": self.next_request, "method": method, "params": params }))
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn respond(&mut self, id: Value, result: Value) -> io::Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    fn initialize(&mut self, result: &Value) -> io::Result<()> {
+        let sync = &result["capabilities"]["textDocumentSync"];
+        let kind = if sync.is_object() { &sync["change"] } else { sync };
+        self.incremental = kind.as_u64() == Some(2);
+        self.initialized = true;
+        self.notify("initialized", json!({}))?;
+        for message in std::mem::take(&mut self.queued) {
+            self.send(message)?;
+        }
+        Ok(())
+    }
+
+    fn text_document(file: &Path) -> Value {
+        json!({ "uri": file_uri(file) })
+    }
+
+    fn position_params(file: &Path, position: CursorPosition) -> Value {
+        json!({ "textDocument": Self::text_document(file), "position": position_json(position) })
+    }
+}
+
+impl Drop for LanguageServer {
+    fn drop(&mut self) {
+        if self.initialized {
+            let _ = self.request(Request::Other, "shutdown", Value::Null);
+            let _ = self.notify("exit", Value::Null);
+        }
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(SERVER_POLL_INTERVAL);
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct LanguageServers {
+    configs: Vec<ServerConfig>,
+    servers: Vec<LanguageServer>,
+    events: Sender<ServerEvent>,
+    next_id: usize,
+    // Configurations that failed or were stopped aren't started again until :lsp start.
+    disabled: Vec<String>,
+    code_actions: Vec<(usize, Value)>,
+}
+
+impl LanguageServers {
+    pub fn new() -> (Self, Receiver<ServerEvent>) {
+        let (events, receiver) = mpsc::channel();
+        let servers = Self {
+            configs: ServerConfig::defaults(),
+            servers: vec![],
+            events,
+            next_id: 0,
+            disabled: vec![],
+            code_actions: vec![],
+        };
+        (servers, receiver)
+    }
+
+    pub fn configure(&mut self, config: ServerConfig) {
+        self.disabled.retain(|name| *name != config.name);
+        self.configs.retain(|configured| configured.name != config.name);
+        self.configs.push(config);
+    }
+
+    fn config_for(&self, file: &Path) -> Option<&ServerConfig> {
+        self.configs.iter().rev().find(|config| config.handles(file))
+    }
+
+    fn server_for(&mut self, file: &Path) -> EditorResult<&mut LanguageServer> {
+        self.servers.iter_mut()
+            .find(|server| server.documents.contains_key(file))
+            .ok_or(EditorError::NoLanguageServer)
+    }
+
+    fn server_index(&mut self, config: &str, root: &Path) -> EditorResult<usize> {
+        if let Some(idx) = self.servers.iter().position(|server| server.config == config && server.root == root) {
+            return Ok(idx);
+        }
+        let config = self.configs.iter().find(|configured| configured.name == config).ok_or(EditorError::NoLanguageServer)?;
+        self.next_id += 1;
+        match LanguageServer::start(self.next_id, config, root.to_path_buf(), self.events.clone()) {
+            Ok(server) => {
+                self.servers.push(server);
+                Ok(self.servers.len() - 1)
+            },
+            Err(err) => {
+                self.disabled.push(config.name.clone());
+                Err(EditorError::Io(PathBuf::from(&config.command[0]), err))
+            },
+        }
+    }
+
+    // Opens the buffer's document on its server, starting the server if
+    // needed, or sends the changes made since the last sync.
+    pub fn sync(&mut self, buffer: &mut Buffer) -> EditorResult<()> {
+        let file = match &buffer.file_path {
+            Some(file) if !buffer.is_large() && !buffer.is_hex() => file.clone(),
+            _ => return Ok(()),
+        };
+        let changes = match &mut buffer.sync {
+            Some(changes) if changes.is_empty() => return Ok(()),
+            Some(changes) => std::mem::take(changes),
+            // Another copy of the file may already be open on the server.
+            None if self.servers.iter().any(|server| server.documents.contains_key(&file)) => return Ok(()),
+            None => {
+                let config = match self.config_for(&file) {
+                    Some(config) if !self.disabled.contains(&config.name) => config,
+                    _ => return Ok(()),
+                };
+                let (name, root) = (config.name.clone(), config.root(&file));
+                let idx = self.server_index(&name, &root)?;
+                let server = &mut self.servers[idx];
+                server.documents.insert(file.clone(), 1);
+                let params = json!({
+                    "textDocument": {
+                        "uri": file_uri(&file),
+                        "languageId": language_id(&file),
+                        "version": 1,
+                        "text": Self::text(buffer),
+                    },
+                });
+                server.notify("textDocument/didOpen", params).map_err(|err| EditorError::Io(file.clone(), err))?;
+                buffer.sync = Some(vec![]);
+                return Ok(());
+            },
+        };
+
+        let server = match self.server_for(&file) {
+            Ok(server) => server,
+            Err(_) => return Ok(()),
+        };
+        let full = !server.incremental || changes.iter().any(|change| matches!(change, TextChange::Full));
+        let content_changes: Vec<Value> = if full {
+            vec![json!({ "text": Self::text(buffer) })]
+        } else {
+            changes.into_iter()
+                .filter_map(|change| match change {
+                    TextChange::Edit(edit) => Some(json!({
+                        "range": { "start": position_json(edit.start), "end": position_json(edit.end) },
+                        "text": edit.text,
+                    })),
+                    TextChange::Full => None,
+                })
+                .collect()
+        };
+        let version = server.documents.entry(file.clone()).or_insert(0);
+        *version += 1;
+        let params = json!({
+            "textDocument": { "uri": file_uri(&file), "version": *version },
+            "contentChanges": content_changes,
+        });
+        server.notify("textDocument/didChange", params).map_err(|err| EditorError::Io(file, err))
+    }
+
+    // Closes documents whose buffers went away so that another buffer for
+    // the same file can take their place.
+    pub fn close_unused(&mut self, attached: &[PathBuf]) {
+        for server in self.servers.iter_mut() {
+            let unused: Vec<PathBuf> = server.documents.keys().filter(|file| !attached.contains(file)).cloned().collect();
+            for file in unused {
+                server.documents.remove(&file);
+                server.diagnostics.remove(&file);
+                let _ = server.notify("textDocument/didClose", json!({ "textDocument": LanguageServer::text_document(&file) }));
+            }
+        }
+    }
+
+    fn text(buffer: &Buffer) -> String {
+        buffer.text_lines().collect::<Vec<&str>>().join("\n") + "\n"
+    }
+
+    fn request(&mut self, file: &Path, kind: Request, method: &str, params: Value) -> EditorResult<()> {
+        self.server_for(file)?
+            .request(kind, method, params)
+            .map_err(|err| EditorError::Io(file.to_path_buf(), err))
+    }
+
+    pub fn hover(&mut self, file: &Path, position: CursorPosition) -> EditorResult<()> {
+        self.request(file, Request::Hover, "textDocument/hover", LanguageServer::position_params(file, position))
+    }
+
+    pub fn definition(&mut self, file: &Path, position: CursorPosition) -> EditorResult<()> {
+        self.request(file, Request::Definition, "textDocument/definition", LanguageServer::position_params(file, position))
+    }
+
+    pub fn references(&mut self, file: &Path, position: CursorPosition) -> EditorResult<()> {
+        let mut params = LanguageServer::position_params(file, position);
+        params["context"] = json!({ "includeDeclaration": true });
+        self.request(file, Request::References, "textDocument/references", params)
+    }
+
+    pub fn rename(&mut self, file: &Path, position: CursorPosition, new_name: &str) -> EditorResult<()> {
+        let mut params = LanguageServer::position_params(file, position);
+        params["newName"] = json!(new_name);
+        self.request(file, Request::Rename, "textDocument/rename", params)
+    }
+
+    pub fn code_actions(&mut self, file: &Path, position: CursorPosition) -> EditorResult<()> {
+        let server = self.server_for(file)?;
+        let diagnostics: Vec<Value> = server.diagnostics.get(file)
+            .into_iter()
+            .flatten()
+            .filter(|diagnostic| Diagnostic::parse(diagnostic).covers_line(position.line))
+            .cloned()
+            .collect();
+        let params = json!({
+            "textDocument": LanguageServer::text_document(file),
+            "range": { "start": position_json(position), "end": position_json(position) },
+            "context": { "diagnostics": diagnostics },
+        });
+        self.request(file, Request::CodeActions, "textDocument/codeAction", params)
+    }
+
+    // The anchor is where the completed text starts in the buffer and comes back with the reply.
+    pub fn completion(&mut self, file: &Path, position: CursorPosition, anchor: CursorPosition) -> EditorResult<()> {
+        let params = LanguageServer::position_params(file, position);
+        self.request(file, Request::Completion(anchor), "textDocument/completion", params)
+    }
+
+    // Runs one of the actions listed by the last code action request, counting from 1.
+    pub fn apply_code_action(&mut self, number: usize) -> EditorResult<Option<ServerReply>> {
+        let (id, action) = number.checked_sub(1)
+            .and_then(|idx| self.code_actions.get(idx))
+            .cloned()
+            .ok_or_else(|| EditorError::InvalidArgument(number.to_string()))?;
+        let server = self.servers.iter_mut()
+            .find(|server| server.id == id)
+            .ok_or(EditorError::NoLanguageServer)?;
+
+        // A code action may carry an edit, a command or both; a bare command is a string.
+        let command = match &action["command"] {
+            Value::String(_) => Some(action.clone()),
+            Value::Object(_) => Some(action["command"].clone()),
+            _ => None,
+        };
+        if let Some(command) = command {
+            let params = json!({ "command": command["command"], "arguments": command["arguments"] });
+            server.request(Request::Other, "workspace/executeCommand", params)
+                .map_err(|err| EditorError::Io(PathBuf::from(&server.config), err))?;
+        }
+        Ok(action.get("edit").map(|edit| ServerReply::Edit(Self::parse_workspace_edit(edit))))
+    }
+
+    pub fn diagnostics(&self) -> Vec<(PathBuf, Diagnostic)> {
+        let mut diagnostics: Vec<(PathBuf, Diagnostic)> = self.servers.iter()
+            .flat_map(|server| server.diagnostics.iter())
+            .flat_map(|(file, diagnostics)| diagnostics.iter().map(|diagnostic| (file.clone(), Diagnostic::parse(diagnostic))))
+            .collect();
+        diagnostics.sort_by_key(|(file, diagnostic)| (file.clone(), diagnostic.start.line, diagnostic.start.column));
+        diagnostics
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self.configs.iter()
+            .map(|config| {
+                let running = self.servers.iter().filter(|server| server.config == config.name).count();
+                let state = if self.disabled.contains(&config.name) {
+                    String::from("stopped")
+                } else if running > 0 {
+                    format!("running ({})", running)
+                } else {
+                    String::from("not started")
+                };
+                format!("{} [{}] {}: {}", config.name, config.extensions.join(","), config.command.join(" "), state)
+            })
+            .collect();
+        lines.extend(self.servers.iter().flat_map(|server| {
+            server.documents.keys().map(move |file| format!("  {} {}", server.config, file.display()))
+        }));
+        lines
+    }
+
+    pub fn stop(&mut self) {
+        for server in self.servers.drain(..) {
+            if !self.disabled.contains(&server.config) {
+                self.disabled.push(server.config.clone());
+            }
+        }
+    }
+
+    pub fn enable(&mut self) {
+        self.disabled.clear();
+    }
+
+    pub fn handle(&mut self, event: ServerEvent) -> Vec<ServerReply> {
+        let (id, message) = match event {
+            ServerEvent::Message(id, message) => (id, message),
+            ServerEvent::Exited(id) => {
+                return match self.servers.iter().position(|server| server.id == id) {
+                    Some(idx) => {
+                        let server = self.servers.remove(idx);
+                        self.disabled.push(server.config.clone());
+                        let text = format!("Language server {} exited, use :lsp start to restart it", server.config);
+                        let mut replies = vec![ServerReply::Message(MessageLevel::Warning, text)];
+                        replies.extend(server.diagnostics.keys().map(|file| ServerReply::Diagnostics(file.clone(), vec![])));
+                        replies
+                    },
+                    None => vec![],
+                };
+            },
+        };
+        let server = match self.servers.iter_mut().find(|server| server.id == id) {
+            Some(server) => server,
+            None => return vec![],
+        };
+
+        let result = match (message.get("id"), message["method"].as_str()) {
+            (Some(request_id), Some(method)) => Self::answer_request(server, request_id.clone(), method, &message["params"]),
+            (Some(request_id), None) => {
+                let kind = request_id.as_u64().and_then(|request_id| server.pending.remove(&request_id));
+                match (kind, message.get("error")) {
+                    (_, Some(error)) => {
+                        let text = error["message"].as_str().unwrap_or("request failed");
+                        Ok(vec![ServerReply::Message(MessageLevel::Error, format!("{}: {}", server.config, text))])
+                    },
+                    (Some(kind), None) => {
+                        let replies = Self::parse_response(server, kind, &message["result"]);
+                        if let Some(ServerReply::CodeActions(_)) = replies.first() {
+                            let actions = message["result"].as_array().cloned().unwrap_or_default();
+                            self.code_actions = actions.into_iter().map(|action| (id, action)).collect();
+                        }
+                        return replies;
+                    },
+                    (None, None) => Ok(vec![]),
+                }
+            },
+            (None, Some(method)) => Ok(Self::parse_notification(server, method, &message["params"])),
+            (None, None) => Ok(vec![]),
+        };
+        result.unwrap_or_else(|err| vec![ServerReply::Message(MessageLevel::Error, err.to_string())])
+    }
+
+    fn answer_request(server: &mut LanguageServer, id: Value, method: &str, params: &Value) -> io::Result<Vec<ServerReply>> {
+        match method {
+            "workspace/applyEdit" => {
+                server.respond(id, json!({ "applied": true }))?;
+                Ok(vec![ServerReply::Edit(Self::parse_workspace_edit(&params["edit"]))])
+            },
+            "workspace/configuration" => {
+                let items = params["items"].as_array().map_or(0, Vec::len);
+                server.respond(id, Value::Array(vec![Value::Null; items]))?;
+                Ok(vec![])
+            },
+            "window/workDoneProgress/create" | "client/registerCapability" | "client/unregisterCapability" => {
+                server.respond(id, Value::Null)?;
+                Ok(vec![])
+            },
+            _ => {
+                let error = json!({ "code": -32601, "message": format!("Unhandled method {}", method) });
+                server.send(json!({ "jsonrpc": "2.0", "id": id, "error": error }))?;
+                Ok(vec![])
+            },
+        }
+    }
+
+    fn parse_response(server: &mut LanguageServer, kind: Request, result: &Value) -> Vec<ServerReply> {
+        match kind {
+            Request::Initialize => match server.initialize(result) {
+                Ok(()) => vec![],
+                Err(err) => vec![ServerReply::Message(MessageLevel::Error, err.to_string())],
+            },
+            Request::Hover => {
+                let lines = Self::hover_lines(&result["contents"]);
+                if lines.is_empty() {
+                    vec![ServerReply::Message(MessageLevel::Info, String::from("No hover information"))]
+                } else {
+                    vec![ServerReply::Hover(lines)]
+                }
+            },
+            Request::Definition => vec![ServerReply::Definition(Location::parse_list(result))],
+            Request::References => vec![ServerReply::References(Location::parse_list(result))],
+            Request::Rename if result.is_null() => {
+                vec![ServerReply::Message(MessageLevel::Warning, String::from("Nothing to rename"))]
+            },
+            Request::Rename => vec![ServerReply::Edit(Self::parse_workspace_edit(result))],
+            Request::CodeActions => {
+                let titles = result.as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|action| action["title"].as_str().unwrap_or("").to_string())
+                    .collect();
+                vec![ServerReply::CodeActions(titles)]
+            },
+            Request::Completion(anchor) => {
+                let items = match result {
+                    Value::Array(items) => items,
+                    _ => result["items"].as_array().map_or(&[][..], Vec::as_slice),
+                };
+                vec![ServerReply::Completion(anchor, items.iter().map(Self::completion_item).collect())]
+            },
+            Request::Other => vec![],
+        }
+    }
+
+    fn completion_item(item: &Value) -> CompletionItem {
+        let label = item["label"].as_str().unwrap_or("").to_string();
+        let text = item["textEdit"]["newText"].as_str()
+            .or_else(|| item["insertText"].as_str())
+            .map_or_else(|| label.clone(), String::from);
+        let mut documentation: Vec<String> = item["detail"].as_str().into_iter().flat_map(str::lines).map(String::from).collect();
+        documentation.extend(Self::hover_lines(&item["documentation"]));
+        CompletionItem {
+            label,
+            text,
+            snippet: item["insertTextFormat"].as_u64() == Some(2),
+            source: CompletionSource::LanguageServer,
+            documentation,
+        }
+    }
+
+    fn parse_notification(server: &mut LanguageServer, method: &str, params: &Value) -> Vec<ServerReply> {
+        match method {
+            "textDocument/publishDiagnostics" => {
+                let file = match params["uri"].as_str().and_then(uri_path) {
+                    Some(file) => file,
+                    None => return vec![],
+                };
+                let raw = params["diagnostics"].as_array().cloned().unwrap_or_default();
+                let diagnostics = raw.iter().map(Diagnostic::parse).collect();
+                server.diagnostics.insert(file.clone(), raw);
+                vec![ServerReply::Diagnostics(file, diagnostics)]
+            },
+            "window/showMessage" => {
+                let level = match params["type"].as_u64() {
+                    Some(1) => MessageLevel::Error,
+                    Some(2) => MessageLevel::Warning,
+                    _ => MessageLevel::Info,
+                };
+                let text = params["message"].as_str().unwrap_or("").to_string();
+                vec![ServerReply::Message(level, format!("{}: {}", server.config, text))]
+            },
+            _ => vec![],
+        }
+    }
+
+    fn hover_lines(contents: &Value) -> Vec<String> {
+        let text = match contents {
+            Value::String(text) => text.clone(),
+            Value::Array(parts) => parts.iter().flat_map(Self::hover_lines).collect::<Vec<String>>().join("\n"),
+            Value::Object(_) => contents["value"].as_str().unwrap_or("").to_string(),
+            _ => String::new(),
+        };
+        // Markdown fences only get in the way in a plain text pager.
+        text.lines()
+            .filter(|line| !line.trim_start().starts_with("```"))
+            .map(String::from)
+            .collect::<Vec<String>>()
+            .into_iter()
+            .skip_while(|line| line.trim().is_empty())
+            .collect()
+    }
+
+    fn parse_workspace_edit(edit: &Value) -> Vec<(PathBuf, Vec<TextEdit>)> {
+        let mut files: Vec<(PathBuf, Vec<TextEdit>)> = vec![];
+        let mut add = |uri: &Value, edits: &Value| {
+            if let Some(file) = uri.as_str().and_then(uri_path) {
+                let edits = edits.as_array().into_iter().flatten().map(TextEdit::parse).collect();
+                files.push((file, edits));
+            }
+        };
+        if let Some(changes) = edit["changes"].as_object() {
+            changes.iter().for_each(|(uri, edits)| add(&json!(uri), edits));
+        }
+        // Creating, renaming and deleting files isn't supported, only text edits.
+        for change in edit["documentChanges"].as_array().into_iter().flatten() {
+            add(&change["textDocument"]["uri"], &change["edits"]);
+        }
+        files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn talks_to_a_language_server() {
+        let dir = TempDir::new("lsp");
+        let file = dir.write("notes.txt", "let count = 1\nTODO count\n");
+        let mut harness = Harness::new(80, 12, "");
+        harness.keys(&format!(":lsp add fake txt {}<CR>:e {}<CR>", env!("FAKE_LANGUAGE_SERVER"), file.display()));
+        harness.wait_for(|harness| harness.screen().row(1).starts_with("W>"));
+
+        harness.keys("ggwK").wait_for(|harness| harness.screen().text().contains("count (3 lines)"));
+        harness.keys("<Esc>jwgd").wait_for(|harness| harness.cursor() == (0, 4));
+
+        harness.keys(":lsp rename total<CR>")
+            .wait_for(|harness| harness.buffer_text() == "let total = 1\nTODO total");
+        harness.keys("j:lsp action<CR>").wait_for(|harness| harness.screen().text().contains("Uppercase line"));
+        harness.keys("<Esc>:lsp action 1<CR>").wait_for(|harness| harness.buffer_text() == "let total = 1\n total");
+        // Until the diagnostics are republished the stale TODO fix would still be listed first.
+        harness.wait_for(|harness| !harness.screen().row(1).starts_with("W>"));
+        harness.keys(":lsp action<CR>").wait_for(|harness| harness.screen().text().contains("Uppercase line"));
+        harness.keys("<Esc>:lsp action 1<CR>").wait_for(|harness| harness.buffer_text() == "let total = 1\n TOTAL");
+
+        harness.keys("oitems.").wait_for(|harness| harness.screen().text().contains("length  lsp"));
+        assert!(harness.keys("<C-n>").screen().text().contains("fn length(&self) -> usize"));
+        harness.keys("li<C-y><Esc>");
+        assert_eq!(harness.buffer_text(), "let total = 1\n TOTAL\nitems.lines(n)");
+    }
+}
@@ -0,0 +1,114 @@
+use crate::prelude::*;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+pub const MAKE_REFRESH_TIME: Duration = Duration::from_millis(100);
+pub const MAKE_OUTPUT_NAME: &str = "[Make Output]";
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+// A build started by :make. Its combined stdout and stderr are read on a
+// background thread and picked up by `update` while the editor keeps running.
+pub struct MakeJob {
+    pub command: String,
+    pub output: Vec<String>,
+    child: Child,
+    pending: Arc<Mutex<Vec<String>>>,
+    reading: Arc<AtomicBool>,
+    started: Instant,
+}
+
+impl MakeJob {
+    pub fn start(command: String) -> EditorResult<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            // Redirecting the shell itself covers every command in a compound
+            // one, and its own syntax errors, rather than just the last command.
+            .arg(format!("exec 2>&1\n{}", command))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| EditorError::Io(PathBuf::from("sh"), err))?;
+        let stdout = child.stdout.take();
+        let pending = Arc::new(Mutex::new(vec![]));
+        let reading = Arc::new(AtomicBool::new(true));
+        let (shared_pending, shared_reading) = (Arc::clone(&pending), Arc::clone(&reading));
+        thread::spawn(move || {
+            if let Some(stdout) = stdout {
+                let mut reader = BufReader::new(stdout);
+                let mut line = vec![];
+                while matches!(reader.read_until(b'\n', &mut line), Ok(1..)) {
+                    let text = String::from_utf8_lossy(&line).trim_end_matches(['\n', '\r']).to_string();
+                    if let Ok(mut pending) = shared_pending.lock() {
+                        pending.push(text);
+                    }
+                    line.clear();
+                }
+            }
+            shared_reading.store(false, Ordering::Relaxed);
+        });
+
+        Ok(Self { command, output: vec![], child, pending, reading, started: Instant::now() })
+    }
+
+    // Moves newly read lines into `output`, returning whether there were any.
+    pub fn update(&mut self) -> bool {
+        let lines = self.pending.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default();
+        let changed = !lines.is_empty();
+        self.output.extend(lines);
+        changed
+    }
+
+    // The exit code once the build is over and all of its output was read;
+    // None inside means it was killed by a signal.
+    pub fn finished(&mut self) -> Option<Option<i32>> {
+        if self.reading.load(Ordering::Relaxed) {
+            return None;
+        }
+        self.child.try_wait().ok().flatten().map(|status| status.code())
+    }
+
+    pub fn indicator(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let frame = SPINNER[(elapsed.as_millis() / MAKE_REFRESH_TIME.as_millis()) as usize % SPINNER.len()];
+        format!("make {} {}s", frame, elapsed.as_secs())
+    }
+}
+
+impl Drop for MakeJob {
+    fn drop(&mut self) {
+        if self.finished().is_none() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn runs_make_in_the_background() {
+        let dir = TempDir::new("make");
+        dir.write("main.c", "int main() {\n    return boom;\n}\n");
+        let script = dir.write("build.sh", format!(
+            "sleep 0.2\necho Compiling\necho '{0}/main.c:1:5: warning: old style'\necho '{0}/main.c:2:12: error: boom undeclared'\nexit 1\n",
+            dir.path().display(),
+        ));
+
+        let mut harness = Harness::new(200, 12, "");
+        harness.keys(&format!(":set makeprg=echo\\ Starting\\ >&2;\\ sh\\ {}<CR>:make<CR>", script.display()));
+        assert!(harness.screen().row(4).contains(" make "));
+        assert!(harness.screen().row(5).contains(&format!("$ echo Starting >&2; sh {}", script.display())));
+        harness.wait_for(|harness| harness.screen().row(11).starts_with("(2 of 2) error: boom undeclared"));
+        assert_eq!(harness.cursor(), (1, 11));
+        assert!(harness.screen().row(6).contains("Starting"));
+        assert!(harness.screen().row(7).contains("Compiling"));
+        assert!(harness.keys(":cp<CR>").screen().row(11).starts_with("(1 of 2) warning: old style"));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+
+pub const JUMP_LIST_SIZE: usize = 100;
+pub const CHANGE_LIST_SIZE: usize = 100;
+
+pub fn adjust_line(line: usize, at: usize, removed: usize, added: usize) -> Option<usize> {
+    if line < at {
+        Some(line)
+    } else if line < at + removed {
+        None
+    } else {
+        Some(line - removed + added)
+    }
+}
+
+pub fn is_global_mark(name: char) -> bool {
+    name.is_ascii_uppercase()
+}
+
+pub fn canonical_path(file: &Path) -> PathBuf {
+    fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf())
+}
+
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || canonical_path(a) == canonical_path(b)
+}
+
+#[derive(Default)]
+pub struct Marks {
+    marks: HashMap<char, CursorPosition>,
+}
+
+impl Marks {
+    pub fn set(&mut self, name: char, position: CursorPosition) {
+        self.marks.insert(name, position);
+    }
+
+    pub fn get(&self, name: char) -> Option<CursorPosition> {
+        self.marks.get(&name).copied()
+    }
+
+    pub fn remove(&mut self, name: char) {
+        self.marks.remove(&name);
+    }
+
+    pub fn global(&self) -> impl Iterator<Item = (char, CursorPosition)> + '_ {
+        self.marks.iter()
+            .filter(|(name, _)| is_global_mark(**name))
+            .map(|(name, position)| (*name, *position))
+    }
+
+    pub fn adjust(&mut self, at: usize, removed: usize, added: usize) {
+        self.marks.retain(|_, position| match adjust_line(position.line, at, removed, added) {
+            Some(line) => {
+                position.line = line;
+                true
+            },
+            None => false,
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct Jump {
+    pub file: Option<PathBuf>,
+    pub position: CursorPosition,
+}
+
+#[derive(Default)]
+pub struct JumpList {
+    entries: Vec<Jump>,
+    current: usize,
+}
+
+impl JumpList {
+    pub fn push(&mut self, jump: Jump) {
+        self.entries.retain(|entry| entry.file != jump.file || entry.position.line != jump.position.line);
+        self.entries.push(jump);
+        if self.entries.len() > JUMP_LIST_SIZE {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len();
+    }
+
+    pub fn back(&mut self, from: Jump, count: usize) -> Option<Jump> {
+        if self.current == self.entries.len() {
+            // Remember where we started so Ctrl-i can come back to it.
+            self.push(from);
+            self.current = self.entries.len() - 1;
+        }
+        if count > self.current {
+            return None;
+        }
+        self.current -= count;
+        self.entries.get(self.current).cloned()
+    }
+
+    pub fn forward(&mut self, count: usize) -> Option<Jump> {
+        if self.current + count >= self.entries.len() {
+            return None;
+        }
+        self.current += count;
+        self.entries.get(self.current).cloned()
+    }
+
+    pub fn entries(&self) -> &[Jump] {
+        &self.entries
+    }
+
+    pub fn adjust(&mut self, file: &Option<PathBuf>, at: usize, removed: usize, added: usize) {
+        for entry in self.entries.iter_mut().filter(|entry| &entry.file == file) {
+            entry.position.line = adjust_line(entry.position.line, at, removed, added).unwrap_or(at);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ChangeList {
+    changes: Vec<CursorPosition>,
+    current: usize,
+}
+
+impl ChangeList {
+    pub fn record(&mut self, position: CursorPosition) {
+        if self.changes.last().is_some_and(|last| last.line == position.line) {
+            self.changes.pop();
+        }
+        self.changes.push(position);
+        if self.changes.len() > CHANGE_LIST_SIZE {
+            self.changes.remove(0);
+        }
+        self.current = self.changes.len();
+    }
+
+    pub fn older(&mut self, count: usize) -> Option<CursorPosition> {
+        if count > self.current || self.changes.is_empty() {
+            return None;
+        }
+        self.current -= count;
+        self.changes.get(self.current).copied()
+    }
+
+    pub fn newer(&mut self, count: usize) -> Option<CursorPosition> {
+        if self.current + count >= self.changes.len() {
+            return None;
+        }
+        self.current += count;
+        self.changes.get(self.current).copied()
+    }
+
+    pub fn adjust(&mut self, at: usize, removed: usize, added: usize) {
+        for change in self.changes.iter_mut() {
+            change.line = adjust_line(change.line, at, removed, added).unwrap_or(at);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::Harness;
+
+    #[test]
+    fn jumps_to_marks_and_back() {
+        let text = (1..=30).map(|n| format!("line {}", n)).collect::<Vec<_>>().join("\n");
+        let mut harness = Harness::new(80, 14, &text);
+        harness.keys("5G3lma20G'a");
+        assert_eq!(harness.cursor(), (4, 0));
+        harness.keys("gg`a");
+        assert_eq!(harness.cursor(), (4, 3));
+
+        harness.keys("<C-o>");
+        assert_eq!(harness.cursor(), (0, 0));
+        harness.keys("<C-o>");
+        assert_eq!(harness.cursor(), (19, 0));
+        harness.keys("<Tab>");
+        assert_eq!(harness.cursor(), (0, 0));
+
+        harness.keys("mBG'B");
+        assert_eq!(harness.cursor(), (0, 0));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::prelude::*;
+
+pub const MESSAGE_HISTORY_SIZE: usize = 200;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum MessageLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+impl MessageLevel {
+    pub fn color(&self) -> Option<style::Color> {
+        match self {
+            MessageLevel::Info => None,
+            MessageLevel::Warning => Some(style::Color::Yellow),
+            MessageLevel::Error => Some(style::Color::Red),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Message {
+    pub level: MessageLevel,
+    pub text: String,
+}
+
+#[derive(Default)]
+pub struct Messages {
+    history: Vec<Message>,
+    current: Option<Message>,
+    echoed: bool,
+}
+
+impl Messages {
+    pub fn push(&mut self, level: MessageLevel, text: impl Into<String>) {
+        let message = Message { level, text: text.into() };
+        self.history.push(message.clone());
+        if self.history.len() > MESSAGE_HISTORY_SIZE {
+            self.history.remove(0);
+        }
+        self.current = Some(message);
+        self.echoed = false;
+    }
+
+    // Shows a message without keeping it in the history.
+    pub fn echo(&mut self, level: MessageLevel, text: impl Into<String>) {
+        self.current = Some(Message { level, text: text.into() });
+        self.echoed = true;
+    }
+
+    pub fn clear_echo(&mut self) {
+        if self.echoed {
+            self.dismiss();
+        }
+    }
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(MessageLevel::Info, text);
+    }
+
+    pub fn warn(&mut self, text: impl Into<String>) {
+        self.push(MessageLevel::Warning, text);
+    }
+
+    pub fn error(&mut self, err: impl fmt::Display) {
+        self.push(MessageLevel::Error, err.to_string());
+    }
+
+    pub fn current(&self) -> Option<&Message> {
+        self.current.as_ref()
+    }
+
+    pub fn dismiss(&mut self) {
+        self.current = None;
+    }
+
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+}
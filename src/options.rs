@@ -0,0 +1,162 @@
+use crate::prelude::*;
+use std::str::FromStr;
+
+pub const OPTION_NAMES: &[&str] = &[
+    "bomb",
+    "breakindent",
+    "complete",
+    "completetrigger",
+    "endofline",
+    "fileencoding",
+    "fileformat",
+    "foldcolumn",
+    "errorformat",
+    "foldmethod",
+    "largefile",
+    "linebreak",
+    "makeprg",
+    "modifiable",
+    "number",
+    "readonly",
+    "relativenumber",
+    "signcolumn",
+    "wrap",
+];
+pub const BOOLEAN_OPTION_NAMES: &[&str] = &["bomb", "breakindent", "endofline", "linebreak", "modifiable", "number", "readonly", "relativenumber", "wrap"];
+
+// Splits at each separator not escaped with a backslash, so option values
+// can hold spaces ("makeprg=make\ -j4") or commas.
+pub fn split_unescaped(value: &str, separator: char) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == separator {
+            items.push(String::new());
+            continue;
+        }
+        let c = match chars.peek() {
+            Some(next) if c == '\\' && *next == separator => chars.next().unwrap_or(c),
+            _ => c,
+        };
+        if let Some(item) = items.last_mut() {
+            item.push(c);
+        }
+    }
+    items
+}
+
+pub enum SignColumn {
+    Auto,
+    Yes,
+    No,
+}
+
+impl FromStr for SignColumn {
+    type Err = EditorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(SignColumn::Auto),
+            "yes" => Ok(SignColumn::Yes),
+            "no" => Ok(SignColumn::No),
+            _ => Err(EditorError::InvalidArgument(format!("signcolumn={}", value))),
+        }
+    }
+}
+
+// Options that belong to the editor as a whole rather than to a frame or buffer.
+pub struct EditorOptions {
+    pub makeprg: String,
+    pub errorformat: Vec<ErrorFormat>,
+}
+
+impl Default for EditorOptions {
+    fn default() -> Self {
+        Self {
+            makeprg: String::from("cargo build"),
+            errorformat: ErrorFormat::parse_list(DEFAULT_ERROR_FORMAT).unwrap_or_default(),
+        }
+    }
+}
+
+impl EditorOptions {
+    pub fn set(&mut self, arg: &str) -> EditorResult<()> {
+        match arg.split_once('=') {
+            Some(("makeprg" | "mp", value)) => self.makeprg = value.to_string(),
+            Some(("errorformat" | "efm", value)) => self.errorformat = ErrorFormat::parse_list(value)?,
+            _ => return Err(EditorError::UnknownOption(arg.to_string())),
+        }
+        Ok(())
+    }
+}
+
+pub struct WindowOptions {
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub breakindent: bool,
+    pub number: bool,
+    pub relativenumber: bool,
+    pub signcolumn: SignColumn,
+    pub foldcolumn: usize,
+    pub foldmethod: FoldMethod,
+    pub largefile: usize,
+    pub complete: Vec<CompletionSource>,
+    pub completetrigger: String,
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        Self {
+            wrap: false,
+            linebreak: false,
+            breakindent: false,
+            number: true,
+            relativenumber: false,
+            signcolumn: SignColumn::Auto,
+            foldcolumn: 0,
+            foldmethod: FoldMethod::Manual,
+            largefile: LARGE_FILE_THRESHOLD_MB,
+            complete: CompletionSource::all(),
+            completetrigger: String::from("./"),
+        }
+    }
+}
+
+impl WindowOptions {
+    pub fn set(&mut self, arg: &str) -> EditorResult<()> {
+        if let Some((name, value)) = arg.split_once('=') {
+            match name {
+                "signcolumn" | "scl" => self.signcolumn = value.parse()?,
+                "foldcolumn" | "fdc" => {
+                    self.foldcolumn = value.parse().map_err(|_| EditorError::InvalidArgument(arg.to_string()))?
+                },
+                "foldmethod" | "fdm" => self.foldmethod = value.parse()?,
+                "largefile" | "lf" => {
+                    self.largefile = value.parse().map_err(|_| EditorError::InvalidArgument(arg.to_string()))?
+                },
+                "complete" | "cpt" => self.complete = CompletionSource::parse_list(value)?,
+                "completetrigger" | "ctg" => self.completetrigger = value.to_string(),
+                _ => return Err(EditorError::UnknownOption(name.to_string())),
+            }
+            return Ok(());
+        }
+
+        let (name, value) = if let Some(name) = arg.strip_prefix("no") {
+            (name, false)
+        } else {
+            (arg, true)
+        };
+
+        let option = match name {
+            "wrap" => &mut self.wrap,
+            "linebreak" | "lbr" => &mut self.linebreak,
+            "breakindent" | "bri" => &mut self.breakindent,
+            "number" | "nu" => &mut self.number,
+            "relativenumber" | "rnu" => &mut self.relativenumber,
+            _ => return Err(EditorError::UnknownOption(arg.to_string())),
+        };
+
+        *option = value;
+        Ok(())
+    }
+}
@@ -0,0 +1,357 @@
+use crate::prelude::*;
+use regex::{Regex, RegexBuilder};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+pub const QUICKFIX_NAME: &str = "[Quickfix List]";
+pub const GREP_REFRESH_TIME: Duration = Duration::from_millis(100);
+const BINARY_CHECK_LENGTH: usize = 8000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuickfixEntry {
+    pub file: PathBuf,
+    pub line: usize,
+    // A char index, since compilers count characters rather than bytes.
+    pub column: usize,
+    pub text: String,
+}
+
+impl QuickfixEntry {
+    pub fn listing(&self) -> String {
+        format!("{}|{} col {}| {}", self.file.display(), self.line + 1, self.column + 1, self.text)
+    }
+}
+
+#[derive(Default)]
+pub struct QuickfixList {
+    pub title: String,
+    pub entries: Vec<QuickfixEntry>,
+    pub current: usize,
+}
+
+impl QuickfixList {
+    pub fn new(title: String, entries: Vec<QuickfixEntry>) -> Self {
+        Self { title, entries, current: 0 }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.entries.iter().map(QuickfixEntry::listing).collect()
+    }
+
+    pub fn current(&self) -> Option<&QuickfixEntry> {
+        self.entries.get(self.current)
+    }
+
+    pub fn step(&mut self, direction: Direction) -> EditorResult<()> {
+        if self.entries.is_empty() {
+            return Err(EditorError::NoErrors);
+        }
+        self.current = match direction {
+            Direction::Forward if self.current + 1 < self.entries.len() => self.current + 1,
+            Direction::Backward if self.current > 0 => self.current - 1,
+            _ => return Err(EditorError::NoMoreItems),
+        };
+        Ok(())
+    }
+
+    // Adds entries while the current one stays selected, keeping the list in
+    // file and line order however the matches came in.
+    pub fn extend(&mut self, entries: Vec<QuickfixEntry>) {
+        let current = self.current().cloned();
+        self.entries.extend(entries);
+        self.entries.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+        self.current = current.and_then(|current| self.entries.iter().position(|entry| *entry == current)).unwrap_or(0);
+    }
+
+    // Selects the nth entry, counting from one like the listing does.
+    pub fn select(&mut self, number: usize) -> EditorResult<()> {
+        if self.entries.is_empty() {
+            return Err(EditorError::NoErrors);
+        }
+        self.current = cmp::min(number.saturating_sub(1), self.entries.len() - 1);
+        Ok(())
+    }
+}
+
+// The pattern may be quoted to hold spaces; the paths to search follow it
+// and default to the working directory.
+pub fn grep_args(args: &str) -> Option<(&str, Vec<PathBuf>)> {
+    let (pattern, rest) = match args.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = args[1..].find(quote)? + 1;
+            (&args[1..end], &args[end + 1..])
+        },
+        _ => args.split_once(' ').unwrap_or((args, "")),
+    };
+    let mut paths: Vec<PathBuf> = rest.split_whitespace().map(PathBuf::from).collect();
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+    (!pattern.is_empty()).then_some((pattern, paths))
+}
+
+// A search started by :grep. It walks the given paths for a smartcase
+// regex, spreading the files over one worker thread per core, and hands
+// matches over to `update` as they are found.
+pub struct GrepJob {
+    pub pattern: String,
+    pending: Arc<Mutex<Vec<QuickfixEntry>>>,
+    searching: Arc<AtomicBool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl GrepJob {
+    pub fn start(pattern: &str, paths: Vec<PathBuf>) -> EditorResult<Self> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(!pattern.chars().any(char::is_uppercase))
+            .build()
+            .map_err(|_| EditorError::InvalidArgument(pattern.to_string()))?;
+        let pending = Arc::new(Mutex::new(vec![]));
+        let searching = Arc::new(AtomicBool::new(true));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (shared_pending, shared_searching, shared_cancelled) = (Arc::clone(&pending), Arc::clone(&searching), Arc::clone(&cancelled));
+        thread::spawn(move || {
+            Self::search(&regex, &paths, &shared_pending, &shared_cancelled);
+            shared_searching.store(false, Ordering::Relaxed);
+        });
+        Ok(Self { pattern: pattern.to_string(), pending, searching, cancelled })
+    }
+
+    fn search(regex: &Regex, paths: &[PathBuf], pending: &Mutex<Vec<QuickfixEntry>>, cancelled: &AtomicBool) {
+        let workers = thread::available_parallelism().map_or(4, |workers| workers.get());
+        let (sender, receiver) = mpsc::channel::<PathBuf>();
+        let receiver = Mutex::new(receiver);
+        let running = || !cancelled.load(Ordering::Relaxed);
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    while let Some(file) = receiver.lock().ok().and_then(|receiver| receiver.recv().ok()).filter(|_| running()) {
+                        let entries = search_file(regex, &file);
+                        if let (false, Ok(mut pending)) = (entries.is_empty(), pending.lock()) {
+                            pending.extend(entries);
+                        }
+                    }
+                });
+            }
+            for path in paths {
+                if path.is_dir() {
+                    walk_files(path, |file| {
+                        let file = if path == Path::new(".") { PathBuf::from(file) } else { path.join(file) };
+                        running() && sender.send(file).is_ok()
+                    });
+                } else {
+                    let _ = sender.send(path.clone());
+                }
+            }
+            drop(sender);
+        });
+    }
+
+    // Takes the matches found since the last call.
+    pub fn update(&mut self) -> Vec<QuickfixEntry> {
+        self.pending.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default()
+    }
+
+    // Whether every file was searched and all of its matches were taken.
+    pub fn finished(&self) -> bool {
+        !self.searching.load(Ordering::Relaxed) && self.pending.lock().is_ok_and(|pending| pending.is_empty())
+    }
+}
+
+impl Drop for GrepJob {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+fn search_file(regex: &Regex, file: &Path) -> Vec<QuickfixEntry> {
+    let bytes = match fs::read(file) {
+        Ok(bytes) => bytes,
+        Err(_) => return vec![],
+    };
+    if bytes[..cmp::min(bytes.len(), BINARY_CHECK_LENGTH)].contains(&0) {
+        return vec![];
+    }
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .enumerate()
+        .filter_map(|(line, text)| {
+            let found = regex.find(text)?;
+            Some(QuickfixEntry {
+                file: file.to_path_buf(),
+                line,
+                column: text[..found.start()].chars().count(),
+                text: text.trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+pub const DEFAULT_ERROR_FORMAT: &str = "cargo,gcc,tsc";
+const GCC_FORMATS: &[&str] = &["%f:%l:%c: %t: %m", "%f:%l: %t: %m"];
+const TSC_FORMATS: &[&str] = &["%f(%l,%c): %t %m"];
+
+// One entry of 'errorformat': the multi-line rustc diagnostics, or a line
+// pattern where %f, %l, %c, %t and %m capture the file, line, column, type
+// and message, %* skips text and %% is a literal percent sign.
+pub enum ErrorFormat {
+    Rustc,
+    Line(Regex),
+}
+
+impl ErrorFormat {
+    // Parses the value of 'errorformat', e.g. "cargo,%f:%l: %m"; a comma
+    // inside a pattern is written as "\,".
+    pub fn parse_list(value: &str) -> EditorResult<Vec<Self>> {
+        let mut formats = vec![];
+        for item in split_unescaped(value, ',') {
+            match item.as_str() {
+                "" => {},
+                "cargo" | "rustc" => formats.push(ErrorFormat::Rustc),
+                "gcc" => formats.extend(GCC_FORMATS.iter().map(|format| Self::line(format)).collect::<EditorResult<Vec<_>>>()?),
+                "tsc" => formats.extend(TSC_FORMATS.iter().map(|format| Self::line(format)).collect::<EditorResult<Vec<_>>>()?),
+                pattern => formats.push(Self::line(pattern)?),
+            }
+        }
+        Ok(formats)
+    }
+
+    fn line(pattern: &str) -> EditorResult<Self> {
+        let invalid = || EditorError::InvalidArgument(format!("errorformat={}", pattern));
+        if !pattern.contains("%f") || !pattern.contains("%l") {
+            return Err(invalid());
+        }
+        let mut regex = String::from("^");
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+                continue;
+            }
+            regex.push_str(match chars.next() {
+                Some('f') => r"(?P<file>.+?)",
+                Some('l') => r"(?P<line>\d+)",
+                Some('c') => r"(?P<column>\d+)",
+                Some('t') => r"(?P<kind>[A-Za-z ]+?)",
+                Some('m') => r"(?P<message>.*)",
+                Some('*') => r".*?",
+                Some('%') => "%",
+                _ => return Err(invalid()),
+            });
+        }
+        regex.push('$');
+        Regex::new(&regex).map(ErrorFormat::Line).map_err(|_| invalid())
+    }
+
+    fn match_line(&self, line: &str) -> Option<QuickfixEntry> {
+        let regex = match self {
+            ErrorFormat::Line(regex) => regex,
+            ErrorFormat::Rustc => return None,
+        };
+        let captures = regex.captures(line)?;
+        let number = |name: &str| captures.name(name).and_then(|found| found.as_str().parse::<usize>().ok());
+        let message = captures.name("message").map_or("", |found| found.as_str());
+        let text = match captures.name("kind") {
+            Some(kind) => format!("{}: {}", kind.as_str(), message),
+            None => message.to_string(),
+        };
+        Some(QuickfixEntry {
+            file: PathBuf::from(captures.name("file")?.as_str()),
+            line: number("line")?.saturating_sub(1),
+            column: number("column").unwrap_or(1).saturating_sub(1),
+            text,
+        })
+    }
+}
+
+// Collects the errors in compiler output. Rustc and cargo diagnostics span two
+// lines, "error[E0425]: message" followed by " --> file:line:column".
+pub fn parse_errors(output: &str, formats: &[ErrorFormat]) -> Vec<QuickfixEntry> {
+    let rustc = formats.iter().any(|format| matches!(format, ErrorFormat::Rustc));
+    let mut entries = vec![];
+    let mut message = None;
+    for line in output.lines() {
+        if let Some(entry) = formats.iter().find_map(|format| format.match_line(line)) {
+            entries.push(entry);
+            continue;
+        }
+        if !rustc {
+            continue;
+        }
+        if line.starts_with("error") || line.starts_with("warning") {
+            message = Some(line);
+            continue;
+        }
+        let (text, location) = match (message, line.trim_start().strip_prefix("--> ")) {
+            (Some(text), Some(location)) => (text, location),
+            _ => continue,
+        };
+        let mut parts = location.rsplitn(3, ':');
+        let (column, number, file) = (parts.next(), parts.next(), parts.next());
+        if let (Some(Ok(column @ 1..)), Some(Ok(number @ 1..)), Some(file)) =
+            (column.map(str::parse::<usize>), number.map(str::parse::<usize>), file)
+        {
+            entries.push(QuickfixEntry { file: PathBuf::from(file), line: number - 1, column: column - 1, text: text.to_string() });
+        }
+        message = None;
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn collects_grep_matches() {
+        let output = "warning: unused variable: `x`\n --> src/main.rs:2:9\n  |\nerror: aborting due to 1 previous error\n";
+        let formats = ErrorFormat::parse_list(DEFAULT_ERROR_FORMAT).unwrap();
+        assert_eq!(parse_errors(output, &formats), vec![QuickfixEntry {
+            file: PathBuf::from("src/main.rs"),
+            line: 1,
+            column: 8,
+            text: String::from("warning: unused variable: `x`"),
+        }]);
+
+        let output = "src/a.ts(3,5): error TS2322: Type 'x' is wrong\nmain.c:7:2: fatal error: missing.h\nlib.c:9: note, see here\n";
+        let formats = ErrorFormat::parse_list(&format!("{},%f:%l: %m\\, %*", DEFAULT_ERROR_FORMAT)).unwrap();
+        let texts: Vec<String> = parse_errors(output, &formats).iter().map(QuickfixEntry::listing).collect();
+        assert_eq!(texts, vec![
+            "src/a.ts|3 col 5| error: TS2322: Type 'x' is wrong",
+            "main.c|7 col 2| fatal error: missing.h",
+            "lib.c|9 col 1| note",
+        ]);
+
+        let dir = TempDir::new("grep");
+        dir.write(".gitignore", "target/\n");
+        dir.write("src/main.rs", "fn main() {\n    let total = count();\n}\n");
+        dir.write("src/lib.rs", "pub fn count() -> usize {\n    Total\n}\n");
+        dir.write("target/built.rs", "total\n");
+
+        let mut harness = Harness::new(200, 12, "");
+        harness.keys(&format!(":grep nothing {}<CR>", dir.path().display()));
+        harness.wait_for(|harness| harness.screen().row(11).starts_with("Pattern not found: nothing"));
+
+        // Matches fill the open quickfix window as they come in, in file order.
+        harness.keys(&format!(":copen<CR>:grep tota[l] {}<CR>", dir.path().display()));
+        harness.wait_for(|harness| harness.screen().row(6).contains("src/main.rs|2 col 9| let total = count(); "));
+        assert!(harness.screen().row(5).contains("src/lib.rs|2 col 5| Total "));
+        harness.keys(":cc 1<CR>");
+        assert_eq!(harness.buffer_text(), "pub fn count() -> usize {\n    Total\n}");
+        assert_eq!(harness.cursor(), (1, 4));
+        assert!(harness.screen().row(11).starts_with("(1 of 2) Total"));
+        harness.keys(":cn<CR>");
+        assert_eq!(harness.cursor(), (1, 8));
+        assert!(harness.keys(":cn<CR>").screen().row(11).contains("No more items"));
+
+        harness.keys(":copen<CR>");
+        assert_eq!(harness.cursor(), (1, 0));
+        harness.keys("k<CR>");
+        assert_eq!(harness.cursor(), (1, 4));
+        harness.keys(":cclose<CR>");
+        assert!(harness.screen().row(10).starts_with("lib.rs"));
+    }
+}
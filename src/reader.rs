@@ -1,13 +1,77 @@
 use crate::prelude::*;
+#[cfg(test)]
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
 
-pub struct Reader;
+pub enum InputEvent {
+    Key(KeyEvent),
+    FilesChanged(Vec<PathBuf>),
+    Server(ServerEvent),
+    Idle,
+    #[cfg(test)]
+    EndOfInput,
+}
+
+#[derive(Default)]
+pub struct Reader {
+    pub watcher: FileWatcher,
+    server_events: Option<Receiver<ServerEvent>>,
+    #[cfg(test)]
+    script: Option<VecDeque<KeyEvent>>,
+}
 
 impl Reader {
-   pub fn read_key(&self)  -> crossterm::Result<KeyEvent> {
+   pub fn new(server_events: Receiver<ServerEvent>) -> Self {
+       Self {
+           server_events: Some(server_events),
+           ..Self::default()
+       }
+   }
+
+   // Replays the given keys instead of reading the terminal and reports the
+   // end of input once they run out.
+   #[cfg(test)]
+   pub fn set_script(&mut self, keys: Vec<KeyEvent>) {
+       self.script = Some(keys.into());
+   }
+
+   fn server_event(&self) -> Option<ServerEvent> {
+       self.server_events.as_ref().and_then(|events| events.try_recv().ok())
+   }
+
+   pub fn next_event(&mut self, timeout: Duration) -> crossterm::Result<InputEvent> {
+       #[cfg(test)]
+       if self.script.is_some() {
+           if let Some(event) = self.server_event() {
+               return Ok(InputEvent::Server(event));
+           }
+           let changed = self.watcher.poll_changes();
+           if !changed.is_empty() {
+               return Ok(InputEvent::FilesChanged(changed));
+           }
+           let key = self.script.as_mut().and_then(VecDeque::pop_front);
+           return Ok(key.map_or(InputEvent::EndOfInput, InputEvent::Key));
+       }
+
+       let deadline = Instant::now() + timeout;
        loop {
-           if event::poll(Duration::from_millis(500))? {
+           if let Some(event) = self.server_event() {
+               return Ok(InputEvent::Server(event));
+           }
+           let changed = self.watcher.poll_changes();
+           if !changed.is_empty() {
+               return Ok(InputEvent::FilesChanged(changed));
+           }
+
+           let now = Instant::now();
+           if now >= deadline {
+               return Ok(InputEvent::Idle);
+           }
+           let interval = if self.server_events.is_some() { SERVER_POLL_INTERVAL } else { FILE_POLL_INTERVAL };
+           if event::poll(cmp::min(deadline - now, interval))? {
                if let Event::Key(event) = event::read()? {
-                   return Ok(event);
+                   return Ok(InputEvent::Key(event));
                }
            }
        }
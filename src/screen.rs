@@ -0,0 +1,95 @@
+// An in-memory terminal that understands the escape sequences the editor
+// emits, so a headless editor can be rendered and inspected.
+pub struct Screen {
+    cells: Vec<Vec<char>>,
+    cursor: (usize, usize),
+    saved_cursor: (usize, usize),
+    pub cursor_visible: bool,
+}
+
+impl Screen {
+    pub fn new(columns: usize, lines: usize) -> Self {
+        Self {
+            cells: vec![vec![' '; columns]; lines],
+            cursor: (0, 0),
+            saved_cursor: (0, 0),
+            cursor_visible: true,
+        }
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn row(&self, row: usize) -> String {
+        self.cells.get(row).map_or(String::new(), |cells| cells.iter().collect::<String>().trim_end().to_string())
+    }
+
+    pub fn text(&self) -> String {
+        (0..self.cells.len()).map(|row| self.row(row)).collect::<Vec<String>>().join("\n")
+    }
+
+    fn put(&mut self, c: char) {
+        let (column, row) = self.cursor;
+        if let Some(cell) = self.cells.get_mut(row).and_then(|cells| cells.get_mut(column)) {
+            *cell = c;
+        }
+        self.cursor.0 += 1;
+    }
+
+    fn clear_line_from(&mut self, column: usize) {
+        if let Some(cells) = self.cells.get_mut(self.cursor.1) {
+            cells.iter_mut().skip(column).for_each(|cell| *cell = ' ');
+        }
+    }
+
+    pub fn apply(&mut self, output: &str) {
+        let mut chars = output.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\x1b' => match chars.next() {
+                    Some('[') => {
+                        let mut params = String::new();
+                        let mut command = None;
+                        for c in chars.by_ref() {
+                            if c.is_ascii_alphabetic() {
+                                command = Some(c);
+                                break;
+                            }
+                            params.push(c);
+                        }
+                        self.apply_csi(&params, command);
+                    },
+                    Some('7') => self.saved_cursor = self.cursor,
+                    Some('8') => self.cursor = self.saved_cursor,
+                    _ => {},
+                },
+                '\r' => self.cursor.0 = 0,
+                '\n' => self.cursor.1 += 1,
+                _ => self.put(c),
+            }
+        }
+    }
+
+    fn apply_csi(&mut self, params: &str, command: Option<char>) {
+        let numbers: Vec<usize> = params.trim_start_matches('?')
+            .split(';')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect();
+        match (command, params) {
+            (Some('H'), _) => {
+                let row = numbers.first().copied().unwrap_or(1).saturating_sub(1);
+                let column = numbers.get(1).copied().unwrap_or(1).saturating_sub(1);
+                self.cursor = (column, row);
+            },
+            (Some('J'), "2") => self.cells.iter_mut().for_each(|cells| cells.iter_mut().for_each(|cell| *cell = ' ')),
+            (Some('K'), "" | "0") => self.clear_line_from(self.cursor.0),
+            (Some('s'), _) => self.saved_cursor = self.cursor,
+            (Some('u'), _) => self.cursor = self.saved_cursor,
+            (Some('l'), "?25") => self.cursor_visible = false,
+            (Some('h'), "?25") => self.cursor_visible = true,
+            // Colors and attributes don't show up in the text grid.
+            _ => {},
+        }
+    }
+}
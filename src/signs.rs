@@ -0,0 +1,96 @@
+use crate::prelude::*;
+use std::collections::BTreeMap;
+
+pub const SIGN_WIDTH: usize = 2;
+pub const USER_SIGN_GROUP: &str = "user";
+
+#[derive(Clone, Debug)]
+pub struct Sign {
+    pub text: String,
+    pub group: String,
+    pub priority: u8,
+    pub color: Option<style::Color>,
+}
+
+impl Sign {
+    pub fn new(text: &str, group: &str) -> Self {
+        Self {
+            text: text.chars().take(SIGN_WIDTH).collect(),
+            group: group.to_string(),
+            priority: 10,
+            color: None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Signs {
+    placed: BTreeMap<usize, Vec<Sign>>,
+}
+
+impl Signs {
+    pub fn place(&mut self, line: usize, sign: Sign) {
+        let signs = self.placed.entry(line).or_default();
+        signs.retain(|placed| placed.group != sign.group);
+        signs.push(sign);
+        signs.sort_by_key(|placed| cmp::Reverse(placed.priority));
+    }
+
+    pub fn unplace(&mut self, line: usize, group: &str) {
+        if let Some(signs) = self.placed.get_mut(&line) {
+            signs.retain(|placed| placed.group != group);
+            if signs.is_empty() {
+                self.placed.remove(&line);
+            }
+        }
+    }
+
+    pub fn clear_group(&mut self, group: &str) {
+        self.placed.retain(|_, signs| {
+            signs.retain(|placed| placed.group != group);
+            !signs.is_empty()
+        });
+    }
+
+    pub fn get(&self, line: usize) -> Option<&Sign> {
+        self.placed.get(&line).and_then(|signs| signs.first())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placed.is_empty()
+    }
+
+    pub fn adjust(&mut self, at: usize, removed: usize, added: usize) {
+        self.placed = std::mem::take(&mut self.placed)
+            .into_iter()
+            .filter_map(|(line, signs)| adjust_line(line, at, removed, added).map(|line| (line, signs)))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::Harness;
+
+    #[test]
+    fn sizes_the_gutter_for_signs_and_relative_numbers() {
+        let text = (1..=12).map(|n| format!("line {}", n)).collect::<Vec<_>>().join("\n");
+        let mut harness = Harness::new(60, 14, &text);
+        let rows = |harness: &Harness, count: usize| -> Vec<String> {
+            (0..count).map(|row| harness.screen().row(row)[..14].to_string()).collect()
+        };
+
+        harness.keys(":set relativenumber<CR>3j");
+        assert_eq!(rows(&harness, 5), ["    3 line 1  ", "    2 line 2  ", "    1 line 3  ", "4     line 4  ", "    1 line 5  "]);
+        assert_eq!(harness.screen().cursor(), (6, 3));
+
+        harness.keys(":sign place 2 >><CR>:sign place 4 W<CR>");
+        assert_eq!(rows(&harness, 5), ["      3 line 1", ">>    2 line 2", "      1 line 3", "W 4     line 4", "      1 line 5"]);
+        assert_eq!(harness.screen().cursor(), (8, 3));
+
+        harness.keys(":set norelativenumber<CR>:set nonumber<CR>");
+        assert_eq!(rows(&harness, 4), ["  line 1      ", ">>line 2      ", "  line 3      ", "W line 4      "]);
+        assert_eq!(harness.keys(":sign clear<CR>").screen().cursor(), (0, 3));
+        assert!(harness.screen().row(0).starts_with("line 1 "));
+    }
+}
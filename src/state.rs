@@ -0,0 +1,63 @@
+use crate::prelude::*;
+
+pub const SESSION_FILE: &str = "session";
+
+pub fn state_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(base.join("editor"))
+}
+
+#[derive(Default)]
+pub struct SessionState {
+    pub global_marks: Vec<(char, PathBuf, CursorPosition)>,
+    pub jumps: Vec<Jump>,
+}
+
+impl SessionState {
+    pub fn load() -> Self {
+        let contents = match state_dir().map(|dir| fs::read_to_string(dir.join(SESSION_FILE))) {
+            Some(Ok(contents)) => contents,
+            _ => return Self::default(),
+        };
+
+        let mut state = Self::default();
+        for line in contents.lines() {
+            let fields: Vec<&str> = line.splitn(5, ' ').collect();
+            let position = match (fields.get(2).map(|f| f.parse()), fields.get(3).map(|f| f.parse())) {
+                (Some(Ok(line)), Some(Ok(column))) => CursorPosition { line, column },
+                _ => continue,
+            };
+
+            match fields[..] {
+                ["mark", name, _, _, file] if name.len() == 1 => {
+                    state.global_marks.push((name.chars().next().unwrap(), PathBuf::from(file), position));
+                },
+                ["jump", _, _, _, file] => {
+                    state.jumps.push(Jump { file: Some(PathBuf::from(file)), position });
+                },
+                _ => {},
+            }
+        }
+
+        state
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let dir = state_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No state directory"))?;
+        fs::create_dir_all(&dir)?;
+
+        let mut contents = String::new();
+        for (name, file, position) in &self.global_marks {
+            contents.push_str(&format!("mark {} {} {} {}\n", name, position.line, position.column, file.display()));
+        }
+        for jump in &self.jumps {
+            if let Some(file) = &jump.file {
+                contents.push_str(&format!("jump - {} {} {}\n", jump.position.line, jump.position.column, file.display()));
+            }
+        }
+
+        fs::write(dir.join(SESSION_FILE), contents)
+    }
+}
@@ -0,0 +1,229 @@
+use crate::prelude::*;
+
+pub const SWAP_DIR: &str = "swap";
+pub const SWAP_EXTENSIONS: &[&str] = &["swp", "swo", "swn", "swm"];
+pub const SWAP_UPDATE_TIME: Duration = Duration::from_secs(4);
+pub const SWAP_UPDATE_COUNT: usize = 200;
+const SWAP_HEADER: &str = "editor swap 1";
+
+pub fn process_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // Signal 0 only checks whether the process exists and may be signalled.
+        let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        result == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        true
+    }
+}
+
+pub struct SwapInfo {
+    pub path: PathBuf,
+    pub pid: u32,
+    pub file: PathBuf,
+    pub modified: bool,
+    pub contents: Vec<String>,
+}
+
+impl SwapInfo {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Not a swap file");
+
+        // Only '\n' ends a line, since a buffer line may itself end in '\r'.
+        let mut lines = contents.strip_suffix('\n').unwrap_or(&contents).split('\n');
+        if lines.next() != Some(SWAP_HEADER) {
+            return Err(invalid());
+        }
+
+        let mut info = Self {
+            path: path.to_path_buf(),
+            pid: 0,
+            file: PathBuf::new(),
+            modified: false,
+            contents: vec![],
+        };
+        for line in lines.by_ref() {
+            match line.split_once(' ') {
+                Some(("pid", pid)) => info.pid = pid.parse().map_err(|_| invalid())?,
+                Some(("file", file)) => info.file = PathBuf::from(file),
+                Some(("modified", modified)) => info.modified = modified == "1",
+                _ if line.is_empty() => break,
+                _ => return Err(invalid()),
+            }
+        }
+        info.contents = lines.map(String::from).collect();
+
+        Ok(info)
+    }
+
+    pub fn owner_alive(&self) -> bool {
+        process_alive(self.pid)
+    }
+
+    pub fn describe(&self) -> Vec<String> {
+        vec![
+            format!("Found a swap file: {}", self.path.display()),
+            format!(
+                "    owned by process {} ({})",
+                self.pid,
+                if self.owner_alive() { "STILL RUNNING" } else { "not running" },
+            ),
+            format!("    modified: {}", if self.modified { "yes" } else { "no" }),
+            format!("While opening file \"{}\"", self.file.display()),
+        ]
+    }
+}
+
+pub struct SwapFile {
+    path: PathBuf,
+    written_tick: Option<usize>,
+}
+
+impl SwapFile {
+    fn candidates(file: &Path) -> Vec<PathBuf> {
+        let file = canonical_path(file);
+        let name = file.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        let state = state_dir().map(|state| state.join(SWAP_DIR));
+        let flattened = file.display().to_string().replace(['/', '\\'], "%");
+
+        let mut candidates: Vec<PathBuf> = SWAP_EXTENSIONS.iter()
+            .map(|ext| dir.join(format!(".{}.{}", name, ext)))
+            .collect();
+        if let Some(state) = state {
+            candidates.extend(SWAP_EXTENSIONS.iter().map(|ext| state.join(format!("{}.{}", flattened, ext))));
+        }
+        candidates
+    }
+
+    pub fn find_existing(file: &Path) -> Option<SwapInfo> {
+        Self::candidates(file)
+            .iter()
+            .filter(|path| path.exists())
+            .filter_map(|path| SwapInfo::read(path).ok())
+            .find(|info| info.pid != std::process::id())
+    }
+
+    pub fn create(file: &Path) -> io::Result<Self> {
+        let mut last_error = io::Error::new(io::ErrorKind::AlreadyExists, "No free swap file name");
+        if let Some(state) = state_dir() {
+            let _ = fs::create_dir_all(state.join(SWAP_DIR));
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        // The swap file holds the buffer's text, so only its owner may read it.
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        for path in Self::candidates(file) {
+            match options.open(&path) {
+                Ok(_) => {
+                    let mut swap = Self { path, written_tick: None };
+                    swap.write(file, false, std::iter::empty(), 0)?;
+                    return Ok(swap);
+                },
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_current(&self, tick: usize) -> bool {
+        self.written_tick == Some(tick)
+    }
+
+    pub fn write<'a>(
+        &mut self,
+        file: &Path,
+        modified: bool,
+        lines: impl Iterator<Item = &'a str>,
+        tick: usize,
+    ) -> io::Result<()> {
+        let mut contents = format!(
+            "{}\npid {}\nfile {}\nmodified {}\n\n",
+            SWAP_HEADER,
+            std::process::id(),
+            canonical_path(file).display(),
+            if modified { 1 } else { 0 },
+        );
+        if modified {
+            lines.for_each(|line| {
+                contents.push_str(line);
+                contents.push('\n');
+            });
+        }
+
+        fs::write(&self.path, contents)?;
+        self.written_tick = Some(tick);
+        Ok(())
+    }
+}
+
+impl Drop for SwapFile {
+    fn drop(&mut self) {
+        // A panicking editor keeps its swap files so the session can be recovered.
+        if !std::thread::panicking() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+    use std::process::Command;
+
+    #[test]
+    fn offers_recovery_from_swap_files() {
+        let dir = TempDir::new("swap");
+        let file = dir.write("notes.txt", "one\ntwo\n");
+        let swap = dir.join(".notes.txt.swp");
+        let mut owner = Command::new("sleep").arg("10").spawn().unwrap();
+        assert!(process_alive(owner.id()));
+        fs::write(&swap, format!("{}\npid {}\nfile {}\nmodified 1\n\none\nTWO\r\n", SWAP_HEADER, owner.id(), file.display())).unwrap();
+
+        // Another editor still has the file open, so only opening it read-only is safe.
+        let mut harness = Harness::new(80, 20, "");
+        harness.keys(&format!(":e {}<CR>", file.display()));
+        assert!(harness.screen().text().contains(&format!("owned by process {} (STILL RUNNING)", owner.id())));
+        assert!(harness.screen().text().contains("[R]ecover, [O]pen read-only"));
+        harness.keys("o");
+        assert_eq!(harness.buffer_text(), "one\ntwo");
+        assert!(harness.screen().row(18).contains("[RO]"));
+        assert!(swap.exists() && dir.join(".notes.txt.swo").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(dir.join(".notes.txt.swo")).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        drop(harness);
+        assert!(!dir.join(".notes.txt.swo").exists());
+
+        owner.kill().unwrap();
+        owner.wait().unwrap();
+        assert!(!process_alive(owner.id()));
+        let mut harness = Harness::new(80, 20, "");
+        harness.keys(&format!(":e {}<CR>", file.display()));
+        assert!(harness.screen().text().contains("(not running)"));
+        harness.keys("r");
+        assert_eq!(harness.buffer_text(), "one\nTWO\r");
+        let info = SwapInfo::read(&swap).unwrap();
+        assert_eq!((info.pid, info.modified), (std::process::id(), false));
+        assert!(harness.keys(":swapname<CR>").screen().row(19).contains(".notes.txt.swp"));
+
+        // Modified buffers are written out after a burst of keys.
+        harness.keys(&"l".repeat(SWAP_UPDATE_COUNT));
+        let info = SwapInfo::read(&swap).unwrap();
+        assert!(info.modified);
+        assert_eq!(info.contents, vec!["one", "TWO\r"]);
+    }
+}
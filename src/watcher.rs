@@ -0,0 +1,103 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::time::{Instant, SystemTime};
+
+pub const FILE_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+pub const DISK_NAME: &str = "[On disk]";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    pub fn of(file: &Path) -> Option<Self> {
+        fs::metadata(file).ok().map(Self::from_metadata)
+    }
+
+    pub fn from_metadata(metadata: fs::Metadata) -> Self {
+        Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+}
+
+pub struct FileWatcher {
+    files: HashMap<PathBuf, Option<FileStamp>>,
+    last_check: Instant,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        Self {
+            files: HashMap::new(),
+            last_check: Instant::now(),
+        }
+    }
+}
+
+impl FileWatcher {
+    pub fn set_watched(&mut self, files: Vec<PathBuf>) {
+        self.files.retain(|file, _| files.contains(file));
+        for file in files {
+            self.files.entry(file.clone()).or_insert_with(|| FileStamp::of(&file));
+        }
+    }
+
+    pub fn poll_changes(&mut self) -> Vec<PathBuf> {
+        if self.last_check.elapsed() < FILE_POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_check = Instant::now();
+
+        let mut changed = vec![];
+        for (file, stamp) in self.files.iter_mut() {
+            let current = FileStamp::of(file);
+            if current != *stamp {
+                *stamp = current;
+                changed.push(file.clone());
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, TempDir};
+
+    #[test]
+    fn reloads_and_compares_changed_files() {
+        let dir = TempDir::new("watch");
+        let file = dir.write("notes.txt", "one\ntwo\n");
+        let mut harness = Harness::new(120, 20, "");
+        harness.keys(&format!(":e {}<CR>", file.display()));
+
+        // Unmodified buffers follow the file.
+        fs::write(&file, "one\ntwo\nthree\n").unwrap();
+        harness.wait_for(|harness| harness.buffer_text() == "one\ntwo\nthree");
+
+        harness.keys("x");
+        fs::write(&file, "ONE\ntwo\nthree\nfour\n").unwrap();
+        harness.wait_for(|harness| harness.screen().text().contains("has changed since editing started"));
+        harness.keys("d");
+        assert_eq!(harness.buffer_text(), "ne\ntwo\nthree");
+        let status = harness.screen().row(18);
+        assert!(status.contains("notes.txt [+]") && status.contains(DISK_NAME));
+        assert!(harness.screen().row(3).contains("4 four"));
+        assert!(harness.screen().row(3).contains(&"-".repeat(10)));
+
+        harness.keys("<C-w>l");
+        assert_eq!(harness.buffer_text(), "ONE\ntwo\nthree\nfour");
+        harness.keys(":q<CR>");
+        assert_eq!(harness.buffer_text(), "ne\ntwo\nthree");
+        assert!(!harness.screen().text().contains(DISK_NAME));
+    }
+}
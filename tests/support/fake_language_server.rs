@@ -0,0 +1,365 @@
+// A tiny language server for the editor's tests, built by build.rs. It only
+// depends on std.
+//
+// It reports a warning for every TODO, answers hover with the word under the
+// cursor and the number of lines it holds, resolves definitions and
+// references by plain word matching, renames by replacing every occurrence
+// and offers a quickfix removing a TODO plus a command that upper-cases a line
+// through workspace/applyEdit. Completion always offers the same two items.
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+enum Json {
+    Null,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(name, _)| name == key).map_or(&Json::Null, |(_, value)| value),
+            _ => &Json::Null,
+        }
+    }
+
+    fn str(&self) -> &str {
+        match self {
+            Json::String(text) => text,
+            _ => "",
+        }
+    }
+
+    fn number(&self) -> usize {
+        match self {
+            Json::Number(number) => *number as usize,
+            _ => 0,
+        }
+    }
+
+    fn items(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn value(&mut self) -> Json {
+        self.skip_whitespace();
+        match self.chars.peek().copied() {
+            Some('{') => {
+                self.chars.next();
+                let mut fields = vec![];
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.next() {
+                        Some('}') | None => break,
+                        Some(',') => continue,
+                        Some('"') => {
+                            let name = self.string();
+                            self.skip_whitespace();
+                            self.chars.next();
+                            fields.push((name, self.value()));
+                        },
+                        Some(_) => {},
+                    }
+                }
+                Json::Object(fields)
+            },
+            Some('[') => {
+                self.chars.next();
+                let mut items = vec![];
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.peek() {
+                        Some(']') | None => {
+                            self.chars.next();
+                            break;
+                        },
+                        Some(',') => {
+                            self.chars.next();
+                        },
+                        Some(_) => items.push(self.value()),
+                    }
+                }
+                Json::Array(items)
+            },
+            Some('"') => {
+                self.chars.next();
+                Json::String(self.string())
+            },
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self.chars.peek().copied().filter(|c| !",]} \n\r\t".contains(*c)) {
+                    word.push(c);
+                    self.chars.next();
+                }
+                // Nothing the server reads is a boolean, so anything else is a number.
+                word.parse().map_or(Json::Null, Json::Number)
+            },
+        }
+    }
+
+    fn string(&mut self) -> String {
+        let mut text = String::new();
+        while let Some(c) = self.chars.next() {
+            match c {
+                '"' => break,
+                '\\' => match self.chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    Some('u') => {
+                        let hex: String = self.chars.by_ref().take(4).collect();
+                        text.push(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32).unwrap_or('?'));
+                    },
+                    Some(c) => text.push(c),
+                    None => break,
+                },
+                c => text.push(c),
+            }
+        }
+        text
+    }
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn range(line: usize, start: usize, end: usize) -> String {
+    format!(
+        "{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}}",
+        line, start, line, end,
+    )
+}
+
+fn send(body: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = stdout.flush();
+}
+
+fn respond(id: &Json, result: &str) {
+    let id = match id {
+        Json::Number(number) => number.to_string(),
+        other => quote(other.str()),
+    };
+    send(&format!("{{\"jsonrpc\":\"2.0\",\"id\":{},\"result\":{}}}", id, result));
+}
+
+fn is_word(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn word_at(lines: &[String], line: usize, column: usize) -> String {
+    let text: Vec<char> = lines.get(line).map_or(vec![], |line| line.chars().collect());
+    let mut start = column.min(text.len());
+    while start > 0 && is_word(text[start - 1]) {
+        start -= 1;
+    }
+    let mut end = column.min(text.len());
+    while end < text.len() && is_word(text[end]) {
+        end += 1;
+    }
+    text[start..end].iter().collect()
+}
+
+// Every whole-word occurrence as (line, start, end) in characters.
+fn occurrences(lines: &[String], word: &str) -> Vec<(usize, usize, usize)> {
+    let mut found = vec![];
+    if word.is_empty() {
+        return found;
+    }
+    for (line_idx, line) in lines.iter().enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let word: Vec<char> = word.chars().collect();
+        for start in 0..chars.len().saturating_sub(word.len() - 1) {
+            let end = start + word.len();
+            let bounded = (start == 0 || !is_word(chars[start - 1])) && (end == chars.len() || !is_word(chars[end]));
+            if chars[start..end] == word[..] && bounded {
+                found.push((line_idx, start, end));
+            }
+        }
+    }
+    found
+}
+
+fn split_lines(text: &str) -> Vec<String> {
+    text.split('\n').map(String::from).collect()
+}
+
+fn apply_change(lines: &mut Vec<String>, change: &Json) {
+    let range = change.get("range");
+    if let Json::Null = range {
+        *lines = split_lines(change.get("text").str());
+        return;
+    }
+    let position = |name: &str| (range.get(name).get("line").number(), range.get(name).get("character").number());
+    let ((start_line, start), (end_line, end)) = (position("start"), position("end"));
+    let char_offset = |line: &str, column: usize| line.char_indices().nth(column).map_or(line.len(), |(idx, _)| idx);
+    let prefix = lines[start_line][..char_offset(&lines[start_line], start)].to_string();
+    let suffix = lines[end_line][char_offset(&lines[end_line], end)..].to_string();
+    let replaced = split_lines(&format!("{}{}{}", prefix, change.get("text").str(), suffix));
+    lines.splice(start_line..=end_line, replaced);
+}
+
+fn publish_diagnostics(uri: &str, lines: &[String]) {
+    let diagnostics: Vec<String> = occurrences(lines, "TODO")
+        .into_iter()
+        .map(|(line, start, end)| format!("{{\"range\":{},\"severity\":2,\"message\":\"TODO found\"}}", range(line, start, end)))
+        .collect();
+    send(&format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"textDocument/publishDiagnostics\",\"params\":{{\"uri\":{},\"diagnostics\":[{}]}}}}",
+        quote(uri),
+        diagnostics.join(","),
+    ));
+}
+
+fn edit(uri: &str, edits: &[(usize, usize, usize, String)]) -> String {
+    let edits: Vec<String> = edits.iter()
+        .map(|(line, start, end, text)| format!("{{\"range\":{},\"newText\":{}}}", range(*line, *start, *end), quote(text)))
+        .collect();
+    format!("{{\"changes\":{{{}:[{}]}}}}", quote(uri), edits.join(","))
+}
+
+fn main() {
+    let mut documents: HashMap<String, Vec<String>> = HashMap::new();
+    let mut next_request = 0;
+    let mut stdin = io::stdin().lock();
+
+    loop {
+        let mut length = 0;
+        loop {
+            let mut header = String::new();
+            if stdin.read_line(&mut header).unwrap_or(0) == 0 {
+                return;
+            }
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = header.to_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+        let mut body = vec![0; length];
+        if stdin.read_exact(&mut body).is_err() {
+            return;
+        }
+        let body = String::from_utf8_lossy(&body).into_owned();
+        let message = Parser { chars: body.chars().peekable() }.value();
+        let params = message.get("params");
+        let id = message.get("id");
+        let uri = params.get("textDocument").get("uri").str().to_string();
+        let (line, column) = (params.get("position").get("line").number(), params.get("position").get("character").number());
+        let lines = documents.get(&uri).cloned().unwrap_or_default();
+
+        match message.get("method").str() {
+            "initialize" => respond(id, concat!(
+                "{\"capabilities\":{\"textDocumentSync\":{\"openClose\":true,\"change\":2},",
+                "\"hoverProvider\":true,\"definitionProvider\":true,\"referencesProvider\":true,",
+                "\"renameProvider\":true,\"codeActionProvider\":true,\"completionProvider\":{},",
+                "\"executeCommandProvider\":{\"commands\":[\"upper\"]}}}",
+            )),
+            "textDocument/didOpen" => {
+                let lines = split_lines(params.get("textDocument").get("text").str());
+                publish_diagnostics(&uri, &lines);
+                documents.insert(uri, lines);
+            },
+            "textDocument/didChange" => {
+                if let Some(lines) = documents.get_mut(&uri) {
+                    params.get("contentChanges").items().iter().for_each(|change| apply_change(lines, change));
+                    publish_diagnostics(&uri, lines);
+                }
+            },
+            "textDocument/didClose" => {
+                documents.remove(&uri);
+            },
+            "textDocument/hover" => {
+                let text = format!("{} ({} lines)", word_at(&lines, line, column), lines.len());
+                respond(id, &format!("{{\"contents\":{{\"kind\":\"plaintext\",\"value\":{}}}}}", quote(&text)));
+            },
+            "textDocument/definition" => match occurrences(&lines, &word_at(&lines, line, column)).first() {
+                Some((line, start, end)) => respond(id, &format!("{{\"uri\":{},\"range\":{}}}", quote(&uri), range(*line, *start, *end))),
+                None => respond(id, "null"),
+            },
+            "textDocument/references" => {
+                let locations: Vec<String> = occurrences(&lines, &word_at(&lines, line, column))
+                    .into_iter()
+                    .map(|(line, start, end)| format!("{{\"uri\":{},\"range\":{}}}", quote(&uri), range(line, start, end)))
+                    .collect();
+                respond(id, &format!("[{}]", locations.join(",")));
+            },
+            "textDocument/completion" => respond(id, concat!(
+                "[{\"label\":\"length\",\"detail\":\"fn length(&self) -> usize\",\"documentation\":\"Number of lines\"},",
+                "{\"label\":\"lines\",\"insertText\":\"lines(${1:n})\",\"insertTextFormat\":2}]",
+            )),
+            "textDocument/rename" => {
+                let new_name = params.get("newName").str().to_string();
+                let edits: Vec<(usize, usize, usize, String)> = occurrences(&lines, &word_at(&lines, line, column))
+                    .into_iter()
+                    .map(|(line, start, end)| (line, start, end, new_name.clone()))
+                    .collect();
+                respond(id, &edit(&uri, &edits));
+            },
+            "textDocument/codeAction" => {
+                let line = params.get("range").get("start").get("line").number();
+                let mut actions = vec![];
+                for diagnostic in params.get("context").get("diagnostics").items() {
+                    let (start, end) = (diagnostic.get("range").get("start"), diagnostic.get("range").get("end"));
+                    let removal = (start.get("line").number(), start.get("character").number(), end.get("character").number(), String::new());
+                    actions.push(format!(
+                        "{{\"title\":\"Remove TODO\",\"kind\":\"quickfix\",\"edit\":{}}}",
+                        edit(&uri, &[removal]),
+                    ));
+                }
+                actions.push(format!(
+                    "{{\"title\":\"Uppercase line\",\"command\":\"upper\",\"arguments\":[{},{}]}}",
+                    quote(&uri),
+                    line,
+                ));
+                respond(id, &format!("[{}]", actions.join(",")));
+            },
+            "workspace/executeCommand" => {
+                let arguments = params.get("arguments").items();
+                let (uri, line) = (arguments[0].str().to_string(), arguments[1].number());
+                let text = documents.get(&uri).and_then(|lines| lines.get(line)).cloned().unwrap_or_default();
+                next_request += 1;
+                send(&format!(
+                    "{{\"jsonrpc\":\"2.0\",\"id\":\"fake-{}\",\"method\":\"workspace/applyEdit\",\"params\":{{\"edit\":{}}}}}",
+                    next_request,
+                    edit(&uri, &[(line, 0, text.chars().count(), text.to_uppercase())]),
+                ));
+                respond(id, "null");
+            },
+            "shutdown" => respond(id, "null"),
+            "exit" => return,
+            _ => {},
+        }
+    }
+}